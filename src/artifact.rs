@@ -0,0 +1,163 @@
+//! Locating the artifact of a previous `cargo build` without building again.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{anyhow, Context};
+use probe_rs_cli_util::ArtifactError;
+use serde::Deserialize;
+
+use crate::{config::Config, diagnostics::RoverError};
+
+/// The parts of the output of `cargo metadata` which are needed to locate an artifact.
+#[derive(Debug, Deserialize)]
+struct Metadata {
+    packages: Vec<Package>,
+    workspace_members: Vec<String>,
+    target_directory: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    id: String,
+    name: String,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Target {
+    name: String,
+    kind: Vec<String>,
+}
+
+/// Finds the artifact `cargo build` would produce with the cargo arguments of the config, without building it.
+///
+/// Without `--target`, the artifacts of the host and of all target triples are considered and the newest one is used.
+pub fn find_artifact(work_dir: &Path, config: &Config) -> Result<PathBuf, RoverError> {
+    let metadata = read_metadata(work_dir, config.manifest_path())
+        .map_err(RoverError::FailedToReadCargoMetadata)?;
+
+    let (name, artifact) = match (config.example(), config.bin()) {
+        (Some(example), _) => (example.clone(), Path::new("examples").join(example)),
+        (None, Some(bin)) => (bin.clone(), PathBuf::from(bin)),
+        (None, None) => {
+            let bin = single_binary(&metadata, config.package())?;
+            (bin.clone(), PathBuf::from(bin))
+        }
+    };
+    let profile = if config.release() { "release" } else { "debug" };
+
+    let candidates = match config.target() {
+        Some(target) => vec![metadata.target_directory.join(target)],
+        None => {
+            let mut candidates = vec![metadata.target_directory.clone()];
+            if let Ok(entries) = std::fs::read_dir(&metadata.target_directory) {
+                candidates.extend(
+                    entries
+                        .filter_map(|entry| entry.ok())
+                        .map(|entry| entry.path())
+                        .filter(|path| path.is_dir()),
+                );
+            }
+            candidates
+        }
+    }
+    .into_iter()
+    .map(|triple_dir| triple_dir.join(profile).join(&artifact))
+    .collect::<Vec<_>>();
+
+    let newest = candidates
+        .iter()
+        .filter_map(|path| {
+            let modified = path.metadata().ok()?.modified().ok()?;
+            Some((modified, path))
+        })
+        .max()
+        .map(|(_, path)| path.clone());
+    newest.ok_or(RoverError::PrebuiltArtifactNotFound {
+        name,
+        searched: candidates,
+    })
+}
+
+/// Runs `cargo metadata` for the project in the working directory.
+fn read_metadata(work_dir: &Path, manifest_path: &Option<PathBuf>) -> anyhow::Result<Metadata> {
+    let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+    let mut command = Command::new(cargo);
+    command
+        .current_dir(work_dir)
+        .args(&["metadata", "--no-deps", "--format-version", "1"]);
+    if let Some(manifest_path) = manifest_path {
+        command.arg("--manifest-path").arg(manifest_path);
+    }
+
+    let output = command
+        .output()
+        .context("Failed to run `cargo metadata`.")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "`cargo metadata` failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    serde_json::from_slice(&output.stdout)
+        .context("Failed to parse the output of `cargo metadata`.")
+}
+
+/// Finds the only binary of the selected package, or of all the workspace members if no package is selected.
+fn single_binary(metadata: &Metadata, package: &Option<String>) -> Result<String, RoverError> {
+    let mut binaries = metadata
+        .packages
+        .iter()
+        .filter(|p| match package {
+            Some(package) => &p.name == package,
+            None => metadata.workspace_members.contains(&p.id),
+        })
+        .flat_map(|p| p.targets.iter())
+        .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+        .map(|target| target.name.clone());
+
+    match (binaries.next(), binaries.next()) {
+        (Some(binary), None) => Ok(binary),
+        (None, _) => Err(RoverError::FailedToBuildCargoProject(
+            ArtifactError::NoArtifacts,
+        )),
+        (Some(_), Some(_)) => Err(RoverError::FailedToBuildCargoProject(
+            ArtifactError::MultipleArtifacts,
+        )),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{single_binary, Metadata};
+
+    #[test]
+    fn the_only_binary_of_the_workspace_is_selected() {
+        let metadata: Metadata = serde_json::from_str(
+            r#"{
+                "packages": [
+                    { "id": "app", "name": "app", "targets": [
+                        { "name": "app", "kind": ["bin"] },
+                        { "name": "app", "kind": ["lib"] }
+                    ] },
+                    { "id": "tool", "name": "tool", "targets": [
+                        { "name": "tool", "kind": ["bin"] }
+                    ] }
+                ],
+                "workspace_members": ["app"],
+                "target_directory": "/project/target"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(single_binary(&metadata, &None).unwrap(), "app");
+        assert_eq!(
+            single_binary(&metadata, &Some("tool".into())).unwrap(),
+            "tool"
+        );
+        assert!(single_binary(&metadata, &Some("other".into())).is_err());
+    }
+}
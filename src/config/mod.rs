@@ -1,8 +1,13 @@
-use std::{collections::HashMap, convert::TryInto, path::PathBuf, str::FromStr};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use anyhow::{bail, Context};
-use probe_rs::{flashing::Format, DebugProbeSelector, WireProtocol};
-use serde::{Deserialize, Serialize};
+use probe_rs::{flashing::BinOptions, DebugProbeSelector, WireProtocol};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::{Map, Value};
 use structopt::StructOpt;
 
@@ -18,6 +23,8 @@ pub struct Config {
     #[structopt(flatten)]
     flashing: Flashing,
     #[structopt(flatten)]
+    reading: Reading,
+    #[structopt(flatten)]
     reset: Reset,
     #[structopt(flatten)]
     probe: Probe,
@@ -26,21 +33,160 @@ pub struct Config {
     #[structopt(flatten)]
     logging: Logging,
 
-    #[structopt(short = "V", long = "version")]
-    version: bool,
-    #[structopt(name = "list-chips", long = "list-chips")]
-    list_chips: bool,
+    #[structopt(
+        long = "config",
+        help = "The name of the config in the config files to use, e.g. `release` or `ci`."
+    )]
+    config: Option<String>,
+    #[structopt(short = "V", long = "version", parse(from_flag = flag))]
+    version: Flag,
+    #[structopt(
+        name = "version-format",
+        long = "version-format",
+        help = "How the version is printed with `--version`: `text` or `json`."
+    )]
+    version_format: Option<ListFormat>,
+    #[structopt(
+        name = "dump-config",
+        long = "dump-config",
+        parse(from_flag = flag),
+        help = "Prints the fully merged config as YAML and exits."
+    )]
+    dump_config: Flag,
+    #[structopt(name = "list-chips", long = "list-chips", parse(from_flag = flag))]
+    list_chips: Flag,
+    #[structopt(
+        name = "list-chips-format",
+        long = "list-chips-format",
+        help = "How the chips are listed with `--list-chips`: `text` or `json`."
+    )]
+    list_chips_format: Option<ListFormat>,
+    #[structopt(
+        name = "list-chips-filter",
+        long = "list-chips-filter",
+        help = "Only lists the families and variants with `--list-chips` whose name contains this text, ignoring the case."
+    )]
+    list_chips_filter: Option<String>,
+    #[structopt(
+        name = "list-channels",
+        long = "list-channels",
+        parse(from_flag = flag),
+        help = "Lists the configured logging channels with their modes and links without connecting to the target."
+    )]
+    list_channels: Flag,
     #[structopt(
         name = "list-probes",
         long = "list-probes",
+        parse(from_flag = flag),
         help = "Lists all the connected probes that can be seen.\n\
         If udev rules or permissions are wrong, some probes might not be listed."
     )]
-    list_probes: bool,
-    #[structopt(name = "disable-progressbars", long = "disable-progressbars")]
-    disable_progressbars: bool,
-    #[structopt(long = "dry-run")]
-    dry_run: bool,
+    list_probes: Flag,
+    #[structopt(name = "disable-progressbars", long = "disable-progressbars", parse(from_flag = flag))]
+    disable_progressbars: Flag,
+    #[structopt(
+        long = "progress-format",
+        help = "How the flash progress is reported: `bars` draws progress bars and `json` prints one JSON object per progress event to stdout."
+    )]
+    progress_format: Option<ProgressFormat>,
+    #[structopt(
+        long = "no-color",
+        parse(from_flag = flag),
+        help = "Disables colors in the output. Colors are also disabled if `NO_COLOR` is set or stdout is not a terminal."
+    )]
+    no_color: Flag,
+    #[structopt(
+        long = "error-format",
+        help = "How errors are reported: `pretty` prints them for humans and `json` prints one JSON object with the error, its causes and the hints to stderr."
+    )]
+    error_format: Option<ErrorFormat>,
+    #[structopt(long = "dry-run", parse(from_flag = flag))]
+    dry_run: Flag,
+    #[structopt(
+        long = "fast",
+        parse(from_flag = flag),
+        help = "Skips optional steps to save time on trusted setups which flash many units.\n\
+        This skips the check of the wire protocol against the architecture of the chip, the warning about a lower\n\
+        probe speed than requested, the layout summary of the flash regions and the drawing of progress bars.\n\
+        Steps which are required to flash correctly are never skipped."
+    )]
+    fast: Flag,
+    #[structopt(
+        long = "watch",
+        parse(from_flag = flag),
+        help = "Rebuilds and reflashes the firmware whenever a source of the cargo project changes.\n\
+        The probe stays open, so GDB and logging keep running across reflashes."
+    )]
+    watch: Flag,
+    #[structopt(
+        long = "reset-only",
+        parse(from_flag = flag),
+        help = "Only attaches to the target and resets it according to the reset config.\n\
+        Nothing is built or flashed and no services are started."
+    )]
+    reset_only: Flag,
+    #[structopt(
+        long = "show-eta",
+        parse(from_flag = flag),
+        help = "Prints an estimate of how long flashing takes before it starts.\n\
+        The estimate is based on the probe speed and logged along with the actual duration afterwards."
+    )]
+    show_eta: Flag,
+    #[structopt(
+        long = "no-build",
+        parse(from_flag = flag),
+        help = "Flashes the artifact of a previous `cargo build` instead of building it.\n\
+        The artifact is selected with the same `--bin`, `--example`, `--package`, `--release` and `--target` arguments."
+    )]
+    no_build: Flag,
+    #[structopt(
+        long = "stats",
+        parse(from_flag = flag),
+        help = "Prints the amount of erased and programmed data and the throughput of both after flashing."
+    )]
+    stats: Flag,
+    #[structopt(
+        long = "nrf-recover",
+        parse(from_flag = flag),
+        help = "Erases a locked nRF52 chip through its CTRL-AP before attaching, which removes the access port protection.\n\
+        All flash contents including the UICR are lost. Requires the chip to be given with `--chip`."
+    )]
+    nrf_recover: Flag,
+    #[structopt(
+        long = "verify-only",
+        parse(from_flag = flag),
+        help = "Only checks whether the target holds the firmware, without erasing or programming anything.\n\
+        Exits with code 6 if the flash contents differ. A CRC32 is compared instead of the full contents with `--flashing.verify crc`."
+    )]
+    verify_only: Flag,
+    #[structopt(
+        long = "doctor",
+        parse(from_flag = flag),
+        help = "Checks whether the host is set up to use debug probes and prints a checklist of the results.\n\
+        Exits with a nonzero code only if a check fails, warnings are fine."
+    )]
+    doctor: Flag,
+    #[structopt(
+        long = "pre-write",
+        help = "Writes a 32-bit value to a word-aligned address after attaching and before flashing, given as `ADDR=VALUE`.\n\
+        This can be given multiple times, e.g. to program the option bytes of an STM32. The writes are done in the given order."
+    )]
+    pre_write: Option<Vec<MemoryWrite>>,
+    #[structopt(
+        long = "ready-file",
+        parse(from_os_str),
+        help = "Writes a file once GDB accepts connections and logging is attached, so orchestration scripts know when to connect.\n\
+        The file contains a JSON object with the process id and the GDB socket."
+    )]
+    ready_file: Option<PathBuf>,
+    #[structopt(
+        long = "export-image",
+        parse(from_os_str),
+        help = "Writes the image which is flashed to the given path, as Intel HEX for `.hex` and as raw binary otherwise.\n\
+        Raw binaries above 64 MiB are refused, as the gaps between the segments are filled. The image is exported before\n\
+        the probe is opened if the chip is given. It is still flashed afterwards unless `--flashing.enabled false` is given."
+    )]
+    export_image: Option<PathBuf>,
     // `cargo build` arguments
     #[structopt(name = "binary", long = "bin")]
     bin: Option<String>,
@@ -48,18 +194,18 @@ pub struct Config {
     example: Option<String>,
     #[structopt(name = "package", short = "p", long = "package")]
     package: Option<String>,
-    #[structopt(name = "release", long = "release")]
-    release: bool,
+    #[structopt(name = "release", long = "release", parse(from_flag = flag))]
+    release: Flag,
     #[structopt(name = "target", long = "target")]
     target: Option<String>,
     #[structopt(name = "PATH", long = "manifest-path", parse(from_os_str))]
     manifest_path: Option<PathBuf>,
+    #[structopt(long, parse(from_flag = flag))]
+    no_default_features: Flag,
+    #[structopt(long, parse(from_flag = flag))]
+    all_features: Flag,
     #[structopt(long)]
-    no_default_features: bool,
-    #[structopt(long)]
-    all_features: bool,
-    #[structopt(long)]
-    features: Vec<String>,
+    features: Option<Vec<String>>,
 }
 
 impl Config {
@@ -73,6 +219,11 @@ impl Config {
         &self.flashing
     }
 
+    /// Get a reference to the config's reading.
+    pub fn reading(&self) -> &Reading {
+        &self.reading
+    }
+
     /// Get a reference to the config's reset.
     pub fn reset(&self) -> &Reset {
         &self.reset
@@ -95,27 +246,206 @@ impl Config {
 
     /// Get a reference to the config's version.
     pub fn version(&self) -> bool {
-        self.version
+        self.version.unwrap_or(false)
     }
 
     /// Get a reference to the config's list chips.
     pub fn list_chips(&self) -> bool {
-        self.list_chips
+        self.list_chips.unwrap_or(false)
+    }
+
+    /// Get the format of the version.
+    pub fn version_format(&self) -> ListFormat {
+        self.version_format.unwrap_or(ListFormat::Text)
+    }
+
+    /// Get the format of the chip listing.
+    pub fn list_chips_format(&self) -> ListFormat {
+        self.list_chips_format.unwrap_or(ListFormat::Text)
+    }
+
+    /// Get a reference to the config's chip listing filter.
+    pub fn list_chips_filter(&self) -> &Option<String> {
+        &self.list_chips_filter
+    }
+
+    /// Get a reference to the config's dump config.
+    pub fn dump_config(&self) -> bool {
+        self.dump_config.unwrap_or(false)
     }
 
     /// Get a reference to the config's list probes.
     pub fn list_probes(&self) -> bool {
-        self.list_probes
+        self.list_probes.unwrap_or(false)
+    }
+
+    /// Whether the logging channels are listed instead of running.
+    pub fn list_channels(&self) -> bool {
+        self.list_channels.unwrap_or(false)
     }
 
     /// Get a reference to the config's disable progressbars.
     pub fn disable_progressbars(&self) -> bool {
-        self.disable_progressbars
+        self.disable_progressbars.unwrap_or(false)
+    }
+
+    /// Get the config's progress format.
+    pub fn progress_format(&self) -> ProgressFormat {
+        self.progress_format.unwrap_or(ProgressFormat::Bars)
+    }
+
+    /// Get whether colors are disabled.
+    pub fn no_color(&self) -> bool {
+        self.no_color.unwrap_or(false)
+    }
+
+    /// Get the config's error format.
+    pub fn error_format(&self) -> ErrorFormat {
+        self.error_format.unwrap_or(ErrorFormat::Pretty)
     }
 
     /// Get a reference to the config's dry run.
     pub fn dry_run(&self) -> bool {
-        self.dry_run
+        self.dry_run.unwrap_or(false)
+    }
+
+    /// Get a reference to the config's fast mode.
+    pub fn fast(&self) -> bool {
+        self.fast.unwrap_or(false)
+    }
+
+    /// Get the config's watch mode.
+    pub fn watch(&self) -> bool {
+        self.watch.unwrap_or(false)
+    }
+
+    /// Get the config's reset only mode.
+    pub fn reset_only(&self) -> bool {
+        self.reset_only.unwrap_or(false)
+    }
+
+    /// Get whether the estimated flash time is shown.
+    pub fn show_eta(&self) -> bool {
+        self.show_eta.unwrap_or(false)
+    }
+
+    /// Get whether the artifact of a previous build is flashed without building.
+    pub fn no_build(&self) -> bool {
+        self.no_build.unwrap_or(false)
+    }
+
+    /// Get whether flash statistics are printed.
+    pub fn stats(&self) -> bool {
+        self.stats.unwrap_or(false)
+    }
+
+    /// Get whether a locked nRF52 chip is recovered before attaching.
+    pub fn nrf_recover(&self) -> bool {
+        self.nrf_recover.unwrap_or(false)
+    }
+
+    /// Get whether the flash contents are only verified.
+    pub fn verify_only(&self) -> bool {
+        self.verify_only.unwrap_or(false)
+    }
+
+    /// Get whether the host environment is checked.
+    pub fn doctor(&self) -> bool {
+        self.doctor.unwrap_or(false)
+    }
+
+    /// Get a reference to the words written before flashing.
+    pub fn pre_write(&self) -> &[MemoryWrite] {
+        self.pre_write.as_deref().unwrap_or_default()
+    }
+
+    /// Get a reference to the config's ready file.
+    pub fn ready_file(&self) -> &Option<PathBuf> {
+        &self.ready_file
+    }
+
+    /// Get a reference to the path the flashed image is exported to.
+    pub fn export_image(&self) -> &Option<PathBuf> {
+        &self.export_image
+    }
+
+    /// Get a reference to the config's cargo binary.
+    pub fn bin(&self) -> &Option<String> {
+        &self.bin
+    }
+
+    /// Get a reference to the config's cargo example.
+    pub fn example(&self) -> &Option<String> {
+        &self.example
+    }
+
+    /// Get a reference to the config's cargo package.
+    pub fn package(&self) -> &Option<String> {
+        &self.package
+    }
+
+    /// Get whether the cargo release profile is used.
+    pub fn release(&self) -> bool {
+        self.release.unwrap_or(false)
+    }
+
+    /// Get a reference to the config's cargo target triple.
+    pub fn target(&self) -> &Option<String> {
+        &self.target
+    }
+
+    /// Get a reference to the config's cargo manifest path.
+    pub fn manifest_path(&self) -> &Option<PathBuf> {
+        &self.manifest_path
+    }
+
+    /// Get a reference to the config's cargo features.
+    pub fn features(&self) -> &[String] {
+        self.features.as_deref().unwrap_or_default()
+    }
+
+    /// The arguments `cargo build` is invoked with to build the artifact of the config.
+    /// They are built from the parsed values, so the options of the config files are honored as well.
+    pub fn cargo_build_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        let mut push = |option: &str, value: Option<String>| {
+            args.push(option.to_string());
+            args.extend(value);
+        };
+
+        if let Some(bin) = &self.bin {
+            push("--bin", Some(bin.clone()));
+        }
+        if let Some(example) = &self.example {
+            push("--example", Some(example.clone()));
+        }
+        if let Some(package) = &self.package {
+            push("--package", Some(package.clone()));
+        }
+        if self.release() {
+            push("--release", None);
+        }
+        if let Some(target) = &self.target {
+            push("--target", Some(target.clone()));
+        }
+        if let Some(manifest_path) = &self.manifest_path {
+            push(
+                "--manifest-path",
+                Some(manifest_path.to_string_lossy().into_owned()),
+            );
+        }
+        if self.no_default_features.unwrap_or(false) {
+            push("--no-default-features", None);
+        }
+        if self.all_features.unwrap_or(false) {
+            push("--all-features", None);
+        }
+        // These include the features given on the commandline and the ones implied by the selected profile.
+        if !self.features().is_empty() {
+            push("--features", Some(self.features().join(",")));
+        }
+
+        args
     }
 }
 
@@ -128,12 +458,28 @@ pub struct Probe {
     usb_vid: Option<String>,
     #[structopt(long = "probe.usb-pid")]
     usb_pid: Option<String>,
+    /// Select the probe with this serial number. Without a VID and PID, all connected probes are searched.
     #[structopt(long = "probe.serial")]
     serial: Option<String>,
     #[structopt(long = "probe.protocol")]
     protocol: Option<WireProtocol>,
     #[structopt(long = "probe.speed")]
     speed: Option<u32>,
+    /// Select the probe with this index in the list of connected probes, as shown by `--list-probes`.
+    #[structopt(long = "probe.index")]
+    index: Option<usize>,
+    /// How often opening the probe is retried before giving up.
+    #[structopt(long = "probe.open-retries")]
+    open_retries: Option<u32>,
+    /// How many milliseconds to wait between two attempts to open the probe.
+    #[structopt(long = "probe.open-retry-delay-ms")]
+    open_retry_delay_ms: Option<u64>,
+    /// Switch the power the probe supplies to the target `on`, `off` or `cycle` it before attaching.
+    #[structopt(long = "probe.target-power")]
+    target_power: Option<TargetPower>,
+    /// How many milliseconds the target power has to settle after switching it.
+    #[structopt(long = "probe.target-power-settle-ms")]
+    target_power_settle_ms: Option<u64>,
 }
 
 impl Probe {
@@ -161,12 +507,55 @@ impl Probe {
     pub fn selector(&self) -> &Option<DebugProbeSelector> {
         &self.selector
     }
+
+    pub fn index(&self) -> Option<usize> {
+        self.index
+    }
+
+    pub fn open_retries(&self) -> u32 {
+        self.open_retries.unwrap_or(0)
+    }
+
+    pub fn open_retry_delay_ms(&self) -> u64 {
+        self.open_retry_delay_ms.unwrap_or(500)
+    }
+
+    pub fn target_power(&self) -> Option<TargetPower> {
+        self.target_power
+    }
+
+    pub fn target_power_settle_ms(&self) -> u64 {
+        self.target_power_settle_ms.unwrap_or(100)
+    }
+}
+
+/// How the power the probe supplies to the target is switched.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetPower {
+    On,
+    Off,
+    /// Switches the power off and on again.
+    Cycle,
+}
+
+impl FromStr for TargetPower {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "on" => Ok(TargetPower::On),
+            "off" => Ok(TargetPower::Off),
+            "cycle" => Ok(TargetPower::Cycle),
+            _ => Err(format!("Target power '{}' is unknown.", s)),
+        }
+    }
 }
 
 /// The flashing config struct holding all the possible flashing options.
 #[derive(Debug, Deserialize, Serialize, StructOpt)]
 pub struct Flashing {
-    #[structopt(long = "flashing.enabled")]
+    #[structopt(name = "flashing.enabled", long = "flashing.enabled")]
     enabled: Option<bool>,
     #[structopt(long = "flashing.restore-unwritten-bytes")]
     restore_unwritten_bytes: Option<bool>,
@@ -174,6 +563,30 @@ pub struct Flashing {
     flash_layout_output_path: Option<String>,
     #[structopt(long = "flashing.do-chip-erase")]
     do_chip_erase: Option<bool>,
+    /// Named flash regions, each flashed from its own binary file, e.g. for a bootloader and an application.
+    #[structopt(long = "flashing.regions")]
+    regions: Option<Vec<FlashRegion>>,
+    /// Keep the watchdog of known chip families from resetting the target during flashing.
+    #[structopt(long = "flashing.disable-watchdog")]
+    disable_watchdog: Option<bool>,
+    /// Restore the watchdog settings after flashing when the watchdog was disabled.
+    #[structopt(long = "flashing.restore-watchdog")]
+    restore_watchdog: Option<bool>,
+    /// How the flash contents are checked after programming: `none`, `crc` or `full`.
+    #[structopt(long = "flashing.verify")]
+    verify: Option<VerifyMode>,
+    /// How many times the whole flash cycle is repeated if it fails.
+    #[structopt(long = "flashing.cycle-retries")]
+    cycle_retries: Option<u32>,
+    /// Only erase and program the sectors whose contents differ from the flash of the target.
+    #[structopt(long = "flashing.incremental")]
+    incremental: Option<bool>,
+    /// Only flash the parts of the loadable ELF segments within this address range, e.g. to reflash the application but not the bootloader.
+    #[structopt(name = "flashing.flash-range", long = "flashing.flash-range")]
+    flash_range: Option<MemoryRange>,
+    /// Flash several targets at once, each given as `<probe>,<chip>,<file>`. The probe is selected with `VID:PID[:serial]`.
+    #[structopt(long = "flashing.targets")]
+    targets: Option<Vec<FlashTarget>>,
 }
 
 impl Flashing {
@@ -199,168 +612,983 @@ impl Flashing {
     pub fn do_chip_erase(&self) -> bool {
         self.do_chip_erase.unwrap_or(false)
     }
+
+    pub fn regions(&self) -> &[FlashRegion] {
+        self.regions.as_deref().unwrap_or_default()
+    }
+
+    pub fn disable_watchdog(&self) -> bool {
+        self.disable_watchdog.unwrap_or(false)
+    }
+
+    pub fn restore_watchdog(&self) -> bool {
+        self.restore_watchdog.unwrap_or(true)
+    }
+
+    pub fn verify(&self) -> VerifyMode {
+        self.verify.unwrap_or(VerifyMode::None)
+    }
+
+    pub fn cycle_retries(&self) -> u32 {
+        self.cycle_retries.unwrap_or(0)
+    }
+
+    pub fn incremental(&self) -> bool {
+        self.incremental.unwrap_or(false)
+    }
+
+    pub fn flash_range(&self) -> Option<MemoryRange> {
+        self.flash_range
+    }
+
+    pub fn targets(&self) -> &[FlashTarget] {
+        self.targets.as_deref().unwrap_or_default()
+    }
 }
 
-/// The reset config struct holding all the possible reset options.
-#[derive(Debug, Deserialize, Serialize, StructOpt)]
-pub struct Reset {
-    #[structopt(long = "reset.enabled")]
-    enabled: Option<bool>,
-    #[structopt(long = "reset.halt-afterwards")]
-    #[structopt(long)]
-    halt_afterwards: Option<bool>,
+/// A target which is flashed with its own probe, concurrently with the other targets.
+/// It is given in the form `<probe>,<chip>,<file>`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(try_from = "String", into = "String")]
+pub struct FlashTarget {
+    pub probe: DebugProbeSelector,
+    pub chip: String,
+    pub file: String,
 }
 
-impl Reset {
-    pub fn enabled(&self) -> bool {
-        match self.enabled {
-            Some(true) | None => true,
-            _ => self.halt_afterwards.is_some(),
+impl FromStr for FlashTarget {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // The file comes last as it is the only part which might contain commas.
+        let mut parts = s.splitn(3, ',');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(probe), Some(chip), Some(file)) => Ok(FlashTarget {
+                probe: probe
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("'{}' is not a valid probe selector: {}", probe, e))?,
+                chip: chip.trim().to_string(),
+                file: file.trim().to_string(),
+            }),
+            _ => Err(format!(
+                "The target '{}' is not in the form `<probe>,<chip>,<file>`.",
+                s
+            )),
         }
     }
+}
 
-    pub fn halt_afterwards(&self) -> bool {
-        self.halt_afterwards.unwrap_or(false)
+impl std::convert::TryFrom<String> for FlashTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
 }
 
-/// The general config struct holding all the possible general options.
-#[derive(Debug, Deserialize, Serialize, StructOpt)]
-pub struct General {
-    #[structopt(long = "general.chip")]
-    chip: Option<String>,
-    #[structopt(long = "general.chip-descriptions")]
-    chip_descriptions: Vec<String>,
-    #[structopt(long = "general.log-level", default_value = "WARN")]
-    log_level: log::Level,
-    #[structopt(long = "general.derives")]
-    derives: Option<String>,
-    /// Use this flag to assert the nreset & ntrst pins during attaching the probe to the chip.
-    #[structopt(long = "general.connect-under-reset")]
-    connect_under_reset: bool,
-    #[structopt(
-        name = "binary file",
-        long = "file",
-        help = "The path to the binary file to be flashed."
-    )]
-    file: Option<String>,
-    #[structopt(
-        name = "format",
-        long = "format",
-        help = "The format of the binary file to be flashed. This is only read if the --file option is used.",
-        default_value = "ELF"
-    )]
-    format: Format,
-    #[structopt(
-        name = "base-address",
-        long = "format.base-address",
-        help = "The address where to put the binary data in flash. This is only considered for binary files."
-    )]
-    format_base_address: Option<u32>,
-    #[structopt(
-        name = "skip",
-        long = "format.skip",
-        help = "The number of bytes to skip and not to be flashed at the start of the binary. This is only considered for binary files."
-    )]
-    format_skip: Option<u32>,
-    #[structopt(
-        name = "directory",
-        long = "work-dir",
-        help = "The work directory from which cargo-flash should operate from."
-    )]
-    work_dir: Option<String>,
+impl From<FlashTarget> for String {
+    fn from(target: FlashTarget) -> Self {
+        let mut probe = format!(
+            "{:04x}:{:04x}",
+            target.probe.vendor_id, target.probe.product_id
+        );
+        if let Some(serial) = &target.probe.serial_number {
+            probe.push(':');
+            probe.push_str(serial);
+        }
+        format!("{},{},{}", probe, target.chip, target.file)
+    }
 }
 
-impl General {
-    pub fn chip(&self) -> &Option<String> {
-        &self.chip
+/// A 32-bit value which is written to a word-aligned address of the target before flashing.
+/// It is given in the form `<address>=<value>`.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct MemoryWrite {
+    pub address: u32,
+    pub value: u32,
+}
+
+impl FromStr for MemoryWrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        let (address, value) = match (parts.next(), parts.next()) {
+            (Some(address), Some(value)) => (address.trim(), value.trim()),
+            _ => {
+                return Err(format!(
+                    "The write '{}' is not in the form `<address>=<value>`.",
+                    s
+                ))
+            }
+        };
+
+        let address =
+            parse_u32(address).map_err(|_| format!("'{}' is not a valid address.", address))?;
+        if address % 4 != 0 {
+            return Err(format!(
+                "The address {:#010x} is not word-aligned. Only whole words can be written.",
+                address
+            ));
+        }
+        let value = parse_u32(value).map_err(|_| format!("'{}' is not a valid value.", value))?;
+
+        Ok(MemoryWrite { address, value })
     }
+}
+
+impl std::convert::TryFrom<String> for MemoryWrite {
+    type Error = String;
 
-    pub fn chip_descriptions(&self) -> &Vec<String> {
-        &self.chip_descriptions
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
     }
+}
 
-    pub fn log_level(&self) -> log::Level {
-        self.log_level
+impl From<MemoryWrite> for String {
+    fn from(write: MemoryWrite) -> Self {
+        format!("{:#010x}={:#010x}", write.address, write.value)
     }
+}
 
-    pub fn connect_under_reset(&self) -> bool {
-        self.connect_under_reset
+/// A named region of the flash which is written from a separate binary file.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct FlashRegion {
+    name: String,
+    base: u32,
+    size: u32,
+    file: String,
+}
+
+impl FlashRegion {
+    /// Get a reference to the region's name.
+    pub fn name(&self) -> &String {
+        &self.name
     }
 
-    /// Get a reference to the config's file.
-    pub fn file(&self) -> &Option<String> {
-        &self.file
+    /// Get the region's base address.
+    pub fn base(&self) -> u32 {
+        self.base
     }
 
-    /// Get a reference to the config's format.
-    pub fn format(&self) -> Format {
-        self.format.clone()
+    /// Get the region's size in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
     }
 
-    /// Get a reference to the config's format base address.
-    pub fn format_base_address(&self) -> Option<u32> {
-        self.format_base_address
+    /// Get the address right after the end of the region.
+    pub fn end(&self) -> u64 {
+        self.base as u64 + self.size as u64
     }
 
-    /// Get a reference to the general's format skip.
-    pub fn format_skip(&self) -> Option<u32> {
-        self.format_skip
+    /// Get a reference to the path of the region's binary file.
+    pub fn file(&self) -> &String {
+        &self.file
     }
+}
 
-    /// Get a reference to the config's work dir.
-    pub fn work_dir(&self) -> &Option<String> {
-        &self.work_dir
+impl FromStr for FlashRegion {
+    type Err = ron::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ron::de::from_str(s)
     }
 }
 
-/// The logging config struct which controls what logging facilities to use and how.
+/// The reading config struct holding all the possible options for reading back the flash.
 #[derive(Debug, Deserialize, Serialize, StructOpt)]
-pub struct Gdb {
-    #[structopt(long = "gdb.enabled")]
+pub struct Reading {
+    #[structopt(name = "reading.enabled", long = "reading.enabled")]
     enabled: Option<bool>,
-    #[structopt(long = "gdb.socket")]
-    socket: Option<String>,
+    /// The memory range to read in the form `start:end`.
+    #[structopt(name = "reading.range", long = "reading.range")]
+    range: Option<MemoryRange>,
+    /// The path of the file the read memory is written to.
+    #[structopt(name = "reading.output-path", long = "reading.output-path")]
+    output_path: Option<String>,
+    /// The format of the output file. This is derived from the file extension if not given.
+    #[structopt(name = "reading.format", long = "reading.format")]
+    format: Option<ReadFormat>,
 }
 
-impl Gdb {
+impl Reading {
     pub fn enabled(&self) -> bool {
         if let Some(enabled) = self.enabled {
             enabled
         } else {
-            self.socket.is_some()
+            self.output_path.is_some()
         }
     }
 
-    pub fn socket(&self) -> &Option<String> {
-        &self.socket
+    pub fn range(&self) -> Option<MemoryRange> {
+        self.range
     }
-}
-
-/// The logging config struct which controls what logging facilities to use and how.
-#[derive(Debug, Deserialize, Serialize, StructOpt)]
-pub struct Logging {
-    #[structopt(long = "logging.enabled")]
-    enabled: Option<bool>,
-    #[structopt(long = "logging.channels")]
-    channels: Vec<Channel>,
-}
 
-impl Logging {
-    pub fn enabled(&self) -> bool {
-        if let Some(enabled) = self.enabled {
-            enabled
-        } else {
-            !self.channels.is_empty()
-        }
+    pub fn output_path(&self) -> &Option<String> {
+        &self.output_path
     }
 
-    pub fn channels(&self) -> &Vec<Channel> {
-        &self.channels
+    /// Get the format of the output file.
+    /// Files ending in `.hex` or `.ihex` are written as Intel HEX and all other files as raw binary.
+    pub fn format(&self) -> ReadFormat {
+        self.format.unwrap_or_else(|| {
+            match self.output_path.as_ref().and_then(|path| {
+                PathBuf::from(path)
+                    .extension()
+                    .map(|e| e.to_ascii_lowercase())
+            }) {
+                Some(extension) if extension == "hex" || extension == "ihex" => ReadFormat::Hex,
+                _ => ReadFormat::Bin,
+            }
+        })
     }
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// How the available chips and the version are listed.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for ListFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "text" => Ok(ListFormat::Text),
+            "json" => Ok(ListFormat::Json),
+            _ => Err(format!("List format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// How the flash progress is reported.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressFormat {
+    Bars,
+    Json,
+}
+
+impl FromStr for ProgressFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "bars" => Ok(ProgressFormat::Bars),
+            "json" => Ok(ProgressFormat::Json),
+            _ => Err(format!("Progress format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// How the flash contents are checked after programming.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "VerifySetting", into = "String")]
+pub enum VerifyMode {
+    /// The flash contents are not checked.
+    None,
+    /// The target computes a CRC32 of each segment which is compared with the one of the binary.
+    Crc,
+    /// The flash contents are read back and compared byte by byte.
+    Full,
+}
+
+impl FromStr for VerifyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "none" | "false" => Ok(VerifyMode::None),
+            "crc" => Ok(VerifyMode::Crc),
+            "full" | "true" => Ok(VerifyMode::Full),
+            _ => Err(format!("Verify mode '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// The verify mode as it is written in a config file.
+/// Booleans are still accepted as `verify` used to be a plain switch.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum VerifySetting {
+    Switch(bool),
+    Mode(String),
+}
+
+impl std::convert::TryFrom<VerifySetting> for VerifyMode {
+    type Error = String;
+
+    fn try_from(setting: VerifySetting) -> Result<Self, Self::Error> {
+        match setting {
+            VerifySetting::Switch(true) => Ok(VerifyMode::Full),
+            VerifySetting::Switch(false) => Ok(VerifyMode::None),
+            VerifySetting::Mode(mode) => mode.parse(),
+        }
+    }
+}
+
+impl From<VerifyMode> for String {
+    fn from(mode: VerifyMode) -> Self {
+        match mode {
+            VerifyMode::None => "none",
+            VerifyMode::Crc => "crc",
+            VerifyMode::Full => "full",
+        }
+        .into()
+    }
+}
+
+/// How errors are reported.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "pretty" => Ok(ErrorFormat::Pretty),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("Error format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// The format of a file the read memory is written to.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFormat {
+    Bin,
+    Hex,
+}
+
+impl FromStr for ReadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "bin" | "binary" => Ok(ReadFormat::Bin),
+            "hex" | "ihex" | "intelhex" => Ok(ReadFormat::Hex),
+            _ => Err(format!("Format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// A range of memory addresses in the form `start:end` where the end is exclusive.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct MemoryRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+impl FromStr for MemoryRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        let (start, end) = match (parts.next(), parts.next()) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Err(format!("The range '{}' is not in the form `start:end`.", s)),
+        };
+        let start = parse_u32(start.trim()).map_err(|e| format!("Invalid range start: {}", e))?;
+        let end = parse_u32(end.trim()).map_err(|e| format!("Invalid range end: {}", e))?;
+        if end <= start {
+            return Err(format!("The range '{}' is empty.", s));
+        }
+        Ok(MemoryRange { start, end })
+    }
+}
+
+impl std::convert::TryFrom<String> for MemoryRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<MemoryRange> for String {
+    fn from(range: MemoryRange) -> Self {
+        format!("{:#010x}:{:#010x}", range.start, range.end)
+    }
+}
+
+/// The reset config struct holding all the possible reset options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct Reset {
+    #[structopt(name = "reset.enabled", long = "reset.enabled")]
+    enabled: Option<bool>,
+    #[structopt(long = "reset.halt-afterwards")]
+    halt_afterwards: Option<bool>,
+    /// How many milliseconds to wait for the core to halt after the reset.
+    #[structopt(long = "reset.halt-timeout-ms")]
+    halt_timeout_ms: Option<u64>,
+    /// Run to `main` after the reset when the target is halted afterwards.
+    #[structopt(long = "reset.run-to-main")]
+    run_to_main: Option<bool>,
+    /// How many milliseconds to wait for the target to reach `main`.
+    #[structopt(long = "reset.run-to-main-timeout-ms")]
+    run_to_main_timeout_ms: Option<u64>,
+}
+
+impl Reset {
+    pub fn enabled(&self) -> bool {
+        match self.enabled {
+            Some(true) | None => true,
+            _ => self.halt_afterwards.is_some(),
+        }
+    }
+
+    pub fn halt_afterwards(&self) -> bool {
+        self.halt_afterwards.unwrap_or(false)
+    }
+
+    /// The time in milliseconds to wait for the core to halt after the reset.
+    pub fn halt_timeout_ms(&self) -> u64 {
+        self.halt_timeout_ms.unwrap_or(500)
+    }
+
+    pub fn run_to_main(&self) -> bool {
+        self.run_to_main.unwrap_or(false)
+    }
+
+    /// The time in milliseconds to wait for the target to reach `main`.
+    /// The startup code initializes the RAM before, which takes far longer than halting.
+    pub fn run_to_main_timeout_ms(&self) -> u64 {
+        self.run_to_main_timeout_ms.unwrap_or(5000)
+    }
+}
+
+/// The general config struct holding all the possible general options.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct General {
+    #[structopt(long = "general.chip")]
+    chip: Option<String>,
+    #[structopt(long = "general.chip-descriptions")]
+    chip_descriptions: Option<Vec<String>>,
+    #[structopt(long = "general.log-level")]
+    log_level: Option<log::Level>,
+    #[structopt(long = "general.derives")]
+    derives: Option<String>,
+    /// Use this flag to assert the nreset & ntrst pins during attaching the probe to the chip.
+    #[structopt(long = "general.connect-under-reset", parse(from_flag = flag))]
+    connect_under_reset: Flag,
+    /// How many milliseconds the reset is asserted before attaching with `connect-under-reset`.
+    #[structopt(long = "general.connect-under-reset-assert-ms")]
+    connect_under_reset_assert_ms: Option<u64>,
+    /// Halt the core right after attaching to it, without resetting it.
+    #[structopt(long = "general.halt-after-connect", parse(from_flag = flag))]
+    halt_after_connect: Flag,
+    /// Attach to the running target without resetting or halting it, and only run logging and GDB.
+    #[structopt(long = "general.attach-running", parse(from_flag = flag))]
+    attach_running: Flag,
+    /// The path a JSON crash report is written to when rover panics.
+    #[structopt(long = "general.crash-report-path", parse(from_os_str))]
+    crash_report_path: Option<PathBuf>,
+    #[structopt(
+        name = "binary file",
+        long = "file",
+        help = "The path to a binary file to be flashed. This can be given multiple times to flash several files at once."
+    )]
+    #[serde(default, deserialize_with = "one_or_many")]
+    file: Option<Vec<String>>,
+    #[structopt(
+        name = "format",
+        long = "format",
+        help = "The format of the binary file to be flashed. This is only read if the --file option is used. Possible values are ELF, HEX, BIN and UF2."
+    )]
+    format: Option<Format>,
+    #[structopt(
+        name = "uf2-family",
+        long = "format.uf2-family",
+        parse(try_from_str = parse_u32),
+        help = "Only flash the blocks of a UF2 file which are tagged with this family ID. This is only considered for UF2 files."
+    )]
+    format_uf2_family: Option<u32>,
+    #[structopt(
+        name = "base-address",
+        long = "format.base-address",
+        help = "The address where to put the binary data in flash. This is only considered for binary files. Given once per file in the order of the files."
+    )]
+    #[serde(default, deserialize_with = "one_or_many")]
+    format_base_address: Option<Vec<u32>>,
+    #[structopt(
+        name = "skip",
+        long = "format.skip",
+        help = "The number of bytes to skip and not to be flashed at the start of the binary. This is only considered for binary files. Given once per file in the order of the files."
+    )]
+    #[serde(default, deserialize_with = "one_or_many")]
+    format_skip: Option<Vec<u32>>,
+    #[structopt(
+        name = "directory",
+        long = "work-dir",
+        help = "The work directory from which cargo-flash should operate from."
+    )]
+    work_dir: Option<String>,
+}
+
+impl General {
+    pub fn chip(&self) -> &Option<String> {
+        &self.chip
+    }
+
+    pub fn chip_descriptions(&self) -> &[String] {
+        self.chip_descriptions.as_deref().unwrap_or_default()
+    }
+
+    pub fn log_level(&self) -> log::Level {
+        self.log_level.unwrap_or(log::Level::Warn)
+    }
+
+    pub fn connect_under_reset(&self) -> bool {
+        self.connect_under_reset.unwrap_or(false)
+    }
+
+    /// The time in milliseconds the reset is held before attaching under reset.
+    /// Without it, the reset is only asserted for as long as attaching takes.
+    pub fn connect_under_reset_assert_ms(&self) -> Option<u64> {
+        self.connect_under_reset_assert_ms
+    }
+
+    pub fn halt_after_connect(&self) -> bool {
+        self.halt_after_connect.unwrap_or(false)
+    }
+
+    /// Whether the target is left running as it is. Nothing is flashed or reset then.
+    pub fn attach_running(&self) -> bool {
+        self.attach_running.unwrap_or(false)
+    }
+
+    /// The path a JSON report with the metadata of the run, the panic message and a backtrace is written to on a panic.
+    pub fn crash_report_path(&self) -> &Option<PathBuf> {
+        &self.crash_report_path
+    }
+
+    /// Get a reference to the config's files.
+    pub fn file(&self) -> &[String] {
+        self.file.as_deref().unwrap_or_default()
+    }
+
+    /// Get a reference to the config's format.
+    pub fn format(&self) -> Format {
+        self.format.clone().unwrap_or(Format::Elf)
+    }
+
+    /// Get the UF2 family ID to filter for.
+    pub fn format_uf2_family(&self) -> Option<u32> {
+        self.format_uf2_family
+    }
+
+    /// Get the format base address of the file with the given index.
+    pub fn format_base_address(&self, index: usize) -> Option<u32> {
+        self.format_base_address.as_ref()?.get(index).copied()
+    }
+
+    /// Get the format skip of the file with the given index.
+    pub fn format_skip(&self, index: usize) -> Option<u32> {
+        self.format_skip.as_ref()?.get(index).copied()
+    }
+
+    /// The format of the file with the given index and path, including the options given for it.
+    /// A `.bin` file is read as binary if a base address is given for it while the format is left at ELF.
+    pub fn image_format(&self, index: usize, path: &Path) -> Format {
+        let is_bin_file = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map_or(false, |extension| extension.eq_ignore_ascii_case("bin"));
+        let bin_options = || BinOptions {
+            base_address: self.format_base_address(index),
+            skip: self.format_skip(index).unwrap_or(0),
+        };
+
+        match self.format() {
+            Format::Bin(_) => Format::Bin(bin_options()),
+            Format::Elf if is_bin_file && self.format_base_address(index).is_some() => {
+                log::info!(
+                    "Reading {} as a binary file as a base address is given for it.",
+                    path.display()
+                );
+                Format::Bin(bin_options())
+            }
+            Format::Uf2(_) => Format::Uf2(Uf2Options {
+                family: self.format_uf2_family(),
+            }),
+            format => format,
+        }
+    }
+
+    /// Describes the options for binary files which are given for the file with the given index,
+    /// but are ignored as the file is read in another format.
+    pub fn ignored_bin_options(&self, index: usize, format: &Format) -> Option<String> {
+        if matches!(format, Format::Bin(_)) {
+            return None;
+        }
+
+        let ignored = [
+            ("--format.base-address", self.format_base_address(index)),
+            ("--format.skip", self.format_skip(index)),
+        ]
+        .iter()
+        .filter(|(_, value)| value.is_some())
+        .map(|(option, _)| *option)
+        .collect::<Vec<_>>();
+        if ignored.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "{} only apply to binary files and are ignored as the file is read as {:?}. Use `--format bin` to read it as a binary file.",
+                ignored.join(" and "),
+                format
+            ))
+        }
+    }
+
+    /// Get a reference to the config's work dir.
+    pub fn work_dir(&self) -> &Option<String> {
+        &self.work_dir
+    }
+}
+
+/// The format of a binary file to be flashed.
+/// These are the formats supported by probe-rs plus UF2.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Format {
+    Bin(BinOptions),
+    Hex,
+    Elf,
+    Uf2(Uf2Options),
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "uf2" => Ok(Format::Uf2(Uf2Options::default())),
+            _ => probe_rs::flashing::Format::from_str(s).map(|format| match format {
+                probe_rs::flashing::Format::Bin(options) => Format::Bin(options),
+                probe_rs::flashing::Format::Hex => Format::Hex,
+                probe_rs::flashing::Format::Elf => Format::Elf,
+            }),
+        }
+    }
+}
+
+/// The options for flashing a UF2 file.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Uf2Options {
+    /// Only the blocks tagged with this family ID are flashed if it is set.
+    pub family: Option<u32>,
+}
+
+/// Parses a number either in decimal or in hexadecimal with a `0x` prefix.
+fn parse_u32(s: &str) -> Result<u32, std::num::ParseIntError> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
+
+/// A commandline flag. It is only set if it was given, so a missing flag does not override the config files.
+type Flag = Option<bool>;
+
+/// Parses a commandline flag by whether it was given.
+fn flag(given: bool) -> Flag {
+    if given {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+/// A list in the config files which can also be given as a single value, e.g. `file: app.elf`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+/// Deserializes a list which can also be given as a single value.
+fn one_or_many<'de, D, T>(deserializer: D) -> Result<Option<Vec<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Ok(
+        Option::<OneOrMany<T>>::deserialize(deserializer)?.map(|value| match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }),
+    )
+}
+
+/// The logging config struct which controls what logging facilities to use and how.
+#[derive(Debug, Deserialize, Serialize, StructOpt)]
+pub struct Gdb {
+    #[structopt(name = "gdb.enabled", long = "gdb.enabled")]
+    enabled: Option<bool>,
+    /// The full address the GDB stub listens on, e.g. `127.0.0.1:1337`. Takes precedence over the bind address and port.
+    #[structopt(long = "gdb.socket")]
+    socket: Option<String>,
+    /// The address the GDB stub binds to. Use `0.0.0.0` to debug from other machines.
+    #[structopt(long = "gdb.bind-address")]
+    bind_address: Option<String>,
+    /// The port the GDB stub listens on.
+    #[structopt(long = "gdb.port")]
+    port: Option<u16>,
+}
+
+impl Gdb {
+    pub fn enabled(&self) -> bool {
+        if let Some(enabled) = self.enabled {
+            enabled
+        } else {
+            self.socket.is_some() || self.bind_address.is_some() || self.port.is_some()
+        }
+    }
+
+    pub fn socket(&self) -> &Option<String> {
+        &self.socket
+    }
+
+    pub fn bind_address(&self) -> &Option<String> {
+        &self.bind_address
+    }
+
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+}
+
+/// The logging config struct which controls what logging facilities to use and how.
+#[derive(Debug, Deserialize, Serialize, StructOpt, Clone)]
+pub struct Logging {
+    #[structopt(name = "logging.enabled", long = "logging.enabled")]
+    enabled: Option<bool>,
+    #[structopt(long = "logging.channels")]
+    channels: Option<Vec<Channel>>,
+    /// Skip channels whose link fails to start instead of aborting the logging setup.
+    #[structopt(long = "logging.continue-on-channel-error")]
+    continue_on_channel_error: Option<bool>,
+    /// Reset the target once logging ends so it is left in a known state.
+    #[structopt(long = "logging.reset-afterwards")]
+    reset_afterwards: Option<bool>,
+    /// The frequency in Hz of the TPIU clock of the target used for ITM tracing.
+    #[structopt(long = "logging.itm-tpiu-clock")]
+    itm_tpiu_clock: Option<u32>,
+    /// The path of a file the undecoded defmt stream is written to.
+    #[structopt(long = "logging.defmt-raw-capture")]
+    defmt_raw_capture: Option<String>,
+    /// How many updates are queued for each link before further updates are dropped until the link catches up.
+    #[structopt(long = "logging.channel-buffer")]
+    channel_buffer: Option<usize>,
+    /// How many microseconds the links and the target are polled apart. Lower values reduce the latency but cost more CPU.
+    #[structopt(long = "logging.poll-interval-us")]
+    poll_interval_us: Option<u64>,
+    /// How many times in a row a command link is spawned again after it exited.
+    #[structopt(long = "logging.command-restarts")]
+    command_restarts: Option<u32>,
+    /// Where to look for the RTT control block: `ram`, an exact address or a `start:end` range.
+    /// If unset, the address of the `_SEGGER_RTT` symbol is used and all of RAM is scanned if it is missing.
+    #[structopt(long = "logging.rtt-scan-region")]
+    rtt_scan_region: Option<RttScanRegion>,
+    /// Give up if RTT could not be initialized within this many milliseconds instead of retrying forever.
+    #[structopt(long = "logging.rtt-timeout-ms")]
+    rtt_timeout_ms: Option<u64>,
+    /// How many bytes are read from an RTT up channel at most per poll.
+    #[structopt(long = "logging.rtt-read-buffer")]
+    rtt_read_buffer: Option<usize>,
+    /// Color the lines of text and defmt channels on stdout and terminal links by their level. Defaults to whether stdout is a terminal.
+    #[structopt(long = "logging.colors")]
+    colors: Option<bool>,
+    /// The prefixes which mark the level of a line of a string channel, e.g. `error=[E]`.
+    #[structopt(long = "logging.level-prefixes")]
+    level_prefixes: Option<Vec<LevelPrefix>>,
+    /// The format of the host side timestamps of string and ITM channels: `elapsed` or `rfc3339`.
+    #[structopt(long = "logging.timestamp-format")]
+    timestamp_format: Option<TimestampFormat>,
+}
+
+impl Logging {
+    pub fn enabled(&self) -> bool {
+        if let Some(enabled) = self.enabled {
+            enabled
+        } else {
+            !self.channels().is_empty()
+        }
+    }
+
+    pub fn channels(&self) -> &[Channel] {
+        self.channels.as_deref().unwrap_or_default()
+    }
+
+    pub fn continue_on_channel_error(&self) -> bool {
+        self.continue_on_channel_error.unwrap_or(false)
+    }
+
+    pub fn reset_afterwards(&self) -> bool {
+        self.reset_afterwards.unwrap_or(false)
+    }
+
+    pub fn itm_tpiu_clock(&self) -> Option<u32> {
+        self.itm_tpiu_clock
+    }
+
+    pub fn defmt_raw_capture(&self) -> &Option<String> {
+        &self.defmt_raw_capture
+    }
+
+    pub fn channel_buffer(&self) -> usize {
+        self.channel_buffer.unwrap_or(1024)
+    }
+
+    pub fn poll_interval_us(&self) -> Option<u64> {
+        self.poll_interval_us
+    }
+
+    pub fn command_restarts(&self) -> u32 {
+        self.command_restarts.unwrap_or(3)
+    }
+
+    pub fn rtt_scan_region(&self) -> Option<RttScanRegion> {
+        self.rtt_scan_region
+    }
+
+    pub fn rtt_timeout_ms(&self) -> Option<u64> {
+        self.rtt_timeout_ms
+    }
+
+    /// The size of the buffer the RTT up channels are read into. It is at least one byte.
+    pub fn rtt_read_buffer(&self) -> usize {
+        self.rtt_read_buffer.unwrap_or(1 << 16).max(1)
+    }
+
+    pub fn colors(&self) -> bool {
+        // NO_COLOR always wins, see https://no-color.org.
+        if std::env::var_os("NO_COLOR").is_some() {
+            return false;
+        }
+        self.colors
+            .unwrap_or_else(|| atty::is(atty::Stream::Stdout))
+    }
+
+    pub fn level_prefixes(&self) -> &[LevelPrefix] {
+        self.level_prefixes.as_deref().unwrap_or_default()
+    }
+
+    pub fn timestamp_format(&self) -> TimestampFormat {
+        self.timestamp_format.unwrap_or(TimestampFormat::Elapsed)
+    }
+}
+
+/// The format of the host side timestamps of logged lines.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// The seconds since logging started.
+    Elapsed,
+    /// The wall clock time in UTC.
+    Rfc3339,
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "elapsed" => Ok(TimestampFormat::Elapsed),
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            _ => Err(format!("Timestamp format '{}' is unknown.", s)),
+        }
+    }
+}
+
+/// A prefix which marks the level of a line of a string channel in the form `level=prefix`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub struct LevelPrefix {
+    pub level: log::Level,
+    pub prefix: String,
+}
+
+impl FromStr for LevelPrefix {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(level), Some(prefix)) if !prefix.is_empty() => Ok(LevelPrefix {
+                level: level
+                    .parse()
+                    .map_err(|_| format!("'{}' is not a log level.", level))?,
+                prefix: prefix.to_string(),
+            }),
+            _ => Err(format!(
+                "The level prefix '{}' is not in the form `level=prefix`.",
+                s
+            )),
+        }
+    }
+}
+
+impl std::convert::TryFrom<String> for LevelPrefix {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<LevelPrefix> for String {
+    fn from(prefix: LevelPrefix) -> Self {
+        format!(
+            "{}={}",
+            prefix.level.to_string().to_lowercase(),
+            prefix.prefix
+        )
+    }
+}
+
+/// The memory region which is scanned for the RTT control block.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+pub enum RttScanRegion {
+    /// The control block is exactly at this address.
+    Exact(u32),
+    /// All of the RAM of the target is scanned.
+    Ram,
+    /// The RAM between the start and the exclusive end is scanned.
+    Range(u32, u32),
+}
+
+impl FromStr for RttScanRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("ram") {
+            Ok(RttScanRegion::Ram)
+        } else if s.contains(':') {
+            let range = s.parse::<MemoryRange>()?;
+            Ok(RttScanRegion::Range(range.start, range.end))
+        } else {
+            parse_u32(s.trim())
+                .map(RttScanRegion::Exact)
+                .map_err(|_| {
+                    format!(
+                        "The RTT scan region '{}' is unknown. Use `ram`, `<address>` or `<start>:<end>`.",
+                        s
+                    )
+                })
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub struct Channel {
     kinds: Vec<ChannelKind>,
     link: LinkKind,
@@ -379,33 +1607,252 @@ impl Channel {
 }
 
 impl FromStr for Channel {
-    type Err = ron::Error;
-
+    type Err = String;
+
+    /// Parses a channel from RON or from the shorthand `<kinds>@<link>`.
+    ///
+    /// The kinds are separated by `+` and are either `rtt:<up>:<mode>`, `rtt:<up>:<down>:<mode>`, `itm:<mode>` or `semihosting`.
+    /// The RTT channels are given by their number or by their name.
+    /// The RTT modes are `raw`, `string`, `string-timestamps`, `string-json`, `defmt` and `defmt-json`.
+    /// The ITM modes are `raw`, `string`, `string-timestamps` and `decoded-json`.
+    /// The link is one of `stdout`, `tcp:<address>`, `ws:<address>`, `cmd:<command>`, `file:<path>`, `serial:<path>:<baud>`,
+    /// `unix:<path>`, `terminal:<title>` or `udp:<address>`.
+    /// For example `rtt:0:defmt@tcp:127.0.0.1:8080`.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        ron::de::from_str(s)
+        let s = s.trim();
+        if s.starts_with('(') {
+            return ron::de::from_str(s).map_err(|error| error.to_string());
+        }
+
+        let mut parts = s.splitn(2, '@');
+        let (kinds, link) = match (parts.next(), parts.next()) {
+            (Some(kinds), Some(link)) => (kinds, link),
+            _ => {
+                return Err(format!(
+                    "Channel '{}' is missing the link. Use the form `<kinds>@<link>`.",
+                    s
+                ))
+            }
+        };
+
+        Ok(Channel {
+            kinds: kinds
+                .split('+')
+                .map(parse_channel_kind)
+                .collect::<Result<_, _>>()?,
+            link: parse_link_kind(link)?,
+        })
+    }
+}
+
+/// Parses a channel kind of the shorthand channel syntax.
+fn parse_channel_kind(s: &str) -> Result<ChannelKind, String> {
+    let parts = s.split(':').collect::<Vec<_>>();
+    match parts[..] {
+        ["rtt", up, mode] => Ok(ChannelKind::Rtt {
+            up: up.parse()?,
+            down: RttChannel::Number(0),
+            mode: mode.parse()?,
+            prefix: None,
+        }),
+        ["rtt", up, down, mode] => Ok(ChannelKind::Rtt {
+            up: up.parse()?,
+            down: down.parse()?,
+            mode: mode.parse()?,
+            prefix: None,
+        }),
+        ["itm", mode] => Ok(ChannelKind::Itm {
+            mode: mode.parse()?,
+        }),
+        ["semihosting"] => Ok(ChannelKind::Semihosting),
+        _ => Err(format!(
+            "Channel kind '{}' is unknown. Use `rtt:<up>:<mode>`, `rtt:<up>:<down>:<mode>`, `itm:<mode>` or `semihosting`.",
+            s
+        )),
+    }
+}
+
+/// Parses a link of the shorthand channel syntax.
+fn parse_link_kind(s: &str) -> Result<LinkKind, String> {
+    let mut parts = s.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some("stdout"), None) => Ok(LinkKind::Stdout),
+        (Some("tcp"), Some(address)) => Ok(LinkKind::Tcp(address.to_string(), None)),
+        (Some("ws"), Some(address)) => Ok(LinkKind::WebSocket(address.to_string(), None)),
+        (Some("cmd"), Some(command)) => Ok(LinkKind::Command(command.to_string())),
+        (Some("file"), Some(path)) => Ok(LinkKind::File(path.to_string())),
+        (Some("serial"), Some(port)) => {
+            // The path itself might contain colons, so the baud rate is split off at the end.
+            let mut parts = port.rsplitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(baud), Some(path)) => Ok(LinkKind::Serial {
+                    path: path.to_string(),
+                    baud: baud
+                        .parse()
+                        .map_err(|_| format!("'{}' is not a valid baud rate.", baud))?,
+                }),
+                _ => Err(format!(
+                    "Serial link '{}' is missing the baud rate. Use `serial:<path>:<baud>`.",
+                    s
+                )),
+            }
+        }
+        #[cfg(unix)]
+        (Some("unix"), Some(path)) => Ok(LinkKind::Unix(path.to_string())),
+        (Some("terminal"), Some(title)) => Ok(LinkKind::Terminal(title.to_string())),
+        (Some("udp"), Some(address)) => Ok(LinkKind::Udp(address.to_string())),
+        _ => Err(format!(
+            "Link '{}' is unknown. Use `stdout`, `tcp:<address>`, `ws:<address>`, `cmd:<command>`, `file:<path>`, `serial:<path>:<baud>`, `unix:<path>`, `terminal:<title>` or `udp:<address>`.",
+            s
+        )),
     }
 }
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq, Hash)]
 pub enum LinkKind {
+    /// The stdout of rover, mixed with its own output.
+    Stdout,
     Command(String),
-    Tcp(String),
-    WebSocket(String),
+    /// A TCP socket. If the token is given, clients have to send it as their first line to get any data.
+    Tcp(String, #[serde(default)] Option<String>),
+    /// A websocket. If the token is given, clients have to send it as their first message to get any data.
+    WebSocket(String, #[serde(default)] Option<String>),
+    /// A websocket whose clients connect with TLS (`wss://`), presenting the PEM encoded certificate chain
+    /// in `cert` with the PEM encoded PKCS #8 private key in `key`.
+    WebSocketTls {
+        socket: String,
+        cert: String,
+        key: String,
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// The path of a file all the output is appended to.
+    File(String),
+    /// A serial port on the host, e.g. to bridge the output to a legacy tool.
+    Serial {
+        path: String,
+        baud: u32,
+    },
+    /// The path of a Unix domain socket local processes can connect to.
+    #[cfg(unix)]
+    Unix(String),
+    /// The title of a pane in the full-screen terminal view of rover.
+    Terminal(String),
+    /// The address every update is sent to as UDP datagrams, without knowing whether they arrive.
+    Udp(String),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl LinkKind {
+    /// The name of the kind of link, as it is listed with `--list-channels`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LinkKind::Stdout => "Stdout",
+            LinkKind::Command(_) => "Command",
+            LinkKind::Tcp(..) => "Tcp",
+            LinkKind::WebSocket(..) => "WebSocket",
+            LinkKind::WebSocketTls { .. } => "WebSocketTls",
+            LinkKind::File(_) => "File",
+            LinkKind::Serial { .. } => "Serial",
+            #[cfg(unix)]
+            LinkKind::Unix(_) => "Unix",
+            LinkKind::Terminal(_) => "Terminal",
+            LinkKind::Udp(_) => "Udp",
+        }
+    }
+
+    /// Where the link forwards to, e.g. the address of a socket or the command line of a command.
+    pub fn target(&self) -> String {
+        match self {
+            LinkKind::Stdout => "stdout".into(),
+            LinkKind::Command(target)
+            | LinkKind::Tcp(target, _)
+            | LinkKind::WebSocket(target, _)
+            | LinkKind::File(target)
+            | LinkKind::Terminal(target)
+            | LinkKind::Udp(target) => target.clone(),
+            LinkKind::WebSocketTls { socket, .. } => socket.clone(),
+            LinkKind::Serial { path, baud } => format!("{} at {} baud", path, baud),
+            #[cfg(unix)]
+            LinkKind::Unix(target) => target.clone(),
+        }
+    }
+
+    /// Whether the link ends up in a terminal, so its lines can be colored.
+    pub fn shows_colors(&self) -> bool {
+        matches!(self, LinkKind::Stdout | LinkKind::Terminal(_))
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum ChannelKind {
     Rtt {
-        up: usize,
-        down: usize,
+        up: RttChannel,
+        down: RttChannel,
         mode: RttMode,
+        /// A tag like `[APP] ` which is prepended to every line of text forwarded from this channel.
+        #[serde(default)]
+        prefix: Option<String>,
     },
     Itm {
         mode: ItmMode,
     },
+    /// The text the firmware writes with the `SYS_WRITEC` and `SYS_WRITE0` semihosting calls.
+    Semihosting,
+}
+
+impl ChannelKind {
+    /// Where the data of this kind comes from, as it is listed with `--list-channels`.
+    pub fn source(&self) -> String {
+        match self {
+            ChannelKind::Rtt { up, down, .. } => format!("RTT up {}, down {}", up, down),
+            ChannelKind::Itm { .. } => "ITM".into(),
+            ChannelKind::Semihosting => "Semihosting".into(),
+        }
+    }
+
+    /// The mode of this kind in the shorthand syntax, if it has one.
+    pub fn mode(&self) -> Option<String> {
+        match self {
+            ChannelKind::Rtt { mode, .. } => Some(mode.to_string()),
+            ChannelKind::Itm { mode } => Some(mode.to_string()),
+            ChannelKind::Semihosting => None,
+        }
+    }
+}
+
+/// An RTT channel, either by its number or by the name the firmware gave it.
+/// Names are resolved once RTT is attached, so they keep working when the firmware reorders its channels.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RttChannel {
+    Number(usize),
+    Name(String),
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl std::fmt::Display for RttChannel {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RttChannel::Number(number) => write!(f, "{}", number),
+            RttChannel::Name(name) => write!(f, "'{}'", name),
+        }
+    }
+}
+
+impl FromStr for RttChannel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            Err("The RTT channel is missing. Use its number or its name.".into())
+        } else {
+            Ok(s.parse()
+                .map(RttChannel::Number)
+                .unwrap_or_else(|_| RttChannel::Name(s.to_string())))
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum RttMode {
     Raw,
     String { timestamps: bool },
@@ -414,13 +1861,70 @@ pub enum RttMode {
     DefmtJson,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+impl FromStr for RttMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(RttMode::Raw),
+            "string" => Ok(RttMode::String { timestamps: false }),
+            "string-timestamps" => Ok(RttMode::String { timestamps: true }),
+            "string-json" => Ok(RttMode::StringJson),
+            "defmt" => Ok(RttMode::Defmt),
+            "defmt-json" => Ok(RttMode::DefmtJson),
+            _ => Err(format!("RTT mode '{}' is unknown.", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for RttMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            RttMode::Raw => "raw",
+            RttMode::String { timestamps: false } => "string",
+            RttMode::String { timestamps: true } => "string-timestamps",
+            RttMode::StringJson => "string-json",
+            RttMode::Defmt => "defmt",
+            RttMode::DefmtJson => "defmt-json",
+        })
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
 pub enum ItmMode {
     Raw,
-    String { timestamps: bool },
+    String {
+        timestamps: bool,
+    },
+    /// Every packet which carries data as a JSON line with its `type`, `port`, `payload` and `timestamp`.
     DecodedJson,
 }
 
+impl FromStr for ItmMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(ItmMode::Raw),
+            "string" => Ok(ItmMode::String { timestamps: false }),
+            "string-timestamps" => Ok(ItmMode::String { timestamps: true }),
+            "decoded-json" => Ok(ItmMode::DecodedJson),
+            _ => Err(format!("ITM mode '{}' is unknown.", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ItmMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ItmMode::Raw => "raw",
+            ItmMode::String { timestamps: false } => "string",
+            ItmMode::String { timestamps: true } => "string-timestamps",
+            ItmMode::DecodedJson => "decoded-json",
+        })
+    }
+}
+
 impl Configs {
     pub fn try_new(name: impl AsRef<str>, args: &Vec<String>) -> anyhow::Result<Config> {
         let mut s = config::Config::new();
@@ -431,40 +1935,68 @@ impl Configs {
             config::FileFormat::Yaml,
         ))?;
 
+        Self::merge_files(&mut s, &std::env::current_dir()?)?;
+
+        let map: HashMap<String, serde_json::value::Value> = s.try_into()?;
+
+        Self::merge_args(name, args, map, std::env::vars())
+    }
+
+    /// Merges the config files found in the given directory.
+    fn merge_files(s: &mut config::Config, directory: &Path) -> anyhow::Result<()> {
         // Ordered list of config files, which are handled in the order specified here.
+        // If a project has multiple of them, the values of the later files win, so YAML overrides TOML.
         let config_files = [
             // Merge in the project-specific configuration files.
             // These files may be added to your git repo.
-            // ".embed",
-            "Rover",
+            ("Rover.toml", config::FileFormat::Toml),
+            ("Rover.json", config::FileFormat::Json),
+            ("Rover.yaml", config::FileFormat::Yaml),
+            ("Rover.yml", config::FileFormat::Yaml),
             // Merge in the local configuration files.
             // These files should not be added to your git repo.
             // ".embed.local",
             // "Embed.local",
-            // As described in https://github.com/mehcode/config-rs/issues/101
-            // the above lines will not work unless that bug is fixed, until
-            // then, we add ".ext" to be replaced with a valid format name.
-            // ".embed.local.ext",
-            // "Embed.local.ext",
         ];
 
-        for file in &config_files {
-            s.merge(config::File::with_name(file).required(false))
-                .with_context(|| format!("Failed to merge config file '{}", file))?;
+        for (file, format) in &config_files {
+            let path = directory.join(file);
+            s.merge(config::File::new(&path.to_string_lossy(), *format).required(false))
+                .with_context(|| format!("Failed to merge config file '{}", path.display()))?;
         }
 
-        let mut map: HashMap<String, serde_json::value::Value> = s.try_into()?;
+        Ok(())
+    }
 
-        if !map.contains_key(name.as_ref()) {
+    /// Merges the commandline arguments over the config with the given name or the one selected with `--config`.
+    /// The config itself is merged over the configs it derives from, and the given environment variables over both.
+    fn merge_args(
+        name: impl AsRef<str>,
+        args: &[String],
+        mut map: HashMap<String, serde_json::value::Value>,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Config> {
+        let mut structopt = Config::from_iter(args);
+        // A config selected on the commandline takes precedence over the given one.
+        let name = structopt
+            .config
+            .clone()
+            .unwrap_or_else(|| name.as_ref().to_string());
+        if !map.contains_key(&name) {
             bail!(
                 "Cannot find config \"{}\" (available configs: {})",
-                name.as_ref(),
+                name,
                 map.keys().cloned().collect::<Vec<String>>().join(", "),
             );
         }
-        let mut structopt = Config::from_iter(args);
-        structopt.general.derives = Some(name.as_ref().to_string());
-        let structopt: Value = serde_json::to_value(&structopt)?;
+        // The environment overrides the selected config and is in turn overridden by the commandline.
+        let mut environment = Self::environment(vars)?;
+        environment["general"]["derives"] = Value::String(name);
+        map.insert("environment".into(), environment);
+        structopt.general.derives = Some("environment".into());
+        let mut structopt: Value = serde_json::to_value(&structopt)?;
+        // Arguments which were not given on the commandline must not override the values of the config files.
+        remove_unset(&mut structopt);
         map.insert("structopt".into(), structopt.clone());
 
         let mut s = config::Config::new();
@@ -475,12 +2007,42 @@ impl Configs {
         Ok(s.try_into()?)
     }
 
+    /// Collects the config values given in environment variables.
+    /// The variables are prefixed with `ROVER_` and a double underscore separates the sections from the keys,
+    /// so `ROVER_GENERAL__CHIP` sets `general.chip` and `ROVER_RESET__HALT_AFTERWARDS` sets `reset.halt_afterwards`.
+    fn environment(vars: impl IntoIterator<Item = (String, String)>) -> anyhow::Result<Value> {
+        let mut s = config::Config::new();
+        for (key, value) in vars {
+            let key = key.to_lowercase();
+            if let Some(key) = key.strip_prefix("rover_") {
+                s.set(&key.replace("__", "."), value)?;
+            }
+        }
+
+        let map: HashMap<String, serde_json::value::Value> = s.try_into()?;
+        Ok(serde_json::to_value(map)?)
+    }
+
     pub fn apply(
         name: &str,
         s: &mut config::Config,
         config: &serde_json::value::Value,
         map: &HashMap<String, serde_json::value::Value>,
     ) -> Result<(), config::ConfigError> {
+        Self::apply_chain(name, s, config, map, &mut vec![])
+    }
+
+    /// Applies the given config over the configs it derives from.
+    /// `chain` holds the names of the configs which derive from this one, so cycles can be detected.
+    fn apply_chain(
+        name: &str,
+        s: &mut config::Config,
+        config: &serde_json::value::Value,
+        map: &HashMap<String, serde_json::value::Value>,
+        chain: &mut Vec<String>,
+    ) -> Result<(), config::ConfigError> {
+        chain.push(name.to_string());
+
         // If this config derives from another config, merge the other config first.
         // Do this recursively.
         if let Some(derives) = config
@@ -488,13 +2050,37 @@ impl Configs {
             .and_then(|g| g.get("derives").and_then(|d| d.as_str()))
             .or(Some("default"))
         {
-            if derives == name {
-                log::warn!("Endless recursion within the {} config.", derives);
+            if name == "default" && derives == name {
+                // The default config derives from itself as it is the root of all chains.
+            } else if chain.iter().any(|n| n == derives) {
+                return Err(config::ConfigError::Message(format!(
+                    "The configs derive from each other in a cycle: {} -> {}",
+                    chain.join(" -> "),
+                    derives
+                )));
             } else if let Some(dconfig) = map.get(derives) {
-                println!("derives {}", derives);
-                Self::apply(derives, s, dconfig, map)?;
+                log::debug!("Config {} derives from {}", name, derives);
+                Self::apply_chain(derives, s, dconfig, map, chain)?;
+            }
+        }
+        // Cargo features are accumulated along the derives chain instead of being replaced.
+        // This way a profile can imply features on top of the ones it inherits or that were given on the commandline.
+        let mut config = config.clone();
+        if let Some(features) = config.get_mut("features").and_then(|f| f.as_array_mut()) {
+            let mut merged = s
+                .get::<Vec<String>>("features")
+                .unwrap_or_default()
+                .into_iter()
+                .map(Value::String)
+                .collect::<Vec<_>>();
+            for feature in features.drain(..) {
+                if !merged.contains(&feature) {
+                    merged.push(feature);
+                }
             }
+            *features = merged;
         }
+
         // Merge this current config.
         s.merge(config::File::from_str(
             // This unwrap can never fail as we just deserialized this. The reverse has to work!
@@ -505,15 +2091,36 @@ impl Configs {
     }
 }
 
+/// Recursively removes all the values which are unset from the given config.
+fn remove_unset(value: &mut Value) {
+    if let Value::Object(map) = value {
+        let unset = map
+            .iter()
+            .filter(|(_, v)| v.is_null())
+            .map(|(k, _)| k.clone())
+            .collect::<Vec<_>>();
+        for key in unset {
+            map.remove(&key);
+        }
+        for v in map.values_mut() {
+            remove_unset(v);
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use std::vec;
+    use std::{collections::HashMap, path::Path, vec};
+
+    use probe_rs::flashing::BinOptions;
 
-    use probe_rs::flashing::Format;
+    use serde_json::json;
+    use structopt::StructOpt;
 
     use super::{
-        Channel, ChannelKind, Config, Configs, Flashing, Gdb, General, ItmMode, LinkKind, Logging,
-        Probe, Reset,
+        Channel, ChannelKind, Config, Configs, FlashTarget, Flashing, Format, Gdb, General,
+        ItmMode, LevelPrefix, LinkKind, Logging, MemoryRange, MemoryWrite, Probe, Reading, Reset,
+        RttChannel, RttMode, RttScanRegion, VerifyMode,
     };
 
     #[test]
@@ -523,17 +2130,558 @@ mod test {
         let _config = Configs::try_new("default", &vec![]).unwrap();
     }
 
+    #[test]
+    fn args_override_config() {
+        // Ensure given arguments override the config while arguments which were not given do not.
+
+        let mut map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        map.insert(
+            "board".to_string(),
+            json!({
+                "general": {
+                    "chip": "nRF52840",
+                    "chip_descriptions": ["board.yaml"],
+                    "log_level": "INFO",
+                    "connect_under_reset": true,
+                    "format": "Hex"
+                },
+                "probe": { "speed": 1000 },
+                "flashing": {
+                    "regions": [{ "name": "bootloader", "base": 0, "size": 4096, "file": "bootloader.bin" }]
+                },
+                "dry_run": true
+            }),
+        );
+        let args = ["rover", "--probe.speed", "4000", "--stats"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+
+        let config = Configs::merge_args("board", &args, map, vec![]).unwrap();
+
+        assert_eq!(config.general().chip(), &Some("nRF52840".to_string()));
+        assert_eq!(config.probe().speed(), Some(4000));
+        assert_eq!(config.flashing().regions().len(), 1);
+        assert_eq!(config.general().chip_descriptions(), ["board.yaml"]);
+        assert_eq!(config.general().log_level(), log::Level::Info);
+        assert!(config.general().connect_under_reset());
+        assert!(matches!(config.general().format(), Format::Hex));
+        assert!(config.dry_run());
+        assert!(config.stats());
+    }
+
+    #[test]
+    fn do_chip_erase_is_parsed() {
+        // Ensure a chip erase requested on the commandline reaches the flashing config.
+
+        let map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        let args = ["rover", "--flashing.do-chip-erase", "true"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+
+        let config = Configs::merge_args("default", &args, map, vec![]).unwrap();
+
+        assert!(config.flashing().do_chip_erase());
+    }
+
+    #[test]
+    fn single_file_is_accepted() {
+        // Ensure configs which give a single file instead of a list still work.
+
+        let mut map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        map.insert(
+            "board".to_string(),
+            json!({
+                "general": { "file": "app.bin", "format_base_address": 134217728, "format_skip": 4 }
+            }),
+        );
+
+        let config = Configs::merge_args("board", &["rover".to_string()], map, vec![]).unwrap();
+
+        assert_eq!(config.general().file(), ["app.bin"]);
+        assert_eq!(config.general().format_base_address(0), Some(0x0800_0000));
+        assert_eq!(config.general().format_skip(0), Some(4));
+    }
+
+    #[test]
+    fn multiple_files_are_collected() {
+        // Ensure every given file keeps its own base address.
+
+        let map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        let args = [
+            "rover",
+            "--file",
+            "bootloader.bin",
+            "--format.base-address",
+            "0",
+            "--file",
+            "app.bin",
+            "--format.base-address",
+            "32768",
+        ]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect::<Vec<_>>();
+
+        let config = Configs::merge_args("default", &args, map, vec![]).unwrap();
+
+        assert_eq!(
+            config.general().file(),
+            &vec!["bootloader.bin".to_string(), "app.bin".to_string()]
+        );
+        assert_eq!(config.general().format_base_address(0), Some(0));
+        assert_eq!(config.general().format_base_address(1), Some(32768));
+    }
+
+    #[test]
+    fn selected_config_is_used() {
+        // Ensure the config selected on the commandline wins over the default one.
+
+        let mut map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        map.insert(
+            "release".to_string(),
+            json!({ "general": { "chip": "nRF52840" }, "probe": { "speed": 4000 } }),
+        );
+        map.insert(
+            "debug".to_string(),
+            json!({ "general": { "chip": "STM32F429ZITx" }, "probe": { "speed": 1000 } }),
+        );
+        let args = ["rover", "--config", "debug"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+
+        let config = Configs::merge_args("default", &args, map.clone(), vec![]).unwrap();
+
+        assert_eq!(config.general().chip(), &Some("STM32F429ZITx".to_string()));
+        assert_eq!(config.probe().speed(), Some(1000));
+
+        let args = ["rover", "--config", "ci"]
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect::<Vec<_>>();
+
+        assert!(Configs::merge_args("default", &args, map, vec![]).is_err());
+    }
+
+    #[test]
+    fn environment_overrides_config() {
+        // Ensure a config value given in the environment lands in the config.
+
+        let map: HashMap<String, serde_json::Value> =
+            serde_yaml::from_str(include_str!("default.yaml")).unwrap();
+        let vars = vec![(
+            "ROVER_RESET__HALT_AFTERWARDS".to_string(),
+            "true".to_string(),
+        )];
+
+        let config = Configs::merge_args("default", &["rover".to_string()], map, vars).unwrap();
+
+        assert!(config.reset().halt_afterwards());
+    }
+
+    #[test]
+    fn channel_shorthand_is_parsed() {
+        let channel: Channel = "rtt:0:defmt+itm:string-timestamps@tcp:127.0.0.1:8080"
+            .parse()
+            .unwrap();
+
+        assert_eq!(
+            channel,
+            Channel {
+                kinds: vec![
+                    ChannelKind::Rtt {
+                        up: RttChannel::Number(0),
+                        down: RttChannel::Number(0),
+                        mode: RttMode::Defmt,
+                        prefix: None,
+                    },
+                    ChannelKind::Itm {
+                        mode: ItmMode::String { timestamps: true },
+                    },
+                ],
+                link: LinkKind::Tcp("127.0.0.1:8080".into(), None),
+            }
+        );
+        assert_eq!(
+            "rtt:1:2:string@ws:127.0.0.1:9000".parse::<Channel>(),
+            "(kinds: [Rtt(up: 1, down: 2, mode: String(timestamps: false))], link: WebSocket(\"127.0.0.1:9000\"))"
+                .parse::<Channel>()
+        );
+        assert_eq!(
+            "rtt:0:raw@serial:/dev/ttyUSB0:115200"
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone()),
+            Ok(LinkKind::Serial {
+                path: "/dev/ttyUSB0".into(),
+                baud: 115200,
+            })
+        );
+        assert_eq!(
+            "rtt:0:string@udp:192.168.1.10:5140"
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone()),
+            Ok(LinkKind::Udp("192.168.1.10:5140".into()))
+        );
+        assert_eq!(
+            "rtt:0:string@terminal:App"
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone()),
+            Ok(LinkKind::Terminal("App".into()))
+        );
+        assert_eq!(
+            "rtt:0:string@stdout"
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone()),
+            Ok(LinkKind::Stdout)
+        );
+        assert_eq!(
+            "rtt:0:string@stdout".parse::<Channel>(),
+            "(kinds: [Rtt(up: 0, down: 0, mode: String(timestamps: false))], link: Stdout)"
+                .parse::<Channel>()
+        );
+        assert_eq!(
+            "rtt:defmt:1:defmt@tcp:127.0.0.1:8080"
+                .parse::<Channel>()
+                .map(|channel| channel.kinds()[0].clone()),
+            Ok(ChannelKind::Rtt {
+                up: RttChannel::Name("defmt".into()),
+                down: RttChannel::Number(1),
+                mode: RttMode::Defmt,
+                prefix: None,
+            })
+        );
+        assert_eq!(
+            "semihosting@file:semihosting.log"
+                .parse::<Channel>()
+                .map(|channel| channel.kinds().clone()),
+            Ok(vec![ChannelKind::Semihosting])
+        );
+        assert!("rtt:0:defmt".parse::<Channel>().is_err());
+        assert!("rtt:0:unknown@tcp:127.0.0.1:8080"
+            .parse::<Channel>()
+            .is_err());
+    }
+
+    #[test]
+    fn channel_ron_round_trips() {
+        for shorthand in &["rtt:0:string-json@cmd:cat", "rtt:Terminal:string@cmd:cat"] {
+            let channel: Channel = shorthand.parse().unwrap();
+            let ron = ron::ser::to_string(&channel).unwrap();
+
+            assert_eq!(ron.parse::<Channel>(), Ok(channel));
+        }
+    }
+
+    #[test]
+    fn cargo_build_args_are_built_from_the_config() {
+        let config = Config::from_iter(&["rover"]);
+        assert!(config.cargo_build_args().is_empty());
+
+        let config = Config::from_iter(&[
+            "rover",
+            "--bin",
+            "app",
+            "-p",
+            "firmware",
+            "--release",
+            "--target",
+            "thumbv7em-none-eabihf",
+            "--manifest-path",
+            "firmware/Cargo.toml",
+            "--no-default-features",
+            "--features",
+            "defmt",
+            "--features",
+            "rtt",
+        ]);
+        assert_eq!(
+            config.cargo_build_args(),
+            vec![
+                "--bin",
+                "app",
+                "--package",
+                "firmware",
+                "--release",
+                "--target",
+                "thumbv7em-none-eabihf",
+                "--manifest-path",
+                "firmware/Cargo.toml",
+                "--no-default-features",
+                "--features",
+                "defmt,rtt",
+            ]
+        );
+
+        let config = Config::from_iter(&["rover", "--example", "blinky", "--all-features"]);
+        assert_eq!(
+            config.cargo_build_args(),
+            vec!["--example", "blinky", "--all-features"]
+        );
+    }
+
+    #[test]
+    fn link_tokens_are_optional() {
+        let link = |ron: &str| {
+            format!("(kinds: [Semihosting], link: {})", ron)
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone())
+        };
+
+        assert_eq!(
+            link("Tcp(\"127.0.0.1:8080\")"),
+            Ok(LinkKind::Tcp("127.0.0.1:8080".into(), None))
+        );
+        assert_eq!(
+            link("WebSocket(\"127.0.0.1:9000\", Some(\"secret\"))"),
+            Ok(LinkKind::WebSocket(
+                "127.0.0.1:9000".into(),
+                Some("secret".into())
+            ))
+        );
+        assert_eq!(
+            link("WebSocketTls(socket: \"127.0.0.1:9443\", cert: \"cert.pem\", key: \"key.pem\")"),
+            Ok(LinkKind::WebSocketTls {
+                socket: "127.0.0.1:9443".into(),
+                cert: "cert.pem".into(),
+                key: "key.pem".into(),
+                token: None,
+            })
+        );
+    }
+
+    #[test]
+    fn channels_are_described() {
+        let channel: Channel =
+            "rtt:defmt:1:defmt-json+itm:decoded-json+semihosting@serial:/dev/ttyUSB0:115200"
+                .parse()
+                .unwrap();
+
+        let kinds = channel
+            .kinds()
+            .iter()
+            .map(|kind| (kind.source(), kind.mode()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                (
+                    "RTT up 'defmt', down 1".to_string(),
+                    Some("defmt-json".to_string())
+                ),
+                ("ITM".to_string(), Some("decoded-json".to_string())),
+                ("Semihosting".to_string(), None),
+            ]
+        );
+        assert_eq!(channel.link().name(), "Serial");
+        assert_eq!(channel.link().target(), "/dev/ttyUSB0 at 115200 baud");
+
+        for mode in &[
+            "raw",
+            "string",
+            "string-timestamps",
+            "string-json",
+            "defmt",
+            "defmt-json",
+        ] {
+            assert_eq!(mode.parse::<RttMode>().unwrap().to_string(), *mode);
+        }
+        for mode in &["raw", "string", "string-timestamps", "decoded-json"] {
+            assert_eq!(mode.parse::<ItmMode>().unwrap().to_string(), *mode);
+        }
+    }
+
+    #[test]
+    fn memory_writes_are_parsed() {
+        let write: MemoryWrite = "0x1FFF7800=0xFFEFF8AA".parse().unwrap();
+        assert_eq!(
+            write,
+            MemoryWrite {
+                address: 0x1FFF_7800,
+                value: 0xFFEF_F8AA,
+            }
+        );
+        assert_eq!(String::from(write).parse(), Ok(write));
+        assert_eq!(
+            "4096=1".parse(),
+            Ok(MemoryWrite {
+                address: 4096,
+                value: 1,
+            })
+        );
+        assert!("0x40022002=1".parse::<MemoryWrite>().is_err());
+        assert!("0x40022000".parse::<MemoryWrite>().is_err());
+        assert!("0x40022000=on".parse::<MemoryWrite>().is_err());
+    }
+
+    #[test]
+    fn bin_options_are_only_applied_to_binary_files() {
+        let mut general = Configs::try_new("default", &vec![]).unwrap().general;
+        general.format_base_address = Some(vec![0x0800_0000]);
+
+        // A `.bin` file with a base address is read as binary even though the format was left at ELF.
+        let format = general.image_format(0, Path::new("firmware.BIN"));
+        assert!(matches!(
+            format,
+            Format::Bin(BinOptions {
+                base_address: Some(0x0800_0000),
+                skip: 0,
+            })
+        ));
+        assert_eq!(general.ignored_bin_options(0, &format), None);
+
+        let format = general.image_format(0, Path::new("firmware.elf"));
+        assert!(matches!(format, Format::Elf));
+        assert_eq!(
+            general.ignored_bin_options(0, &format),
+            Some("--format.base-address only apply to binary files and are ignored as the file is read as Elf. Use `--format bin` to read it as a binary file.".into())
+        );
+        // The options are given per file, so the next file has none.
+        assert_eq!(general.ignored_bin_options(1, &format), None);
+    }
+
+    #[test]
+    fn flash_target_round_trips() {
+        let target: FlashTarget = "0483:374b:0671FF,STM32F407VGTx,app,v2.elf".parse().unwrap();
+        assert_eq!(target.chip, "STM32F407VGTx");
+        assert_eq!(target.file, "app,v2.elf");
+        assert_eq!(target.probe.vendor_id, 0x0483);
+
+        assert_eq!(String::from(target.clone()).parse(), Ok(target));
+        assert!("0483:374b,STM32F407VGTx".parse::<FlashTarget>().is_err());
+    }
+
+    #[test]
+    fn memory_range_is_parsed() {
+        assert_eq!(
+            "0x08000000:0x08010000".parse(),
+            Ok(MemoryRange {
+                start: 0x0800_0000,
+                end: 0x0801_0000,
+            })
+        );
+        assert!("0x1000:0x1000".parse::<MemoryRange>().is_err());
+        assert!("0x1000".parse::<MemoryRange>().is_err());
+    }
+
+    #[test]
+    fn verify_mode_accepts_switches() {
+        let mode: VerifyMode = serde_json::from_str("\"crc\"").unwrap();
+        assert_eq!(mode, VerifyMode::Crc);
+        let mode: VerifyMode = serde_json::from_str("true").unwrap();
+        assert_eq!(mode, VerifyMode::Full);
+        let mode: VerifyMode = serde_json::from_str("false").unwrap();
+        assert_eq!(mode, VerifyMode::None);
+        assert!(serde_json::from_str::<VerifyMode>("\"partial\"").is_err());
+    }
+
+    #[test]
+    fn level_prefix_is_parsed() {
+        assert_eq!(
+            "warn=[W] ".parse(),
+            Ok(LevelPrefix {
+                level: log::Level::Warn,
+                prefix: "[W] ".into(),
+            })
+        );
+        assert!("loud=LOUD".parse::<LevelPrefix>().is_err());
+        assert!("error=".parse::<LevelPrefix>().is_err());
+    }
+
+    #[test]
+    fn rtt_scan_region_is_parsed() {
+        assert_eq!("RAM".parse(), Ok(RttScanRegion::Ram));
+        assert_eq!("0x20000000".parse(), Ok(RttScanRegion::Exact(0x2000_0000)));
+        assert_eq!(
+            "0x20000000:0x20008000".parse(),
+            Ok(RttScanRegion::Range(0x2000_0000, 0x2000_8000))
+        );
+        assert!("flash".parse::<RttScanRegion>().is_err());
+        assert_eq!(
+            serde_yaml::from_str::<RttScanRegion>("Range: [4096, 8192]").unwrap(),
+            RttScanRegion::Range(0x1000, 0x2000)
+        );
+    }
+
+    #[test]
+    fn profile_features_are_merged() {
+        // Ensure the features of a profile are kept and extended by the ones given on the commandline.
+
+        let mut map = HashMap::new();
+        map.insert("default".to_string(), json!({ "features": [] }));
+        map.insert(
+            "board_a".to_string(),
+            json!({ "general": { "derives": "default" }, "features": ["board_a"] }),
+        );
+        let structopt = json!({ "general": { "derives": "board_a" }, "features": ["extra"] });
+
+        let mut s = config::Config::new();
+        Configs::apply("structopt", &mut s, &structopt, &map).unwrap();
+
+        assert_eq!(
+            s.get::<Vec<String>>("features").unwrap(),
+            vec!["board_a".to_string(), "extra".to_string()]
+        );
+    }
+
+    #[test]
+    fn cyclic_derives_are_rejected() {
+        // Ensure configs deriving from each other result in an error instead of endless recursion.
+
+        let mut map = HashMap::new();
+        map.insert("a".to_string(), json!({ "general": { "derives": "b" } }));
+        map.insert("b".to_string(), json!({ "general": { "derives": "a" } }));
+        let structopt = json!({ "general": { "derives": "a" } });
+
+        let mut s = config::Config::new();
+        let error = Configs::apply("structopt", &mut s, &structopt, &map).unwrap_err();
+
+        assert!(error.to_string().contains("a -> b -> a"));
+    }
+
+    #[test]
+    fn toml_config_is_loaded() {
+        // Ensure a project which only has a TOML config file gets its values.
+
+        let directory = std::env::temp_dir().join(format!("rover-toml-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("Rover.toml"),
+            "[board.general]\nchip = \"nRF52840\"\n",
+        )
+        .unwrap();
+
+        let mut s = config::Config::new();
+        let result = Configs::merge_files(&mut s, &directory);
+        std::fs::remove_dir_all(&directory).unwrap();
+        result.unwrap();
+
+        assert_eq!(s.get::<String>("board.general.chip").unwrap(), "nRF52840");
+    }
+
     #[test]
     fn create_config() {
         let config = Config {
+            config: None,
             general: General {
                 chip: None,
-                chip_descriptions: vec![],
-                log_level: log::Level::Info,
+                chip_descriptions: None,
+                log_level: Some(log::Level::Info),
                 derives: None,
-                connect_under_reset: false,
+                connect_under_reset: None,
+                connect_under_reset_assert_ms: None,
+                halt_after_connect: None,
+                attach_running: None,
+                crash_report_path: None,
                 file: None,
-                format: Format::Elf,
+                format: Some(Format::Elf),
+                format_uf2_family: None,
                 format_base_address: None,
                 format_skip: None,
                 work_dir: None,
@@ -543,10 +2691,27 @@ mod test {
                 restore_unwritten_bytes: None,
                 flash_layout_output_path: None,
                 do_chip_erase: None,
+                regions: None,
+                disable_watchdog: None,
+                restore_watchdog: None,
+                verify: None,
+                cycle_retries: None,
+                incremental: None,
+                flash_range: None,
+                targets: None,
+            },
+            reading: Reading {
+                enabled: None,
+                range: None,
+                output_path: None,
+                format: None,
             },
             reset: Reset {
                 enabled: Some(false),
                 halt_afterwards: None,
+                halt_timeout_ms: None,
+                run_to_main: None,
+                run_to_main_timeout_ms: None,
             },
             probe: Probe {
                 usb_vid: None,
@@ -555,32 +2720,72 @@ mod test {
                 protocol: None,
                 speed: None,
                 selector: None,
+                index: None,
+                open_retries: None,
+                open_retry_delay_ms: None,
+                target_power: None,
+                target_power_settle_ms: None,
             },
             gdb: Gdb {
                 enabled: None,
                 socket: None,
+                bind_address: None,
+                port: None,
             },
             logging: Logging {
-                channels: vec![Channel {
+                channels: Some(vec![Channel {
                     kinds: vec![ChannelKind::Itm { mode: ItmMode::Raw }],
                     link: LinkKind::Command("echo".into()),
-                }],
+                }]),
                 enabled: None,
+                continue_on_channel_error: None,
+                reset_afterwards: None,
+                itm_tpiu_clock: None,
+                defmt_raw_capture: None,
+                channel_buffer: None,
+                poll_interval_us: None,
+                command_restarts: None,
+                rtt_scan_region: None,
+                rtt_timeout_ms: None,
+                rtt_read_buffer: None,
+                colors: None,
+                level_prefixes: None,
+                timestamp_format: None,
             },
-            version: false,
-            list_chips: false,
-            list_probes: false,
-            disable_progressbars: false,
+            version: None,
+            dump_config: None,
+            list_chips: None,
+            list_chips_format: None,
+            version_format: None,
+            list_chips_filter: None,
+            list_channels: None,
+            list_probes: None,
+            disable_progressbars: None,
+            progress_format: None,
+            no_color: None,
+            error_format: None,
             bin: None,
             example: None,
             package: None,
-            release: false,
+            release: None,
             target: None,
             manifest_path: None,
-            no_default_features: false,
-            all_features: false,
-            features: vec![],
-            dry_run: false,
+            no_default_features: None,
+            all_features: None,
+            features: None,
+            dry_run: None,
+            fast: None,
+            watch: None,
+            reset_only: None,
+            show_eta: None,
+            no_build: None,
+            stats: None,
+            nrf_recover: None,
+            verify_only: None,
+            doctor: None,
+            pre_write: None,
+            ready_file: None,
+            export_image: None,
         };
 
         serde_yaml::to_writer(std::io::stdout(), &config).unwrap();
@@ -2,6 +2,7 @@
 use colored::*;
 use std::error::Error;
 use std::fmt::Write;
+use std::path::PathBuf;
 
 use bytesize::ByteSize;
 
@@ -13,10 +14,28 @@ use probe_rs::{
 };
 use probe_rs_cli_util::ArtifactError;
 
+use crate::{config::LinkKind, uf2::Uf2Error};
+
+/// All the errors rover can end with.
+///
+/// The process exits with a code depending on the kind of the error, see [`RoverError::exit_code`]:
+///
+/// | Code | Errors                                                     |
+/// |------|------------------------------------------------------------|
+/// | 1    | Any error not listed below                                 |
+/// | 2    | No probe was found or the probe could not be opened        |
+/// | 3    | The chip was not found or is ambiguous                     |
+/// | 4    | Flashing or verifying the target failed                    |
+/// | 5    | Building the firmware failed                               |
+/// | 6    | The target does not hold the firmware with `--verify-only` |
 #[derive(Debug, thiserror::Error)]
 pub enum RoverError {
     #[error("No connected probes were found.")]
     NoProbesFound,
+    #[error("Failed to load the config.")]
+    FailedToLoadConfig(anyhow::Error),
+    #[error("Failed to print the config.")]
+    FailedToDumpConfig(#[source] serde_yaml::Error),
     #[error("Failed to list the target descriptions.")]
     FailedToReadFamilies(#[source] RegistryError),
     #[error("Failed to open the ELF file '{path}' for flashing.")]
@@ -33,6 +52,10 @@ pub enum RoverError {
     FailedToParseCredentials,
     #[error("{} probes were found.", .list.len())]
     MultipleProbesFound { list: Vec<DebugProbeInfo> },
+    #[error("There is no probe with the index {index}. Only {count} probes are connected.")]
+    ProbeIndexOutOfRange { index: usize, count: usize },
+    #[error("No connected probe has the serial number '{serial}'.")]
+    NoProbeWithSerial { serial: String },
     #[error("The flashing procedure failed for '{path}'.")]
     FlashingFailed {
         #[source]
@@ -40,6 +63,8 @@ pub enum RoverError {
         target: Target,
         target_spec: Option<String>,
         path: String,
+        /// Whether programming had started, which leaves the flash partially written.
+        partially_written: bool,
     },
     #[error("Failed to parse the chip description '{path}'.")]
     FailedChipDescriptionParsing {
@@ -61,6 +86,13 @@ pub enum RoverError {
     },
     #[error("Failed to build the cargo project.")]
     FailedToBuildCargoProject(#[source] ArtifactError),
+    #[error("Failed to read the cargo metadata of the project.")]
+    FailedToReadCargoMetadata(anyhow::Error),
+    #[error("The prebuilt artifact '{name}' was not found.")]
+    PrebuiltArtifactNotFound {
+        name: String,
+        searched: Vec<PathBuf>,
+    },
     #[error("The chip '{name}' was not found in the database.")]
     ChipNotFound {
         #[source]
@@ -78,7 +110,13 @@ pub enum RoverError {
         #[source]
         source: DebugProbeError,
         speed: u32,
+        /// The supported speeds closest to the requested one, if they are known.
+        nearest: Vec<u32>,
     },
+    #[error("The chip '{chip}' cannot be recovered with `--nrf-recover`.")]
+    NrfRecoverUnsupported { chip: String },
+    #[error("Failed to recover the nRF chip through its CTRL-AP.")]
+    NrfRecoverFailed(#[source] probe_rs::Error),
     #[error("Connecting to the chip was unsuccessful.")]
     AttachingFailed {
         #[source]
@@ -91,14 +129,193 @@ pub enum RoverError {
     TargetResetFailed(#[source] probe_rs::Error),
     #[error("The target could not be reset and halted.")]
     TargetResetHaltFailed(#[source] probe_rs::Error),
+    #[error("The target could not be halted.")]
+    TargetHaltFailed(#[source] probe_rs::Error),
+    #[error("`general.attach_running` cannot be combined with `{option}`.")]
+    AttachRunningConflict { option: String },
+    #[error("`flashing.incremental` cannot be combined with `flashing.do_chip_erase`.")]
+    IncrementalChipErase,
+    #[error("The GDB stub stopped with an error.")]
+    GdbStubFailed(anyhow::Error),
+    #[error("The target could not be run to `main`.")]
+    RunToMainFailed(#[source] probe_rs::Error),
     #[error("No .defmt section was present in the ELF binary.")]
     NoDefmtSection,
     #[error("Parsing of the defmt data failed.")]
     DefmtParsing(anyhow::Error),
+    // The link is not printed as a whole, as it might hold a token.
+    #[error("Failed to start the {} logging link '{}'.", .link.name(), .link.target())]
+    FailedToStartUpdater {
+        #[source]
+        source: std::io::Error,
+        link: LinkKind,
+    },
+    #[error("Failed to load the TLS certificate '{cert}' or its key '{key}'.")]
+    FailedToLoadTlsIdentity {
+        #[source]
+        source: std::io::Error,
+        cert: String,
+        key: String,
+    },
+    #[error("Flashing failed on {} of the targets.", .failures.len())]
+    FailedToFlashTargets { failures: Vec<(String, RoverError)> },
+    #[error("RTT could not be initialized within {0} ms.")]
+    RttInitTimeout(u64),
+    #[error("The RTT {direction} channel '{name}' does not exist.")]
+    RttChannelNotFound {
+        name: String,
+        direction: String,
+        available: Vec<String>,
+    },
+    #[error("Failed to set up the SWO trace output.")]
+    FailedToSetupSwv(#[source] probe_rs::Error),
+    #[error("The chip '{name}' matches multiple possible targets.")]
+    AmbiguousChip {
+        name: String,
+        matching_chips: Vec<String>,
+    },
+    #[error("Failed to open the file '{path}' of the flash region '{name}'.")]
+    FailedToOpenRegionFile {
+        #[source]
+        source: std::io::Error,
+        name: String,
+        path: String,
+    },
+    #[error("The flash regions '{first}' and '{second}' overlap.")]
+    FlashRegionsOverlap { first: String, second: String },
+    #[error("The file of the flash region '{name}' does not fit into the region.")]
+    FlashRegionTooSmall {
+        name: String,
+        size: u32,
+        file_size: u64,
+    },
+    #[error("The flash region '{name}' does not lie within the flash of the target.")]
+    FlashRegionOutsideNvm {
+        name: String,
+        base: u32,
+        size: u32,
+        target: Target,
+    },
+    #[error("Failed to access the watchdog of the target.")]
+    WatchdogAccessFailed(#[source] probe_rs::Error),
+    #[error("Failed to parse '{path}' for verifying the flash contents.")]
+    FailedToParseVerifyData { path: String },
+    #[error("Failed to read back the flash contents for verification.")]
+    VerifyReadFailed(#[source] probe_rs::Error),
+    #[error("Failed to compute the CRC32 of the flash contents on the target.")]
+    CrcVerifyFailed(#[source] probe_rs::Error),
+    #[error("The flash contents at {address:#010x} do not match the flashed binary.")]
+    VerifyFailed {
+        address: u32,
+        expected: u8,
+        actual: u8,
+    },
+    #[error("The target does not hold the expected firmware. The flash contents differ at {address:#010x}.")]
+    FirmwareMismatch {
+        address: u32,
+        expected: u8,
+        actual: u8,
+    },
+    #[error("{failed} checks of the host environment failed.")]
+    DoctorChecksFailed { failed: usize },
+    #[error("Failed to create the defmt capture file '{path}'.")]
+    FailedToCreateDefmtCapture {
+        #[source]
+        source: std::io::Error,
+        path: String,
+    },
+    #[error("The chip '{chip}' does not support the protocol {protocol}.")]
+    UnsupportedProtocol {
+        chip: String,
+        protocol: WireProtocol,
+        supported: Vec<WireProtocol>,
+    },
+    #[error("Failed to parse the UF2 file '{path}'.")]
+    FailedToParseUf2 {
+        #[source]
+        source: Uf2Error,
+        path: String,
+    },
+    #[error("Failed to load the UF2 data into the flash loader.")]
+    FailedToLoadUf2Data(#[source] FlashError),
+    #[error("Failed to parse the ELF file '{path}'.")]
+    FailedToParseElf { path: String },
+    #[error("Failed to load the ELF segments within the flash range.")]
+    FailedToLoadElfSegments(#[source] FlashError),
+    #[error("The flash range {start:#010x}:{end:#010x} does not contain any loadable ELF data.")]
+    NothingInFlashRange { start: u32, end: u32 },
+    #[error("Reading the flash requires both a memory range and an output path.")]
+    IncompleteReadConfig,
+    #[error("Failed to read the memory of the target.")]
+    FailedToReadMemory(#[source] probe_rs::Error),
+    #[error("Failed to write the ready file '{path}'.")]
+    FailedToWriteReadyFile {
+        #[source]
+        source: std::io::Error,
+        path: String,
+    },
+    #[error("Failed to watch the project for changes.")]
+    FailedToWatch(#[source] notify::Error),
+    #[error("Failed to write the memory contents to '{path}'.")]
+    FailedToWriteReadOutput {
+        #[source]
+        source: std::io::Error,
+        path: String,
+    },
+    #[error("Failed to write {value:#010x} to {address:#010x} before flashing.")]
+    PreWriteFailed {
+        #[source]
+        source: probe_rs::Error,
+        address: u32,
+        value: u32,
+    },
+    #[error("Failed to export the image to '{path}'.")]
+    FailedToExportImage {
+        #[source]
+        source: std::io::Error,
+        path: String,
+    },
+    #[error("The binary exported to '{path}' would be {size} bytes large.")]
+    ExportedBinaryTooLarge { size: u64, path: String },
 }
 
-pub(crate) fn render_diagnostics(error: RoverError) {
-    let (errors_to_omit, hints) = match &error {
+impl RoverError {
+    /// The code the process exits with for this error.
+    /// Scripts can tell missing hardware from bad firmware with it.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RoverError::NoProbesFound
+            | RoverError::FailedToOpenProbe(_)
+            | RoverError::FailedToParseCredentials
+            | RoverError::MultipleProbesFound { .. }
+            | RoverError::ProbeIndexOutOfRange { .. }
+            | RoverError::NoProbeWithSerial { .. } => 2,
+            RoverError::ChipNotFound { .. }
+            | RoverError::AmbiguousChip { .. }
+            | RoverError::FailedChipDescriptionParsing { .. }
+            | RoverError::NrfRecoverUnsupported { .. } => 3,
+            RoverError::FlashingFailed { .. }
+            | RoverError::FailedToFlashTargets { .. }
+            | RoverError::FlashRegionsOverlap { .. }
+            | RoverError::FlashRegionTooSmall { .. }
+            | RoverError::FlashRegionOutsideNvm { .. }
+            | RoverError::NothingInFlashRange { .. }
+            | RoverError::VerifyFailed { .. }
+            | RoverError::VerifyReadFailed(_)
+            | RoverError::CrcVerifyFailed(_) => 4,
+            RoverError::FailedToBuildCargoProject(_)
+            | RoverError::FailedToBuildExternalCargoProject { .. }
+            | RoverError::FailedToReadCargoMetadata(_)
+            | RoverError::PrebuiltArtifactNotFound { .. } => 5,
+            RoverError::FirmwareMismatch { .. } => 6,
+            _ => 1,
+        }
+    }
+}
+
+/// Collects the messages of the error chain worth showing and the hints which help to resolve the error.
+pub(crate) fn diagnostics(error: &RoverError) -> (Vec<String>, Vec<String>) {
+    let (errors_to_omit, hints) = match error {
         RoverError::NoProbesFound => (
             0,
             vec![
@@ -107,6 +324,16 @@ pub(crate) fn render_diagnostics(error: RoverError) {
                 "For a guide on how to set up your probes, see https://probe.rs/guide/2_probes/.".into(),
             ],
         ),
+        RoverError::FailedToLoadConfig(e) => (
+            0,
+            vec![
+                format!("{:#}", e),
+            ],
+        ),
+        RoverError::FailedToDumpConfig(_e) => (
+            0,
+            vec![],
+        ),
         RoverError::FailedToReadFamilies(_e) => (
             0,
             vec![],
@@ -156,7 +383,7 @@ pub(crate) fn render_diagnostics(error: RoverError) {
         RoverError::MultipleProbesFound { list } => (
             0,
             vec![
-                "You can select a probe with the `--probe` argument. See `--help` for how to use it.".into(),
+                "You can select a probe with the `--probe.selector` argument or by its index below with `--probe.index`. See `--help` for how to use them.".into(),
                 format!("The following devices were found:\n \
                                         {} \
                                             \
@@ -168,13 +395,34 @@ pub(crate) fn render_diagnostics(error: RoverError) {
                                         list.iter().enumerate().map(|(num, link)| format!("[{}]: {:?}\n", num, link)).collect::<String>())
             ],
         ),
+        RoverError::ProbeIndexOutOfRange { index, count } => (
+            0,
+            vec![
+                format!("There is no probe with the index {}, as only {} probes are connected.", index, count),
+                "Use `--list-probes` to see the connected probes and their indices.".into(),
+            ],
+        ),
+        RoverError::NoProbeWithSerial { serial } => (
+            0,
+            vec![
+                format!("None of the connected probes reports the serial number '{}'.", serial),
+                "Use `--list-probes` to see the connected probes and their serial numbers.".into(),
+            ],
+        ),
         RoverError::FailedToParseCredentials => (
             0,
             vec![
                 "Make sure you specify the chip credentials in hex format.".into()
             ]
         ),
-        RoverError::FlashingFailed { source, target, target_spec, .. } => generate_flash_error_hints(source, target, target_spec),
+        RoverError::FlashingFailed { source, target, target_spec, partially_written, .. } => {
+            let (errors_to_omit, mut hints) = generate_flash_error_hints(source, target, target_spec);
+            if *partially_written {
+                hints.insert(0, "The flash is partially written, so the firmware on the target is broken. Flash it again or erase it before running it.".into());
+                hints.push("`--reset-only` does not fix this as it does not touch the flash contents.".into());
+            }
+            (errors_to_omit, hints)
+        }
         RoverError::FailedChipDescriptionParsing { .. } => (
             0,
             vec![],
@@ -235,16 +483,39 @@ pub(crate) fn render_diagnostics(error: RoverError) {
                 vec![],
             ),
         },
-        RoverError::ChipNotFound { source, .. } => match source {
-            RegistryError::ChipNotFound(_) => (
-                0,
-                vec![
+        RoverError::FailedToReadCargoMetadata(e) => (
+            0,
+            vec![
+                format!("{:#}", e),
+                "Make sure the working directory you selected is indeed a cargo project with a Cargo.toml in it.".into(),
+            ],
+        ),
+        RoverError::PrebuiltArtifactNotFound { searched, .. } => {
+            let mut hints = vec![
+                "Build the project with the same `--bin`, `--example`, `--release` and `--target` arguments first, or drop `--no-build`.".into(),
+            ];
+            hints.extend(
+                searched
+                    .iter()
+                    .map(|path| format!("Looked for {}", path.display())),
+            );
+            (0, hints)
+        }
+        RoverError::ChipNotFound { source, name } => match source {
+            RegistryError::ChipNotFound(_) => {
+                let mut hints = vec![];
+                let suggestions = similar_chips(name);
+                if !suggestions.is_empty() {
+                    hints.push(format!("Did you mean {}?", suggestions.join(" or ")));
+                }
+                hints.extend(vec![
                     "Did you spell the name of your chip correctly? Capitalization does not matter."
                         .into(),
                     "Maybe your chip is not supported yet. You could add it yourself with our tool here: https://github.com/probe-rs/target-gen.".into(),
                     "You can list all the available chips by passing the `--list-chips` argument.".into(),
-                ],
-            ),
+                ]);
+                (0, hints)
+            },
             _ => (
                 0,
                 vec![],
@@ -254,12 +525,34 @@ pub(crate) fn render_diagnostics(error: RoverError) {
             0,
             vec![],
         ),
-        RoverError::FailedToSelectProtocolSpeed { speed, .. } => (
+        RoverError::FailedToSelectProtocolSpeed { speed, nearest, .. } if nearest.is_empty() => (
             0,
             vec![
                 format!("Try specifying a speed lower than {} kHz", speed)
             ],
         ),
+        RoverError::FailedToSelectProtocolSpeed { nearest, .. } => (
+            0,
+            vec![
+                format!(
+                    "The probe does not support this speed. The nearest supported speeds are {}.",
+                    nearest.iter().map(|speed| format!("{} kHz", speed)).collect::<Vec<_>>().join(" and ")
+                ),
+            ],
+        ),
+        RoverError::NrfRecoverUnsupported { .. } => (
+            0,
+            vec![
+                "The recovery sequence only applies to the nRF52 family. Select such a chip with `--chip`.".into(),
+            ],
+        ),
+        RoverError::NrfRecoverFailed(_e) => (
+            0,
+            vec![
+                "Make sure the probe is connected to the SWD pins and the chip is powered.".into(),
+                "Try lowering the probe speed with `--probe.speed`.".into(),
+            ],
+        ),
         RoverError::AttachingFailed { source, connect_under_reset } => match source {
             ProbeRsError::ChipNotFound(RegistryError::ChipAutodetectFailed) => (
                 0,
@@ -294,6 +587,41 @@ pub(crate) fn render_diagnostics(error: RoverError) {
             0,
             vec![],
         ),
+        RoverError::TargetHaltFailed(_e) => (
+            0,
+            vec![],
+        ),
+        RoverError::AttachRunningConflict { option } => {
+            let mut hints = vec![
+                format!("`{}` disturbs the running target, which `general.attach_running` is meant to leave alone.", option),
+                "Remove one of the two options.".into(),
+            ];
+            if option == "flashing.verify: crc" {
+                hints.push("The CRC is computed by code run on the target. `--flashing.verify full` only reads the flash back.".into());
+            }
+            (0, hints)
+        }
+        RoverError::IncrementalChipErase => (
+            0,
+            vec![
+                "A chip erase also erases the unchanged sectors incremental flashing skips, which would leave the firmware incomplete.".into(),
+                "Remove one of the two options.".into(),
+            ],
+        ),
+        RoverError::GdbStubFailed(e) => (
+            0,
+            vec![
+                format!("{:#}", e),
+                "Make sure no other program is bound to the address of the GDB stub, or select another one with `--gdb.port`.".into(),
+            ],
+        ),
+        RoverError::RunToMainFailed(_e) => (
+            0,
+            vec![
+                "Setting the breakpoint at `main` needs a free hardware breakpoint.".into(),
+                "If `main` is reached later than `reset.run-to-main-timeout-ms` after the reset, increase the timeout.".into(),
+            ],
+        ),
         RoverError::NoDefmtSection => (
             0,
             vec![],
@@ -302,24 +630,290 @@ pub(crate) fn render_diagnostics(error: RoverError) {
             1,
             vec![],
         ),
+        RoverError::FailedToStartUpdater { source, link } => (
+            0,
+            match (source.kind(), link) {
+                (_, LinkKind::File(path)) => vec![
+                    format!("Make sure the directory of '{}' exists and is writable.", path),
+                    "Use `--logging.continue-on-channel-error true` to skip failing channels and keep logging on the others.".into(),
+                ],
+                (_, LinkKind::Serial { path, .. }) => vec![
+                    format!("Make sure the serial port '{}' exists and is not used by another program.", path),
+                    "Use `--logging.continue-on-channel-error true` to skip failing channels and keep logging on the others.".into(),
+                ],
+                (_, LinkKind::Terminal(_)) => vec![
+                    "The terminal view needs an interactive terminal. Use another link when the output of rover is redirected.".into(),
+                    "Use `--logging.continue-on-channel-error true` to skip failing channels and keep logging on the others.".into(),
+                ],
+                (std::io::ErrorKind::AddrInUse, _) => vec![
+                    "The address of this link is already in use. Make sure no other program (or another channel) is bound to it.".into(),
+                    "Use `--logging.continue-on-channel-error true` to skip failing channels and keep logging on the others.".into(),
+                ],
+                _ => vec![
+                    "Use `--logging.continue-on-channel-error true` to skip failing channels and keep logging on the others.".into(),
+                ],
+            },
+        ),
+        RoverError::FailedToLoadTlsIdentity { source, .. } => (
+            0,
+            match source.kind() {
+                std::io::ErrorKind::InvalidData => vec![
+                    "The certificate chain and the private key have to be PEM encoded, with the key in PKCS #8 format.".into(),
+                    "Convert other keys with `openssl pkcs8 -topk8 -nocrypt -in key.pem -out key.pkcs8.pem`.".into(),
+                ],
+                _ => vec![
+                    "Make sure the certificate and the key exist and are readable.".into(),
+                ],
+            },
+        ),
+        RoverError::FailedToFlashTargets { failures } => (
+            0,
+            failures
+                .iter()
+                .map(|(target, error)| {
+                    // The hints only show the messages, so the causes of each failure are appended to it.
+                    let mut message = format!("{}: {}", target, error);
+                    let mut source = error.source();
+                    while let Some(s) = source {
+                        message.push_str(&format!(": {}", s));
+                        source = s.source();
+                    }
+                    message
+                })
+                .collect(),
+        ),
+        RoverError::RttInitTimeout(timeout) => (
+            0,
+            vec![
+                format!("The RTT control block was not found within {} ms. Make sure your firmware initializes RTT right after startup.", timeout),
+                "Raise the limit with `--logging.rtt-timeout-ms` if the firmware needs longer to get there.".into(),
+            ],
+        ),
+        RoverError::RttChannelNotFound { name: _, direction, available } => (
+            0,
+            if available.is_empty() {
+                vec![format!("The firmware does not announce any RTT {} channels.", direction)]
+            } else {
+                vec![
+                    format!("The firmware announces the RTT {} channels {}.", direction, available.join(", ")),
+                    "Channels are matched by their exact name. Use the number of the channel for unnamed ones.".into(),
+                ]
+            },
+        ),
+        RoverError::FailedToSetupSwv(_e) => (
+            0,
+            vec![
+                "Make sure your probe supports SWO and the SWO pin of the target is connected to it.".into(),
+            ],
+        ),
+        RoverError::AmbiguousChip { name, matching_chips } => (
+            0,
+            vec![
+                format!(
+                    "The specified chip '{}' did match multiple possible targets. Try to specify your chip more exactly. The following possible targets were found:\n{}",
+                    name,
+                    matching_chips.iter().map(|chip| format!("\t{}\n", chip)).collect::<String>()
+                ),
+            ],
+        ),
+        RoverError::FailedToOpenRegionFile { source, name, path } => (
+            0,
+            match source.kind() {
+                std::io::ErrorKind::NotFound => vec![
+                    format!("Make sure the path '{}' of the flash region '{}' is the correct location of its binary.", path, name)
+                ],
+                _ => vec![]
+            },
+        ),
+        RoverError::FlashRegionsOverlap { first, second } => (
+            0,
+            vec![
+                format!("The flash regions '{}' and '{}' overlap. Make sure the base address and size of each region are correct.", first, second),
+            ],
+        ),
+        RoverError::FlashRegionTooSmall { name, size, file_size } => (
+            0,
+            vec![
+                format!(
+                    "The file of the flash region '{}' is {} large but the region only has {}. Increase the size of the region or make the binary smaller.",
+                    name,
+                    ByteSize(*file_size).to_string_as(true),
+                    ByteSize(*size as u64).to_string_as(true),
+                ),
+            ],
+        ),
+        RoverError::FlashRegionOutsideNvm { name, base, size, target } => (
+            0,
+            vec![
+                format!(
+                    "The flash region '{}' at {:#010x} - {:#010x} does not lie within the flash of the target.",
+                    name,
+                    base,
+                    *base as u64 + *size as u64,
+                ),
+                available_flash_regions(target),
+            ],
+        ),
+        RoverError::WatchdogAccessFailed(_e) => (
+            0,
+            vec![
+                "Try flashing without `flashing.disable_watchdog` and check whether the watchdog actually interferes.".into(),
+            ],
+        ),
+        RoverError::FailedToParseVerifyData { path } => (
+            0,
+            vec![
+                format!("The file '{}' could not be parsed to verify the flash contents. Try flashing without `flashing.verify`.", path),
+            ],
+        ),
+        RoverError::VerifyReadFailed(_e) => (
+            0,
+            vec![
+                "Try lowering the probe speed with `--probe.speed`.".into(),
+            ],
+        ),
+        RoverError::CrcVerifyFailed(_e) => (
+            0,
+            vec![
+                "Try `--flashing.verify full` to read back the flash contents instead.".into(),
+            ],
+        ),
+        RoverError::VerifyFailed { address, expected, actual } => (
+            0,
+            vec![
+                format!(
+                    "The byte at {:#010x} was expected to be {:#04x} but is {:#04x}. The flash contents do not match the binary after programming.",
+                    address, expected, actual
+                ),
+                "This can be caused by an unstable connection to the probe. Try lowering the probe speed with `--probe.speed` and flash again.".into(),
+            ],
+        ),
+        RoverError::FirmwareMismatch { address, expected, actual } => (
+            0,
+            vec![
+                format!(
+                    "The byte at {:#010x} was expected to be {:#04x} but is {:#04x}.",
+                    address, expected, actual
+                ),
+                "Flash the firmware to the target, or check that the right firmware and chip were selected.".into(),
+            ],
+        ),
+        RoverError::DoctorChecksFailed { .. } => (
+            0,
+            vec![
+                "The hints below each failed check explain how to resolve it.".into(),
+            ],
+        ),
+        RoverError::FailedToCreateDefmtCapture { source: _, path } => (
+            0,
+            vec![
+                format!("Make sure the directory of '{}' exists and is writable.", path),
+            ],
+        ),
+        RoverError::FailedToParseUf2 { source: _, path } => (
+            0,
+            vec![
+                format!("Make sure '{}' is a UF2 file or select the correct format with `--format`.", path),
+            ],
+        ),
+        RoverError::FailedToLoadUf2Data(_e) => (
+            0,
+            vec![
+                "Make sure the UF2 file was built for this chip. Use `--format.uf2-family` to only flash the blocks of a specific family.".into(),
+            ],
+        ),
+        RoverError::FailedToParseElf { path } => (
+            0,
+            vec![
+                format!("Make sure '{}' is a valid ELF file.", path),
+            ],
+        ),
+        RoverError::FailedToLoadElfSegments(_e) => (
+            0,
+            vec![
+                "Make sure the flash range given with `--flashing.flash-range` lies within the flash of the target.".into(),
+            ],
+        ),
+        RoverError::NothingInFlashRange { start, end } => (
+            0,
+            vec![
+                format!(
+                    "None of the loadable segments of the ELF file lie within {:#010x}:{:#010x}, so there is nothing to flash.",
+                    start, end
+                ),
+                "Check the addresses given with `--flashing.flash-range` against the memory layout of your firmware.".into(),
+            ],
+        ),
+        RoverError::IncompleteReadConfig => (
+            0,
+            vec![
+                "Reading the flash requires both a memory range and an output path.".into(),
+                "Set them with `--reading.range <start>:<end>` and `--reading.output-path <path>`.".into(),
+            ],
+        ),
+        RoverError::FailedToReadMemory(_e) => (
+            0,
+            vec![
+                "Make sure the range given with `--reading.range` lies within the memory of the target.".into(),
+            ],
+        ),
+        RoverError::FailedToWriteReadyFile { source: _, path } => (
+            0,
+            vec![
+                format!("Make sure the directory of '{}' exists and is writable.", path),
+            ],
+        ),
+        RoverError::FailedToWriteReadOutput { source: _, path } => (
+            0,
+            vec![
+                format!("Make sure the directory of '{}' exists and is writable.", path),
+            ],
+        ),
+        RoverError::PreWriteFailed { source: _, address, value: _ } => (
+            0,
+            vec![
+                format!("Make sure {:#010x} is writable on this chip. Option bytes usually have to be unlocked with writes to their key register first, which can be given as earlier `--pre-write`s.", address),
+                "The writes before a failed one were done, so the target might be in a partially configured state.".into(),
+            ],
+        ),
+        RoverError::FailedToExportImage { source: _, path } => (
+            0,
+            vec![
+                format!("Make sure the directory of '{}' exists and is writable.", path),
+            ],
+        ),
+        RoverError::ExportedBinaryTooLarge { .. } => (
+            0,
+            vec![
+                "A raw binary fills the gaps between the segments of the image, which are far apart in this image.".into(),
+                "Export it as Intel HEX by giving a path ending in `.hex`, which keeps the segments apart.".into(),
+            ],
+        ),
+        RoverError::UnsupportedProtocol { chip, protocol, supported } => (
+            0,
+            vec![
+                format!(
+                    "The chip '{}' cannot be debugged via {}. Select one of the supported protocols with `--probe.protocol`: {}",
+                    chip,
+                    protocol,
+                    supported.iter().map(|p| p.to_string()).collect::<Vec<_>>().join(", ")
+                ),
+            ],
+        ),
+        RoverError::FailedToWatch(_e) => (
+            0,
+            vec![
+                "On Linux, the number of watched directories might be limited by `fs.inotify.max_user_watches`.".into(),
+            ],
+        ),
     };
 
-    use std::io::Write;
-    let mut stderr = std::io::stderr();
+    let mut chain = vec![];
     let mut source = error.source();
     let mut i = 0;
     while let Some(s) = source {
         if hints.is_empty() || i >= errors_to_omit {
-            let string = format!("{}: {}", i, s);
-            write_with_offset(
-                &mut stderr,
-                if i == 0 {
-                    "Error".red().bold()
-                } else {
-                    "".red().bold()
-                },
-                &string,
-            );
+            chain.push(s.to_string());
         } else {
             log::debug!("{}: {}", i, s);
         }
@@ -327,6 +921,28 @@ pub(crate) fn render_diagnostics(error: RoverError) {
         source = s.source();
     }
 
+    (chain, hints)
+}
+
+/// Writes the error chain and the hints for the error to stderr for humans to read.
+pub(crate) fn render_diagnostics(error: RoverError) {
+    let (chain, hints) = diagnostics(&error);
+
+    use std::io::Write;
+    let mut stderr = std::io::stderr();
+    for (i, message) in chain.iter().enumerate() {
+        let string = format!("{}: {}", i, message);
+        write_with_offset(
+            &mut stderr,
+            if i == 0 {
+                "Error".red().bold()
+            } else {
+                "".red().bold()
+            },
+            &string,
+        );
+    }
+
     // if !hints.is_empty() {
     //     let _ = write_with_offset(&mut stderr, "Error".red().bold(), &selected_error);
     // };
@@ -341,6 +957,89 @@ pub(crate) fn render_diagnostics(error: RoverError) {
     let _ = stderr.flush();
 }
 
+/// Writes the error, its chain and the hints for the error to stderr as a single JSON object for tools to read.
+pub(crate) fn render_diagnostics_json(error: RoverError) {
+    let (chain, hints) = diagnostics(&error);
+    let diagnostics = serde_json::json!({
+        "error": error.to_string(),
+        "chain": chain,
+        "hints": hints,
+    });
+    eprintln!("{}", diagnostics);
+}
+
+/// Finds the names of the known chips which are closest to the given name, to suggest them for a misspelled chip.
+fn similar_chips(name: &str) -> Vec<String> {
+    let name = name.to_lowercase();
+    let families = match probe_rs::config::families() {
+        Ok(families) => families,
+        Err(_) => return vec![],
+    };
+
+    let mut candidates = families
+        .iter()
+        .flat_map(|family| family.variants().iter())
+        .map(|variant| {
+            (
+                edit_distance(&name, &variant.name.to_lowercase()),
+                variant.name.clone(),
+            )
+        })
+        // Names which differ in more than a third of their characters are not worth suggesting.
+        .filter(|(distance, _)| *distance <= (name.chars().count() / 3).max(1))
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(3)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// The Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Lists the flash regions of the given target.
+fn available_flash_regions(target: &Target) -> String {
+    let mut hint_available_regions = String::new();
+
+    let _ = writeln!(
+        hint_available_regions,
+        "The following flash memory is available for the chip '{}':",
+        target.name
+    );
+
+    for memory_region in &target.memory_map {
+        match memory_region {
+            MemoryRegion::Ram(_) => {}
+            MemoryRegion::Generic(_) => {}
+            MemoryRegion::Nvm(flash) => {
+                let _ = writeln!(
+                    hint_available_regions,
+                    "  {:#010x} - {:#010x} ({})",
+                    flash.range.start,
+                    flash.range.end,
+                    ByteSize((flash.range.end - flash.range.start) as u64).to_string_as(true)
+                );
+            }
+        }
+    }
+
+    hint_available_regions
+}
+
 fn generate_flash_error_hints(
     error: &FlashError,
     target: &Target,
@@ -366,33 +1065,8 @@ fn generate_flash_error_hints(
 
                 let mut hints = Vec::new();
 
-                let mut hint_available_regions = String::new();
-
                 // Show the available flash regions
-                let _ = writeln!(
-                    hint_available_regions,
-                    "The following flash memory is available for the chip '{}':",
-                    target.name
-                );
-
-                for memory_region in &target.memory_map {
-                    match memory_region {
-                        MemoryRegion::Ram(_) => {}
-                        MemoryRegion::Generic(_) => {}
-                        MemoryRegion::Nvm(flash) => {
-                            let _ = writeln!(
-                                hint_available_regions,
-                                "  {:#010x} - {:#010x} ({})",
-                                flash.range.start,
-                                flash.range.end,
-                                ByteSize((flash.range.end - flash.range.start) as u64)
-                                    .to_string_as(true)
-                            );
-                        }
-                    }
-                }
-
-                hints.push(hint_available_regions);
+                hints.push(available_flash_regions(target));
 
                 if let Some(target_spec) = target_spec {
                     // Check if the chip specification was unique
@@ -439,3 +1113,17 @@ fn write_with_offset(mut output: impl std::io::Write, header: ColoredString, msg
         let _ = writeln!(output, "            {}", line);
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::edit_distance;
+
+    #[test]
+    fn edit_distance_counts_edits() {
+        assert_eq!(edit_distance("stm32f407vgtx", "stm32f407vgtx"), 0);
+        assert_eq!(edit_distance("stm32f47vgtx", "stm32f407vgtx"), 1);
+        assert_eq!(edit_distance("stm32f407vgty", "stm32f407vgtx"), 1);
+        assert_eq!(edit_distance("nrf52840", "nrf52832"), 2);
+        assert_eq!(edit_distance("", "abc"), 3);
+    }
+}
@@ -0,0 +1,188 @@
+//! Checks of the host environment which point out why probes cannot be used before anything fails.
+
+use std::path::Path;
+
+use colored::*;
+use probe_rs::Probe;
+
+use crate::diagnostics::{diagnostics, RoverError};
+
+/// The directories udev reads its rules from.
+const UDEV_RULES_DIRS: &[&str] = &[
+    "/etc/udev/rules.d",
+    "/lib/udev/rules.d",
+    "/usr/lib/udev/rules.d",
+];
+
+/// The group the udev rules of probe-rs grant access to the probes.
+const PROBE_GROUP: &str = "plugdev";
+
+/// The outcome of a single check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Pass,
+    /// Something is likely to cause problems, but might be fine on this system.
+    Warn,
+    Fail,
+}
+
+/// A single check along with the hints which help to resolve it if it does not pass.
+struct Check {
+    name: String,
+    status: Status,
+    hints: Vec<String>,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, hints: Vec<String>) -> Self {
+        Check {
+            name: name.into(),
+            status,
+            hints,
+        }
+    }
+}
+
+/// Runs all checks and prints them as a checklist.
+/// Fails if any of the checks failed. Warnings alone do not fail.
+pub fn run_doctor() -> Result<(), RoverError> {
+    let mut checks = vec![check_probes()];
+    if cfg!(target_os = "linux") {
+        checks.push(check_udev_rules());
+        checks.push(check_group());
+    }
+    checks.push(check_targets());
+
+    for check in &checks {
+        let status = match check.status {
+            Status::Pass => "pass".green().bold(),
+            Status::Warn => "warn".yellow().bold(),
+            Status::Fail => "fail".red().bold(),
+        };
+        println!("    [{}] {}", status, check.name);
+        if check.status != Status::Pass {
+            for hint in &check.hints {
+                println!("           {} {}", "Hint".blue().bold(), hint);
+            }
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.status == Status::Fail)
+        .count();
+    if failed > 0 {
+        Err(RoverError::DoctorChecksFailed { failed })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_probes() -> Check {
+    let probes = Probe::list_all();
+    if probes.is_empty() {
+        let (_, hints) = diagnostics(&RoverError::NoProbesFound);
+        Check::new("No debug probes were found", Status::Fail, hints)
+    } else {
+        Check::new(
+            format!("{} debug probes were found", probes.len()),
+            Status::Pass,
+            vec![],
+        )
+    }
+}
+
+fn check_udev_rules() -> Check {
+    let dirs = UDEV_RULES_DIRS.iter().map(Path::new).collect::<Vec<_>>();
+    if udev_rules_installed(&dirs) {
+        Check::new(
+            "The udev rules of probe-rs are installed",
+            Status::Pass,
+            vec![],
+        )
+    } else {
+        let (_, hints) = diagnostics(&RoverError::NoProbesFound);
+        Check::new(
+            "The udev rules of probe-rs were not found",
+            Status::Warn,
+            hints.into_iter().take(1).collect(),
+        )
+    }
+}
+
+fn check_group() -> Check {
+    let groups = std::process::Command::new("id")
+        .arg("-Gn")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned());
+    match groups {
+        Ok(groups) if in_group(&groups, PROBE_GROUP) => Check::new(
+            format!("The user is in the '{}' group", PROBE_GROUP),
+            Status::Pass,
+            vec![],
+        ),
+        _ => Check::new(
+            format!("The user is not in the '{}' group", PROBE_GROUP),
+            Status::Warn,
+            vec![format!(
+                "The udev rules grant access to the members of '{0}'. Add yourself with `sudo usermod -a -G {0} $USER` and log in again, unless your rules grant access otherwise.",
+                PROBE_GROUP
+            )],
+        ),
+    }
+}
+
+fn check_targets() -> Check {
+    match probe_rs::config::families() {
+        Ok(families) => Check::new(
+            format!("The target database with {} families loads", families.len()),
+            Status::Pass,
+            vec![],
+        ),
+        Err(error) => Check::new(
+            format!("The target database failed to load: {}", error),
+            Status::Fail,
+            vec![],
+        ),
+    }
+}
+
+/// Whether any of the directories contains the udev rules of probe-rs.
+fn udev_rules_installed(dirs: &[&Path]) -> bool {
+    dirs.iter()
+        .filter_map(|dir| std::fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .any(|entry| {
+            let name = entry.file_name().to_string_lossy().to_lowercase();
+            name.contains("probe-rs") && name.ends_with(".rules")
+        })
+}
+
+/// Whether the output of `id -Gn` contains the group.
+fn in_group(groups: &str, group: &str) -> bool {
+    groups.split_whitespace().any(|g| g == group)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{in_group, udev_rules_installed};
+
+    #[test]
+    fn udev_rules_are_found() {
+        let dir = std::env::temp_dir().join(format!("rover-udev-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        assert!(!udev_rules_installed(&[dir.as_path()]));
+
+        std::fs::write(dir.join("69-probe-rs.rules"), "").unwrap();
+        let found = udev_rules_installed(&[dir.as_path()]);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(found);
+    }
+
+    #[test]
+    fn groups_are_matched_exactly() {
+        assert!(in_group("user adm plugdev sudo\n", "plugdev"));
+        assert!(!in_group("user plugdevs", "plugdev"));
+    }
+}
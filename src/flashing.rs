@@ -1,49 +1,275 @@
-use std::{fs::File, path::Path, sync::Arc};
+use std::{
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
 
+use bytesize::ByteSize;
+use colored::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use probe_rs::{
-    flashing::{FlashLoader, FlashProgress, Format},
-    Session, Target,
+    config::MemoryRegion,
+    flashing::{BinOptions, FlashError, FlashLayout, FlashLoader, FlashProgress, ProgressEvent},
+    MemoryInterface, Session, Target,
 };
 use probe_rs_cli_util::logging;
+use serde::Serialize;
+
+use crate::{
+    config::{
+        Config, FlashRegion, Format, MemoryRange, MemoryWrite, ProgressFormat, ReadFormat,
+        VerifyMode,
+    },
+    diagnostics::RoverError,
+    incremental, uf2, verify, watchdog,
+};
+
+/// The number of bytes read from the target at once when reading back the flash.
+const READ_CHUNK_SIZE: usize = 1024;
+
+/// The largest raw binary which is exported.
+/// Images with segments far apart, e.g. in the flash and the option bytes, would fill gigabytes with `0xFF`.
+const MAX_BINARY_SIZE: u64 = 64 * 1024 * 1024;
 
-use crate::{config::Config, diagnostics::RoverError};
+/// Performs the flash download and repeats the whole cycle up to `flashing.cycle_retries` times if it fails.
+/// The loader is rebuilt for every retry.
+/// If `regions` are given, they are flashed instead of the images.
+pub fn run_flash_cycles(
+    session: &mut Session,
+    images: &[(PathBuf, Format)],
+    regions: &[FlashRegion],
+    config: &Config,
+    mut loader: Option<FlashLoader>,
+    progress_bars: bool,
+    speed_khz: u32,
+) -> Result<(), RoverError> {
+    // A chip erase wipes the sectors incremental flashing skips, which would leave only the changed sectors behind.
+    if config.flashing().incremental() && config.flashing().do_chip_erase() {
+        return Err(RoverError::IncrementalChipErase);
+    }
+
+    let retries = config.flashing().cycle_retries();
+    let mut attempt = 0;
+    loop {
+        match run_flash_download(
+            session,
+            images,
+            regions,
+            config,
+            loader.take(),
+            progress_bars,
+            speed_khz,
+        ) {
+            Ok(()) => {
+                if attempt > 0 {
+                    log::info!("Flashing succeeded after {} retries.", attempt);
+                }
+                return Ok(());
+            }
+            Err(error) if attempt < retries => {
+                attempt += 1;
+                log::warn!(
+                    "Flashing failed: {}. Retrying the whole flash cycle ({}/{}).",
+                    error,
+                    attempt,
+                    retries
+                );
+                std::thread::sleep(Duration::from_millis(500));
+            }
+            Err(error) => {
+                if attempt > 0 {
+                    log::error!("Flashing failed after {} retries.", attempt);
+                }
+                return Err(error);
+            }
+        }
+    }
+}
 
 /// Performs the flash download with the given loader. Ensure that the loader has the data to load already stored.
 /// This function also manages the update and display of progress bars.
+/// Progress bars are only drawn if `progress_bars` is set, as concurrent downloads would draw over each other.
+/// The flash time is estimated from `speed_khz`, the speed of the probe, with `--show-eta`.
+/// A given loader is flashed as it is, so incremental flashing only applies when no loader is given.
+/// The watchdog is kept from resetting the target with `flashing.disable_watchdog` and restored however flashing ends.
 pub fn run_flash_download(
     session: &mut Session,
-    path: &Path,
-    format: &Format,
+    images: &[(PathBuf, Format)],
+    regions: &[FlashRegion],
     config: &Config,
     loader: Option<FlashLoader>,
+    progress_bars: bool,
+    speed_khz: u32,
 ) -> Result<(), RoverError> {
-    let mut buffer = Vec::new();
+    // Keep the watchdog from resetting the target in the middle of flashing.
+    let saved_watchdog = if config.flashing().disable_watchdog() && !config.dry_run() {
+        watchdog::disable(session)?
+    } else {
+        None
+    };
 
-    // Add data from the ELF.
-    let mut file = File::open(&path).map_err(|error| RoverError::FailedToOpenElf {
-        source: error,
-        path: format!("{}", path.display()),
-    })?;
+    let result = flash_download(
+        session,
+        images,
+        regions,
+        config,
+        loader,
+        progress_bars,
+        speed_khz,
+    );
+
+    let restored = match saved_watchdog {
+        Some(saved_watchdog) if config.flashing().restore_watchdog() => {
+            watchdog::restore(session, saved_watchdog)
+        }
+        _ => Ok(()),
+    };
+    // A failure of the flashing itself is reported over one of restoring the watchdog.
+    result.and(restored)
+}
 
-    let mut loader = match loader {
-        Some(loader) => loader,
-        None => build_flashloader(
+/// Performs the flash download of `run_flash_download` while the watchdog is taken care of.
+fn flash_download(
+    session: &mut Session,
+    images: &[(PathBuf, Format)],
+    regions: &[FlashRegion],
+    config: &Config,
+    loader: Option<FlashLoader>,
+    progress_bars: bool,
+    speed_khz: u32,
+) -> Result<(), RoverError> {
+    // If flash regions are given, they are flashed instead of the given images.
+    let region_images = regions.iter().map(region_image).collect::<Vec<_>>();
+    let images = if regions.is_empty() {
+        images
+    } else {
+        &region_images[..]
+    };
+    let paths = images
+        .iter()
+        .map(|(path, _)| format!("{}", path.display()))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    // In incremental mode, only the sectors whose flash contents differ from the new data are flashed.
+    // The flash contents are compared before the loader is built, so the loader only gets the changed data.
+    let compare_instant = Instant::now();
+    let mut changes = if loader.is_none() && config.flashing().incremental() && !config.dry_run() {
+        // The regions are still checked even though their files are read as segments.
+        if !regions.is_empty() {
+            open_regions(session.target(), regions)?;
+            if !config.fast() {
+                print_region_layout(regions);
+            }
+        }
+        let segments =
+            verify::collect_segments(session.target(), images, config.flashing().flash_range())?;
+        Some(incremental::changed_segments(session, segments)?)
+    } else {
+        None
+    };
+    let compare_time = compare_instant.elapsed();
+    let flashed_bytes = changes.as_ref().map(|changes| {
+        changes
+            .segments
+            .iter()
+            .map(|segment| segment.data.len())
+            .sum::<usize>()
+    });
+    if let Some(changes) = &changes {
+        logging::println(format!(
+            "    {} {} of {} sectors which are unchanged ({})",
+            "Skipping".green().bold(),
+            changes.skipped_sectors,
+            changes.skipped_sectors + changes.changed_sectors,
+            ByteSize(changes.skipped_bytes as u64).to_string_as(true)
+        ));
+        if changes.changed_sectors == 0 {
+            return Ok(());
+        }
+    }
+
+    // The files and buffers have to outlive the loader.
+    let mut files;
+    let mut buffers;
+    let mut changed_data;
+
+    let mut loader = match (&mut changes, loader) {
+        (_, Some(loader)) => loader,
+        (Some(changes), None) => {
+            changed_data = vec![];
+            let target = session.target();
+            let mut loader = FlashLoader::new(
+                target.memory_map.to_vec(),
+                config.flashing().restore_unwritten_bytes(),
+                target.source().clone(),
+            );
+            let segments = std::mem::take(&mut changes.segments)
+                .into_iter()
+                .map(|segment| (segment.address, segment.data))
+                .collect();
+            add_segments(&mut loader, &mut changed_data, segments)
+                .map_err(RoverError::FailedToLoadElfSegments)?;
+            loader
+        }
+        (None, None) => {
+            files = if regions.is_empty() {
+                open_images(images)?
+            } else {
+                let files = open_regions(session.target(), regions)?;
+                if !config.fast() {
+                    print_region_layout(regions);
+                }
+                files
+            };
+            buffers = vec![Vec::new(); images.len()];
+            build_flashloader(
+                session.target(),
+                images,
+                &mut files,
+                &mut buffers,
+                config.flashing().restore_unwritten_bytes(),
+                config.flashing().flash_range(),
+            )?
+        }
+    };
+
+    // Collect the data to verify against before flashing so unreadable files are reported early.
+    let verify_segments = if config.flashing().verify() != VerifyMode::None && !config.dry_run() {
+        Some(verify::collect_segments(
             session.target(),
-            &mut file,
-            format,
-            &mut buffer,
-            config.flashing().restore_unwritten_bytes(),
-        )?,
+            images,
+            config.flashing().flash_range(),
+        )?)
+    } else {
+        None
     };
 
-    if !config.disable_progressbars() {
+    // The estimate is made by the progress callback as soon as the flash layout is known.
+    let estimate = Arc::new(Mutex::new(None));
+    let show_eta = config.show_eta();
+
+    // A failure after programming started leaves the flash partially written, which is reported along with the error.
+    let programming_started = Arc::new(AtomicBool::new(false));
+    let stats = Arc::new(Mutex::new(FlashStats::default()));
+
+    let commit = CommitOptions::new(config);
+    let instant = Instant::now();
+    let flash_time;
+
+    if progress_bars
+        && config.progress_format() == ProgressFormat::Bars
+        && !config.disable_progressbars()
+        && !config.fast()
+    {
         // Create progress bars.
         let multi_progress = MultiProgress::new();
-        let style = ProgressStyle::default_bar()
-                    .tick_chars("⠁⠁⠉⠙⠚⠒⠂⠂⠒⠲⠴⠤⠄⠄⠤⠠⠠⠤⠦⠖⠒⠐⠐⠒⠓⠋⠉⠈⠈✔")
-                    .progress_chars("##-")
-                    .template("{msg:.green.bold} {spinner} [{elapsed_precise}] [{wide_bar}] {bytes:>8}/{total_bytes:>8} @ {bytes_per_sec:>10} (eta {eta:3})");
+        let style = progress_style();
 
         // Create a new progress bar for the fill progress if filling is enabled.
         let fill_progress = if config.flashing().restore_unwritten_bytes() {
@@ -61,17 +287,38 @@ pub fn run_flash_download(
             logging::set_progress_bar(erase_progress.clone());
         }
         erase_progress.set_style(style.clone());
-        erase_progress.set_message("     Erasing sectors");
+        erase_progress.set_message(if config.flashing().do_chip_erase() {
+            "     Chip erasing   "
+        } else {
+            "     Erasing sectors"
+        });
 
         // Create a new progress bar for the program progress.
         let program_progress = multi_progress.add(ProgressBar::new(0));
-        program_progress.set_style(style);
+        program_progress.set_style(style.clone());
         program_progress.set_message(" Programming pages  ");
 
+        // Create a new progress bar for the verify progress if verifying is enabled.
+        // Its length is known up front, so it lines up with the other bars while they are running.
+        let verify_progress = if let Some(segments) = &verify_segments {
+            let total_size = segments.iter().map(|s| s.data.len() as u64).sum();
+            let verify_progress = multi_progress.add(ProgressBar::new(total_size));
+            verify_progress.set_style(style);
+            verify_progress.set_message("     Verifying      ");
+            Some(verify_progress)
+        } else {
+            None
+        };
+
         // Register callback to update the progress.
         let flash_layout_output_path = config.flashing().flash_layout_output_path().clone();
+        let progress_estimate = estimate.clone();
+        let progress_programming_started = programming_started.clone();
+        let progress_stats = stats.clone();
         let progress = FlashProgress::new(move |event| {
             use crate::ProgressEvent::*;
+            track_programming(&event, &progress_programming_started);
+            progress_stats.lock().unwrap().record(&event);
             match event {
                 Initialized { flash_layout } => {
                     let total_page_size: u32 = flash_layout.pages().iter().map(|s| s.size()).sum();
@@ -86,10 +333,12 @@ pub fn run_flash_download(
                     }
                     erase_progress.set_length(total_sector_size as u64);
                     program_progress.set_length(total_page_size as u64);
-                    let visualizer = flash_layout.visualize();
-                    flash_layout_output_path
-                        .as_ref()
-                        .map(|path| visualizer.write_svg(path));
+                    if let Some(path) = &flash_layout_output_path {
+                        write_flash_layout(&flash_layout, path);
+                    }
+                    if show_eta {
+                        announce_estimate(&flash_layout, speed_khz, &progress_estimate);
+                    }
                 }
                 StartedProgramming => {
                     program_progress.enable_steady_tick(100);
@@ -150,43 +399,673 @@ pub fn run_flash_download(
         });
 
         loader
-            .commit(session, &progress, false, config.dry_run())
-            .map_err(|error| RoverError::FlashingFailed {
-                source: error,
-                target: session.target().clone(),
-                target_spec: config.general().chip().clone(),
-                path: format!("{}", path.display()),
+            .commit(session, &progress, commit.do_chip_erase, commit.dry_run)
+            .map_err(|error| {
+                // Nothing is verified after a failure, so the verify bar must not wait for it.
+                if let Some(verify_progress) = &verify_progress {
+                    verify_progress.abandon();
+                }
+                RoverError::FlashingFailed {
+                    source: error,
+                    target: session.target().clone(),
+                    target_spec: config.general().chip().clone(),
+                    path: paths.clone(),
+                    partially_written: programming_started.load(Ordering::Relaxed),
+                }
             })?;
 
+        flash_time = instant.elapsed();
+
+        if let Some(segments) = &verify_segments {
+            verify_flash(session, config, segments, verify_progress.as_ref())?;
+        }
+
         // We don't care if we cannot join this thread.
         let _ = progress_thread_handle.join();
     } else {
+        let progress = match config.progress_format() {
+            ProgressFormat::Json => {
+                let flash_layout_output_path = config.flashing().flash_layout_output_path().clone();
+                let progress_programming_started = programming_started.clone();
+                let progress_stats = stats.clone();
+                FlashProgress::new(move |event| {
+                    track_programming(&event, &progress_programming_started);
+                    progress_stats.lock().unwrap().record(&event);
+                    if let ProgressEvent::Initialized { flash_layout } = &event {
+                        if let Some(path) = &flash_layout_output_path {
+                            write_flash_layout(flash_layout, path);
+                        }
+                    }
+                    // This unwrap is fine as the event only contains plain values.
+                    println!(
+                        "{}",
+                        serde_json::to_string(&JsonProgressEvent::from(event)).unwrap()
+                    );
+                })
+            }
+            ProgressFormat::Bars => {
+                let progress_estimate = estimate.clone();
+                let progress_programming_started = programming_started.clone();
+                let progress_stats = stats.clone();
+                FlashProgress::new(move |event| {
+                    track_programming(&event, &progress_programming_started);
+                    progress_stats.lock().unwrap().record(&event);
+                    if let ProgressEvent::Initialized { flash_layout } = &event {
+                        if show_eta {
+                            announce_estimate(flash_layout, speed_khz, &progress_estimate);
+                        }
+                    }
+                })
+            }
+        };
+
         loader
-            .commit(
-                session,
-                &FlashProgress::new(|_| {}),
-                false,
-                config.dry_run(),
-            )
+            .commit(session, &progress, commit.do_chip_erase, commit.dry_run)
             .map_err(|error| RoverError::FlashingFailed {
                 source: error,
                 target: session.target().clone(),
                 target_spec: config.general().chip().clone(),
-                path: format!("{}", path.display()),
+                path: paths.clone(),
+                partially_written: programming_started.load(Ordering::Relaxed),
             })?;
+
+        flash_time = instant.elapsed();
+
+        if let Some(segments) = &verify_segments {
+            verify_flash(session, config, segments, None)?;
+        }
+    }
+
+    if config.stats() {
+        logging::println(stats.lock().unwrap().summary());
+    }
+
+    if let Some(estimate) = *estimate.lock().unwrap() {
+        log::info!(
+            "Flashing took {:.1}s, the estimate was {:.1}s ({:+.1}s).",
+            flash_time.as_secs_f32(),
+            estimate.as_secs_f32(),
+            flash_time.as_secs_f32() - estimate.as_secs_f32()
+        );
+    }
+
+    // Assume that the skipped data would have taken as long per byte as the flashed data.
+    // Reading back the flash to find the unchanged sectors is part of the cost, so it is subtracted.
+    if let (Some(changes), Some(flashed_bytes)) = (&changes, flashed_bytes) {
+        if flashed_bytes > 0 {
+            let saved = flash_time.as_secs_f32() * changes.skipped_bytes as f32
+                / flashed_bytes as f32
+                - compare_time.as_secs_f32();
+            if saved > 0.0 {
+                logging::println(format!(
+                    "    {} about {:.1}s by skipping unchanged sectors",
+                    "Saved".green().bold(),
+                    saved
+                ));
+            } else {
+                log::info!(
+                    "Reading back the flash took {:.1}s longer than flashing the unchanged sectors would have.",
+                    -saved
+                );
+            }
+        }
     }
 
     Ok(())
 }
 
-/// Builds a new flash loader for the given target and ELF.
-/// This will check the ELF for validity and check what pages have to be flashed etc.
+/// The number of bits transferred over the wire per byte of data, including the protocol overhead.
+/// Each 32 bit word takes a full SWD transfer of about 46 bits, and the commands around the data add about as much again.
+const BITS_PER_BYTE: u64 = 24;
+
+/// The time it takes to erase a KiB of flash. Erase times differ a lot between chips, so this is only a rough guess.
+const ERASE_TIME_PER_KIB: Duration = Duration::from_millis(10);
+
+/// Estimates how long flashing the given amounts of data takes.
+/// Erasing takes a fixed time per KiB while the programmed and filled data is limited by the probe speed.
+fn estimate_flash_time(
+    total_sector_size: u32,
+    total_page_size: u32,
+    total_fill_size: u32,
+    speed_khz: u32,
+) -> Duration {
+    let erase = ERASE_TIME_PER_KIB * (total_sector_size / 1024);
+    let transferred_bits = (total_page_size as u64 + total_fill_size as u64) * BITS_PER_BYTE;
+    let transfer = Duration::from_millis(transferred_bits / speed_khz.max(1) as u64);
+    erase + transfer
+}
+
+/// The amount of data erased and programmed and how long it took.
+#[derive(Debug, Default)]
+struct FlashStats {
+    erased_bytes: u64,
+    erase_started: Option<Instant>,
+    erase_time: Duration,
+    programmed_bytes: u64,
+    program_started: Option<Instant>,
+    program_time: Duration,
+}
+
+impl FlashStats {
+    /// Accounts for a progress event of the flash loader.
+    /// The times span from the start to the end of each phase, so they include the overhead between the sectors and pages.
+    fn record(&mut self, event: &ProgressEvent) {
+        match event {
+            ProgressEvent::StartedErasing => self.erase_started = Some(Instant::now()),
+            ProgressEvent::SectorErased { size, .. } => self.erased_bytes += *size as u64,
+            ProgressEvent::FinishedErasing | ProgressEvent::FailedErasing => {
+                if let Some(started) = self.erase_started.take() {
+                    self.erase_time += started.elapsed();
+                }
+            }
+            ProgressEvent::StartedProgramming => self.program_started = Some(Instant::now()),
+            ProgressEvent::PageProgrammed { size, .. } => self.programmed_bytes += *size as u64,
+            ProgressEvent::FinishedProgramming | ProgressEvent::FailedProgramming => {
+                if let Some(started) = self.program_started.take() {
+                    self.program_time += started.elapsed();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Formats the statistics as a table with one row per phase.
+    fn summary(&self) -> String {
+        format!(
+            "    {}\n{}\n{}",
+            "Statistics".green().bold(),
+            stats_row("Erased", self.erased_bytes, self.erase_time),
+            stats_row("Programmed", self.programmed_bytes, self.program_time)
+        )
+    }
+}
+
+/// Formats the amount of data of a flash phase, its duration and its throughput.
+fn stats_row(phase: &str, bytes: u64, time: Duration) -> String {
+    let throughput = if time > Duration::from_secs(0) {
+        format!(
+            "{}/s",
+            ByteSize((bytes as f64 / time.as_secs_f64()) as u64).to_string_as(true)
+        )
+    } else {
+        "-".to_string()
+    };
+    format!(
+        "      {:<12}{:>12} in {:>6.2}s ({})",
+        phase,
+        ByteSize(bytes).to_string_as(true),
+        time.as_secs_f32(),
+        throughput
+    )
+}
+
+/// Remembers whether the flash loader started programming pages.
+fn track_programming(event: &ProgressEvent, started: &AtomicBool) {
+    if matches!(
+        event,
+        ProgressEvent::StartedProgramming | ProgressEvent::PageProgrammed { .. }
+    ) {
+        started.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Prints the estimated flash time for the layout and stores it to compare it with the actual time afterwards.
+fn announce_estimate(
+    flash_layout: &FlashLayout,
+    speed_khz: u32,
+    estimate: &Mutex<Option<Duration>>,
+) {
+    let eta = estimate_flash_time(
+        flash_layout.sectors().iter().map(|s| s.size()).sum(),
+        flash_layout.pages().iter().map(|s| s.size()).sum(),
+        flash_layout.fills().iter().map(|s| s.size()).sum(),
+        speed_khz,
+    );
+    logging::println(format!(
+        "    {} time ~{}s",
+        "Estimated".green().bold(),
+        eta.as_secs_f32().ceil()
+    ));
+    *estimate.lock().unwrap() = Some(eta);
+}
+
+/// A flash progress event as it is printed with `--progress-format json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum JsonProgressEvent {
+    Initialized {
+        total_page_size: u32,
+        total_sector_size: u32,
+        total_fill_size: u32,
+    },
+    StartedErasing,
+    StartedProgramming,
+    StartedFilling,
+    PageProgrammed {
+        size: u32,
+        time_ms: u64,
+    },
+    SectorErased {
+        size: u32,
+        time_ms: u64,
+    },
+    PageFilled {
+        size: u32,
+        time_ms: u64,
+    },
+    FailedErasing,
+    FinishedErasing,
+    FailedProgramming,
+    FinishedProgramming,
+    FailedFilling,
+    FinishedFilling,
+}
+
+impl From<ProgressEvent> for JsonProgressEvent {
+    fn from(event: ProgressEvent) -> Self {
+        match event {
+            ProgressEvent::Initialized { flash_layout } => JsonProgressEvent::Initialized {
+                total_page_size: flash_layout.pages().iter().map(|s| s.size()).sum(),
+                total_sector_size: flash_layout.sectors().iter().map(|s| s.size()).sum(),
+                total_fill_size: flash_layout.fills().iter().map(|s| s.size()).sum(),
+            },
+            ProgressEvent::StartedErasing => JsonProgressEvent::StartedErasing,
+            ProgressEvent::StartedProgramming => JsonProgressEvent::StartedProgramming,
+            ProgressEvent::StartedFilling => JsonProgressEvent::StartedFilling,
+            ProgressEvent::PageProgrammed { size, time } => JsonProgressEvent::PageProgrammed {
+                size,
+                time_ms: time as u64,
+            },
+            ProgressEvent::SectorErased { size, time } => JsonProgressEvent::SectorErased {
+                size,
+                time_ms: time as u64,
+            },
+            ProgressEvent::PageFilled { size, time } => JsonProgressEvent::PageFilled {
+                size,
+                time_ms: time as u64,
+            },
+            ProgressEvent::FailedErasing => JsonProgressEvent::FailedErasing,
+            ProgressEvent::FinishedErasing => JsonProgressEvent::FinishedErasing,
+            ProgressEvent::FailedProgramming => JsonProgressEvent::FailedProgramming,
+            ProgressEvent::FinishedProgramming => JsonProgressEvent::FinishedProgramming,
+            ProgressEvent::FailedFilling => JsonProgressEvent::FailedFilling,
+            ProgressEvent::FinishedFilling => JsonProgressEvent::FinishedFilling,
+        }
+    }
+}
+
+/// Writes the flash layout to the given path.
+/// Paths ending in `.txt` get a list of the address ranges, which is printed to stdout for `-`.
+/// All other paths get an SVG visualization.
+fn write_flash_layout(flash_layout: &FlashLayout, path: &str) {
+    let result = if path == "-" {
+        print!("{}", flash_layout_text(flash_layout));
+        Ok(())
+    } else if Path::new(path)
+        .extension()
+        .map_or(false, |extension| extension == "txt")
+    {
+        fs::write(path, flash_layout_text(flash_layout))
+    } else {
+        flash_layout.visualize().write_svg(path)
+    };
+
+    if let Err(error) = result {
+        log::warn!("Failed to write the flash layout to '{}': {}", path, error);
+    }
+}
+
+/// Lists the address ranges of the sectors, pages and fills of the flash layout.
+fn flash_layout_text(flash_layout: &FlashLayout) -> String {
+    let mut text = String::new();
+    text += &layout_blocks(
+        "Sectors",
+        flash_layout
+            .sectors()
+            .iter()
+            .map(|s| (s.address(), s.size())),
+    );
+    text += &layout_blocks(
+        "Pages",
+        flash_layout.pages().iter().map(|p| (p.address(), p.size())),
+    );
+    text += &layout_blocks(
+        "Fills",
+        flash_layout.fills().iter().map(|f| (f.address(), f.size())),
+    );
+    text
+}
+
+/// Lists blocks given as address and size under a heading, one block per line.
+fn layout_blocks(heading: &str, blocks: impl Iterator<Item = (u32, u32)>) -> String {
+    let mut text = format!("{}:\n", heading);
+    for (address, size) in blocks {
+        text += &format!(
+            "  {:#010x}..{:#010x} ({})\n",
+            address,
+            address as u64 + size as u64,
+            ByteSize(size as u64).to_string_as(true)
+        );
+    }
+    text
+}
+
+/// Checks the flash contents against the segments in the configured verify mode.
+fn verify_flash(
+    session: &mut Session,
+    config: &Config,
+    segments: &[verify::Segment],
+    progress: Option<&ProgressBar>,
+) -> Result<(), RoverError> {
+    match config.flashing().verify() {
+        VerifyMode::None => Ok(()),
+        VerifyMode::Crc => verify::verify_crc(session, segments, progress),
+        VerifyMode::Full => verify::verify(session, segments, progress),
+    }
+}
+
+/// Compares the flash contents of the target with the images without erasing or programming anything.
+/// The flash contents are read back, unless a CRC32 check is configured with `flashing.verify`.
+pub fn run_verify_only(
+    session: &mut Session,
+    images: &[(PathBuf, Format)],
+    config: &Config,
+) -> Result<(), RoverError> {
+    // If flash regions are configured, they are checked instead of the given images.
+    let regions = config.flashing().regions();
+    let region_images = regions.iter().map(region_image).collect::<Vec<_>>();
+    let images = if regions.is_empty() {
+        images
+    } else {
+        open_regions(session.target(), regions)?;
+        &region_images[..]
+    };
+
+    let segments =
+        verify::collect_segments(session.target(), images, config.flashing().flash_range())?;
+    let result = match config.flashing().verify() {
+        VerifyMode::Crc => verify::verify_crc(session, &segments, None),
+        VerifyMode::None | VerifyMode::Full => verify::verify(session, &segments, None),
+    };
+
+    match result {
+        Ok(()) => {
+            logging::println(format!(
+                "    {} {} matches the flash contents",
+                "Verified".green().bold(),
+                images
+                    .iter()
+                    .map(|(path, _)| format!("{}", path.display()))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ));
+            Ok(())
+        }
+        Err(RoverError::VerifyFailed {
+            address,
+            expected,
+            actual,
+        }) => Err(RoverError::FirmwareMismatch {
+            address,
+            expected,
+            actual,
+        }),
+        Err(error) => Err(error),
+    }
+}
+
+/// Reads the configured memory range from the target and writes it to the output file.
+/// This also manages the update and display of the progress bar.
+pub fn run_flash_read(session: &mut Session, config: &Config) -> Result<(), RoverError> {
+    let range = config
+        .reading()
+        .range()
+        .ok_or(RoverError::IncompleteReadConfig)?;
+    let output_path = config
+        .reading()
+        .output_path()
+        .clone()
+        .ok_or(RoverError::IncompleteReadConfig)?;
+
+    let progress = if !config.disable_progressbars() && !config.fast() {
+        let progress = ProgressBar::new((range.end - range.start) as u64);
+        progress.set_style(progress_style());
+        progress.set_message("     Reading flash  ");
+        progress.enable_steady_tick(100);
+        Some(progress)
+    } else {
+        None
+    };
+
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+    let mut data = vec![0u8; (range.end - range.start) as usize];
+    for (i, chunk) in data.chunks_mut(READ_CHUNK_SIZE).enumerate() {
+        let address = range.start + (i * READ_CHUNK_SIZE) as u32;
+        core.read_8(address, chunk).map_err(|error| {
+            if let Some(progress) = &progress {
+                progress.abandon();
+            }
+            RoverError::FailedToReadMemory(error)
+        })?;
+        if let Some(progress) = &progress {
+            progress.inc(chunk.len() as u64);
+        }
+    }
+    if let Some(progress) = &progress {
+        progress.finish();
+    }
+
+    let content = match config.reading().format() {
+        ReadFormat::Bin => data,
+        ReadFormat::Hex => to_intel_hex(&[(range.start, &data[..])]).into_bytes(),
+    };
+    std::fs::write(&output_path, content).map_err(|error| RoverError::FailedToWriteReadOutput {
+        source: error,
+        path: output_path.clone(),
+    })
+}
+
+/// Writes the given words to the target in the given order.
+pub fn run_pre_writes(session: &mut Session, writes: &[MemoryWrite]) -> Result<(), RoverError> {
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+    for write in writes {
+        logging::println(format!(
+            "    {} {:#010x} to {:#010x}",
+            "Writing".green().bold(),
+            write.value,
+            write.address
+        ));
+        core.write_word_32(write.address, write.value)
+            .map_err(|error| RoverError::PreWriteFailed {
+                source: error,
+                address: write.address,
+                value: write.value,
+            })?;
+    }
+    Ok(())
+}
+
+/// Writes the image which is flashed for the given images to the path.
+/// Paths ending in `.hex` get an Intel HEX file which keeps the segments apart, anything else gets a raw binary
+/// which starts at the lowest address and has the gaps between the segments filled with `0xFF`.
+/// A raw binary larger than `MAX_BINARY_SIZE` is refused.
+pub fn export_image(
+    target: &Target,
+    images: &[(PathBuf, Format)],
+    config: &Config,
+    path: &Path,
+) -> Result<(), RoverError> {
+    // If flash regions are configured, they are flashed instead of the given images.
+    let regions = config.flashing().regions();
+    let region_images = regions.iter().map(region_image).collect::<Vec<_>>();
+    let images = if regions.is_empty() {
+        images
+    } else {
+        &region_images[..]
+    };
+
+    let mut segments = verify::collect_segments(target, images, config.flashing().flash_range())?
+        .into_iter()
+        .map(|segment| (segment.address, segment.data))
+        .collect::<Vec<_>>();
+    segments.sort_by_key(|(address, _)| *address);
+
+    let is_hex = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map_or(false, |extension| extension.eq_ignore_ascii_case("hex"));
+    let content = if is_hex {
+        let segments = segments
+            .iter()
+            .map(|(address, data)| (*address, &data[..]))
+            .collect::<Vec<_>>();
+        to_intel_hex(&segments).into_bytes()
+    } else {
+        let size = binary_size(&segments);
+        if size > MAX_BINARY_SIZE {
+            return Err(RoverError::ExportedBinaryTooLarge {
+                size,
+                path: format!("{}", path.display()),
+            });
+        }
+        let (start, data) = to_binary(&segments);
+        log::info!(
+            "The exported binary starts at {:#010x}, which has to be given as its base address.",
+            start
+        );
+        data
+    };
+
+    fs::write(path, content).map_err(|error| RoverError::FailedToExportImage {
+        source: error,
+        path: format!("{}", path.display()),
+    })?;
+    logging::println(format!(
+        "    {} the image to {}",
+        "Exported".green().bold(),
+        path.display()
+    ));
+    Ok(())
+}
+
+/// The size of the binary `to_binary` joins the segments into, including the gaps.
+fn binary_size(segments: &[(u32, Vec<u8>)]) -> u64 {
+    let start = segments.iter().map(|(address, _)| *address as u64).min();
+    let end = segments
+        .iter()
+        .map(|(address, data)| *address as u64 + data.len() as u64)
+        .max();
+    match (start, end) {
+        (Some(start), Some(end)) => end - start,
+        _ => 0,
+    }
+}
+
+/// Joins the segments, which have to be sorted by their address, into a single binary.
+/// Returns the address the binary starts at. Gaps are filled with `0xFF`, the value of erased flash.
+fn to_binary(segments: &[(u32, Vec<u8>)]) -> (u32, Vec<u8>) {
+    let start = match segments.first() {
+        Some((address, _)) => *address,
+        None => return (0, vec![]),
+    };
+
+    let mut data = vec![];
+    for (address, segment) in segments {
+        let offset = (address - start) as usize;
+        let end = offset + segment.len();
+        if data.len() < end {
+            data.resize(end, 0xFF);
+        }
+        data[offset..end].copy_from_slice(segment);
+    }
+    (start, data)
+}
+
+/// Encodes the segments as an Intel HEX file, each with its data starting at the given address.
+fn to_intel_hex(segments: &[(u32, &[u8])]) -> String {
+    fn record(output: &mut String, kind: u8, offset: u16, data: &[u8]) {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&offset.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        let checksum = bytes
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+            .wrapping_neg();
+        bytes.push(checksum);
+
+        output.push(':');
+        for byte in bytes {
+            output.push_str(&format!("{:02X}", byte));
+        }
+        output.push('\n');
+    }
+
+    let mut output = String::new();
+    let mut upper = None;
+    for (start, data) in segments {
+        let mut address = *start;
+        for chunk in data.chunks(16) {
+            // A record must not cross a 64 KiB boundary, so chunks are split at it.
+            let until_boundary = 0x1_0000 - (address & 0xFFFF) as usize;
+            let parts = if chunk.len() > until_boundary {
+                vec![&chunk[..until_boundary], &chunk[until_boundary..]]
+            } else {
+                vec![chunk]
+            };
+
+            for part in parts {
+                let address_upper = (address >> 16) as u16;
+                if upper != Some(address_upper) {
+                    record(&mut output, 0x04, 0, &address_upper.to_be_bytes());
+                    upper = Some(address_upper);
+                }
+                record(&mut output, 0x00, address as u16, part);
+                address = address.wrapping_add(part.len() as u32);
+            }
+        }
+    }
+    record(&mut output, 0x01, 0, &[]);
+
+    output
+}
+
+/// The progress style shared by all the progress bars.
+/// The message is only colored if the rest of the output is colored as well.
+fn progress_style() -> ProgressStyle {
+    let msg = if colored::control::SHOULD_COLORIZE.should_colorize() {
+        "{msg:.green.bold}"
+    } else {
+        "{msg}"
+    };
+    ProgressStyle::default_bar()
+        .tick_chars("⠁⠁⠉⠙⠚⠒⠂⠂⠒⠲⠴⠤⠄⠄⠤⠠⠠⠤⠦⠖⠒⠐⠐⠒⠓⠋⠉⠈⠈✔")
+        .progress_chars("##-")
+        .template(&format!("{} {{spinner}} [{{elapsed_precise}}] [{{wide_bar}}] {{bytes:>8}}/{{total_bytes:>8}} @ {{bytes_per_sec:>10}} (eta {{eta:3}})", msg))
+}
+
+/// Opens the files of all the given images.
+pub fn open_images(images: &[(PathBuf, Format)]) -> Result<Vec<File>, RoverError> {
+    images
+        .iter()
+        .map(|(path, _)| {
+            File::open(path).map_err(|error| RoverError::FailedToOpenElf {
+                source: error,
+                path: format!("{}", path.display()),
+            })
+        })
+        .collect()
+}
+
+/// Builds a new flash loader for the given target and images.
+/// This will check the images for validity and check what pages have to be flashed etc.
 pub fn build_flashloader<'data>(
     target: &Target,
-    file: &'data mut File,
-    format: &Format,
-    buffer: &'data mut Vec<Vec<u8>>,
+    images: &[(PathBuf, Format)],
+    files: &'data mut [File],
+    buffers: &'data mut [Vec<Vec<u8>>],
     keep_unwritten: bool,
+    flash_range: Option<MemoryRange>,
 ) -> Result<FlashLoader<'data>, RoverError> {
     // Create the flash loader
     let mut loader = FlashLoader::new(
@@ -194,24 +1073,317 @@ pub fn build_flashloader<'data>(
         keep_unwritten,
         target.source().clone(),
     );
+    let mut elf_data_in_range = false;
 
-    match format {
-        Format::Bin(bin_options) => {
-            loader
-                .load_bin_data(buffer, file, bin_options.clone())
-                .map_err(RoverError::FailedToLoadElfData)?;
-        }
-        Format::Hex => {
-            loader
-                .load_hex_data(buffer, file)
-                .map_err(RoverError::FailedToLoadElfData)?;
+    for (((path, format), file), buffer) in
+        images.iter().zip(files.iter_mut()).zip(buffers.iter_mut())
+    {
+        match format {
+            Format::Bin(bin_options) => {
+                loader
+                    .load_bin_data(buffer, file, bin_options.clone())
+                    .map_err(RoverError::FailedToLoadElfData)?;
+            }
+            Format::Hex => {
+                loader
+                    .load_hex_data(buffer, file)
+                    .map_err(RoverError::FailedToLoadElfData)?;
+            }
+            Format::Elf => match flash_range {
+                Some(range) => {
+                    let mut content = vec![];
+                    file.read_to_end(&mut content).map_err(|error| {
+                        RoverError::FailedToOpenElf {
+                            source: error,
+                            path: format!("{}", path.display()),
+                        }
+                    })?;
+                    let segments = verify::parse_elf(&content).ok_or_else(|| {
+                        RoverError::FailedToParseElf {
+                            path: format!("{}", path.display()),
+                        }
+                    })?;
+                    let segments = verify::clip(segments, range)
+                        .into_iter()
+                        .map(|segment| (segment.address, segment.data))
+                        .collect::<Vec<_>>();
+                    elf_data_in_range |= !segments.is_empty();
+
+                    add_segments(&mut loader, buffer, segments)
+                        .map_err(RoverError::FailedToLoadElfSegments)?;
+                }
+                None => {
+                    loader
+                        .load_elf_data(buffer, file)
+                        .map_err(RoverError::FailedToLoadElfData)?;
+                }
+            },
+            Format::Uf2(uf2_options) => {
+                let mut content = vec![];
+                file.read_to_end(&mut content)
+                    .map_err(|error| RoverError::FailedToOpenElf {
+                        source: error,
+                        path: format!("{}", path.display()),
+                    })?;
+                let segments = uf2::parse(&content, uf2_options.family).map_err(|error| {
+                    RoverError::FailedToParseUf2 {
+                        source: error,
+                        path: format!("{}", path.display()),
+                    }
+                })?;
+
+                let segments = segments
+                    .into_iter()
+                    .map(|segment| (segment.address, segment.data))
+                    .collect();
+                add_segments(&mut loader, buffer, segments)
+                    .map_err(RoverError::FailedToLoadUf2Data)?;
+            }
         }
-        Format::Elf => {
-            loader
-                .load_elf_data(buffer, file)
-                .map_err(RoverError::FailedToLoadElfData)?;
+    }
+
+    // Only the ELF files are restricted to the flash range, so only they have to contain data within it.
+    if let Some(range) = flash_range {
+        let has_elf = images
+            .iter()
+            .any(|(_, format)| matches!(format, Format::Elf));
+        if has_elf && !elf_data_in_range {
+            return Err(RoverError::NothingInFlashRange {
+                start: range.start,
+                end: range.end,
+            });
         }
     }
 
     Ok(loader)
 }
+
+/// Adds the given segments to the loader.
+/// The loader only borrows the data, so it is kept in the buffer.
+fn add_segments<'data>(
+    loader: &mut FlashLoader<'data>,
+    buffer: &'data mut Vec<Vec<u8>>,
+    segments: Vec<(u32, Vec<u8>)>,
+) -> Result<(), FlashError> {
+    let addresses = segments
+        .iter()
+        .map(|(address, _)| *address)
+        .collect::<Vec<_>>();
+    buffer.extend(segments.into_iter().map(|(_, data)| data));
+    let buffer: &'data Vec<Vec<u8>> = buffer;
+    for (address, data) in addresses.into_iter().zip(buffer.iter()) {
+        loader.add_data(address, data)?;
+    }
+
+    Ok(())
+}
+
+/// Opens the binary files of all the given flash regions.
+/// This also checks that the regions do not overlap, that each file fits its region and that each region lies within the flash of the target.
+pub fn open_regions(target: &Target, regions: &[FlashRegion]) -> Result<Vec<File>, RoverError> {
+    let mut files = Vec::with_capacity(regions.len());
+
+    for region in regions {
+        let in_flash = target
+            .memory_map
+            .iter()
+            .any(|memory_region| match memory_region {
+                MemoryRegion::Nvm(flash) => {
+                    flash.range.start <= region.base() && region.end() <= flash.range.end as u64
+                }
+                _ => false,
+            });
+        if !in_flash {
+            return Err(RoverError::FlashRegionOutsideNvm {
+                name: region.name().clone(),
+                base: region.base(),
+                size: region.size(),
+                target: target.clone(),
+            });
+        }
+
+        let file =
+            File::open(region.file()).map_err(|error| RoverError::FailedToOpenRegionFile {
+                source: error,
+                name: region.name().clone(),
+                path: region.file().clone(),
+            })?;
+        let file_size = file
+            .metadata()
+            .map_err(|error| RoverError::FailedToOpenRegionFile {
+                source: error,
+                name: region.name().clone(),
+                path: region.file().clone(),
+            })?
+            .len();
+        if file_size > region.size() as u64 {
+            return Err(RoverError::FlashRegionTooSmall {
+                name: region.name().clone(),
+                size: region.size(),
+                file_size,
+            });
+        }
+
+        files.push(file);
+    }
+
+    let mut sorted = regions.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|region| region.base());
+    for pair in sorted.windows(2) {
+        if pair[0].end() > pair[1].base() as u64 {
+            return Err(RoverError::FlashRegionsOverlap {
+                first: pair[0].name().clone(),
+                second: pair[1].name().clone(),
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// Returns the image which writes the binary file of the region to its base address.
+pub fn region_image(region: &FlashRegion) -> (PathBuf, Format) {
+    (
+        region.file().into(),
+        Format::Bin(BinOptions {
+            base_address: Some(region.base()),
+            skip: 0,
+        }),
+    )
+}
+
+/// Prints the layout of the given flash regions ordered by their base address.
+fn print_region_layout(regions: &[FlashRegion]) {
+    let mut sorted = regions.iter().collect::<Vec<_>>();
+    sorted.sort_by_key(|region| region.base());
+
+    logging::println("    Flash layout:");
+    for region in sorted {
+        logging::println(format!(
+            "        {:<16} {:#010x} - {:#010x} ({}) {}",
+            region.name(),
+            region.base(),
+            region.end(),
+            ByteSize(region.size() as u64).to_string_as(true),
+            region.file()
+        ));
+    }
+}
+
+/// The options `FlashLoader::commit` is called with.
+#[derive(Debug, PartialEq)]
+struct CommitOptions {
+    /// Whether the whole chip is erased instead of only the sectors which are written.
+    do_chip_erase: bool,
+    dry_run: bool,
+}
+
+impl CommitOptions {
+    fn new(config: &Config) -> Self {
+        Self {
+            do_chip_erase: config.flashing().do_chip_erase(),
+            dry_run: config.dry_run(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use structopt::StructOpt;
+
+    use super::{
+        binary_size, estimate_flash_time, layout_blocks, stats_row, to_binary, to_intel_hex,
+        CommitOptions,
+    };
+    use crate::config::Config;
+
+    #[test]
+    fn chip_erase_is_requested_from_the_flash_loader() {
+        let config = Config::from_iter(&["rover", "--flashing.do-chip-erase", "true"]);
+        assert_eq!(
+            CommitOptions::new(&config),
+            CommitOptions {
+                do_chip_erase: true,
+                dry_run: false,
+            }
+        );
+
+        let config = Config::from_iter(&["rover"]);
+        assert!(!CommitOptions::new(&config).do_chip_erase);
+    }
+
+    #[test]
+    fn layout_blocks_are_listed_with_their_ranges() {
+        assert_eq!(
+            layout_blocks(
+                "Sectors",
+                vec![(0x0800_0000, 0x4000), (0x0800_4000, 0x4000)].into_iter()
+            ),
+            "Sectors:\n  0x08000000..0x08004000 (16.0 KiB)\n  0x08004000..0x08008000 (16.0 KiB)\n"
+        );
+        assert_eq!(layout_blocks("Fills", std::iter::empty()), "Fills:\n");
+    }
+
+    #[test]
+    fn flash_time_is_estimated_from_the_probe_speed() {
+        // 64 KiB erased in 640ms and 64 KiB programmed at 1 MHz in 1572ms.
+        assert_eq!(
+            estimate_flash_time(0x1_0000, 0x1_0000, 0, 1000),
+            Duration::from_millis(640 + 1572)
+        );
+        // Filled data is read back over the same link.
+        assert_eq!(
+            estimate_flash_time(0, 0x8000, 0x8000, 4000),
+            Duration::from_millis(393)
+        );
+    }
+
+    #[test]
+    fn stats_rows_show_the_throughput() {
+        assert_eq!(
+            stats_row("Programmed", 0x1_0000, Duration::from_secs(2)),
+            "      Programmed      64.0 KiB in   2.00s (32.0 KiB/s)"
+        );
+        assert_eq!(
+            stats_row("Erased", 0, Duration::from_secs(0)),
+            "      Erased               0 B in   0.00s (-)"
+        );
+    }
+
+    #[test]
+    fn intel_hex_splits_at_64k_boundaries() {
+        assert_eq!(
+            to_intel_hex(&[(0x0800_FFFE, &[1, 2, 3][..])]),
+            ":020000040800F2\n\
+             :02FFFE000102FE\n\
+             :020000040801F1\n\
+             :0100000003FC\n\
+             :00000001FF\n"
+        );
+    }
+
+    #[test]
+    fn binaries_fill_the_gaps_between_segments() {
+        assert_eq!(
+            to_binary(&[(0x0800_0000, vec![1, 2]), (0x0800_0004, vec![3])]),
+            (0x0800_0000, vec![1, 2, 0xFF, 0xFF, 3])
+        );
+        assert_eq!(to_binary(&[]), (0, vec![]));
+    }
+
+    #[test]
+    fn binary_size_includes_the_gaps() {
+        assert_eq!(
+            binary_size(&[(0x0800_0000, vec![1, 2]), (0x0800_0004, vec![3])]),
+            5
+        );
+        // The option bytes of an STM32F4 are far away from its flash.
+        assert_eq!(
+            binary_size(&[(0x0800_0000, vec![0; 4]), (0x1FFF_C000, vec![0; 16])]),
+            0x17FF_C010
+        );
+        assert_eq!(binary_size(&[]), 0);
+    }
+}
@@ -1,31 +1,141 @@
 use std::{
-    sync::{Arc, Mutex},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
     thread::JoinHandle,
+    time::Duration,
 };
 
 use probe_rs::Session;
-use probe_rs_cli_util::logging;
 
 use crate::diagnostics::RoverError;
 
-const DEFAULT_GDB_LINK: &str = "127.0.0.1:1337";
+pub(crate) const DEFAULT_GDB_BIND_ADDRESS: &str = "127.0.0.1";
+pub(crate) const DEFAULT_GDB_PORT: u16 = 1337;
 
+/// Assembles the address the GDB stub listens on.
+///
+/// A full `socket` takes precedence over the separate bind address and port.
+pub(crate) fn gdb_link(
+    socket: Option<&str>,
+    bind_address: Option<&str>,
+    port: Option<u16>,
+) -> String {
+    match socket {
+        Some(socket) => socket.to_string(),
+        None => format!(
+            "{}:{}",
+            bind_address.unwrap_or(DEFAULT_GDB_BIND_ADDRESS),
+            port.unwrap_or(DEFAULT_GDB_PORT)
+        ),
+    }
+}
+
+/// Starts the GDB stub in a separate thread.
+///
+/// The thread ends with the error of the stub once it stops, or once `halt` is set.
+/// In both cases `halt` is set so all the other services shut down as well.
+/// The stub itself cannot be stopped from the outside, so on a shutdown it is left to end with the process.
+/// The stub of probe-rs always debugs the first core.
+/// `ready` is signaled once the stub accepts connections.
 pub fn run_gdb(
     session: Arc<Mutex<Session>>,
-    link: Option<String>,
+    socket: Option<String>,
+    bind_address: Option<String>,
+    port: Option<u16>,
+    halt: Arc<AtomicBool>,
+    ready: Sender<()>,
 ) -> JoinHandle<Result<(), RoverError>> {
     std::thread::spawn(move || {
-        let gdb_connection_string = link.as_deref().or(Some(DEFAULT_GDB_LINK));
-        // This next unwrap will always resolve as the connection string is always Some(T).
-        log::info!(
-            "Firing up GDB stub at {}.",
-            gdb_connection_string.as_ref().unwrap(),
-        );
-        if let Err(e) = probe_rs_gdb_server::run(gdb_connection_string, &session) {
-            logging::eprintln("During the execution of GDB an error was encountered:");
-            logging::eprintln(format!("{:?}", e));
-        }
+        let gdb_connection_string = gdb_link(socket.as_deref(), bind_address.as_deref(), port);
+        log::info!("Firing up GDB stub at {}.", gdb_connection_string);
+
+        let (done_sender, done_receiver) = channel();
+        let stub_link = gdb_connection_string.clone();
+        let stub = std::thread::spawn(move || {
+            let result = probe_rs_gdb_server::run(Some(&stub_link), &session);
+            let _ = done_sender.send(result);
+        });
 
-        Ok(())
+        // The stub does not tell when it is bound, so it is probed until it accepts a connection.
+        let mut ready = Some(ready);
+        let result = loop {
+            if ready.is_some() && is_listening(&gdb_connection_string) {
+                log::info!("GDB stub is listening at {}.", gdb_connection_string);
+                if let Some(ready) = ready.take() {
+                    // Nobody might be waiting for the signal, so a failed send is fine.
+                    let _ = ready.send(());
+                }
+            }
+
+            match done_receiver.recv_timeout(Duration::from_millis(100)) {
+                Ok(result) => break result.map_err(RoverError::GdbStubFailed),
+                Err(RecvTimeoutError::Timeout) if halt.load(Ordering::Relaxed) => break Ok(()),
+                Err(RecvTimeoutError::Timeout) => (),
+                // The stub panicked. The panic is forwarded so it gets reported like any other panic.
+                Err(RecvTimeoutError::Disconnected) => match stub.join() {
+                    Err(panic) => std::panic::resume_unwind(panic),
+                    Ok(()) => break Ok(()),
+                },
+            }
+        };
+
+        halt.store(true, Ordering::Relaxed);
+
+        result
     })
 }
+
+/// Whether something accepts TCP connections at the address.
+/// An unspecified address like `0.0.0.0` is reached through the loopback interface.
+fn is_listening(link: &str) -> bool {
+    let addresses = match link.to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(_) => return false,
+    };
+    addresses
+        .map(|mut address| {
+            match address.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => {
+                    address.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST))
+                }
+                IpAddr::V6(ip) if ip.is_unspecified() => {
+                    address.set_ip(IpAddr::V6(Ipv6Addr::LOCALHOST))
+                }
+                _ => (),
+            }
+            address
+        })
+        .any(|address| TcpStream::connect_timeout(&address, Duration::from_millis(100)).is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::TcpListener;
+
+    use super::{gdb_link, is_listening};
+
+    #[test]
+    fn socket_takes_precedence() {
+        assert_eq!(gdb_link(None, None, None), "127.0.0.1:1337");
+        assert_eq!(gdb_link(None, Some("0.0.0.0"), None), "0.0.0.0:1337");
+        assert_eq!(gdb_link(None, None, Some(3333)), "127.0.0.1:3333");
+        assert_eq!(
+            gdb_link(Some("localhost:2000"), Some("0.0.0.0"), Some(3333)),
+            "localhost:2000"
+        );
+    }
+
+    #[test]
+    fn listeners_on_all_interfaces_are_found() {
+        let listener = TcpListener::bind("0.0.0.0:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(is_listening(&format!("0.0.0.0:{}", port)));
+
+        drop(listener);
+        assert!(!is_listening(&format!("0.0.0.0:{}", port)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Incremental flashing which only erases and programs the sectors whose contents change.
+
+use std::ops::Range;
+
+use probe_rs::{MemoryInterface, Session, Target};
+
+use crate::{diagnostics::RoverError, verify::Segment};
+
+/// The result of comparing the new data with the flash contents of the target.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// The parts of the new data which lie in sectors that differ and have to be flashed.
+    pub segments: Vec<Segment>,
+    /// The number of sectors which have to be flashed.
+    pub changed_sectors: usize,
+    /// The number of sectors which already contain the new data and are skipped.
+    pub skipped_sectors: usize,
+    /// The number of bytes of new data in the skipped sectors.
+    pub skipped_bytes: usize,
+}
+
+/// Reads back the sectors the given segments are flashed to and keeps only the parts of the segments in sectors which differ.
+///
+/// Data in sectors which are not known from the flash algorithm of the target is always flashed,
+/// as skipping it could erase it along with changed data next to it.
+pub fn changed_segments(
+    session: &mut Session,
+    segments: Vec<Segment>,
+) -> Result<Changes, RoverError> {
+    let target = session.target().clone();
+    let sectors = split_by_sectors(segments, |address| sector_containing(&target, address));
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+
+    let mut changes = Changes::default();
+    for (sector, pieces) in sectors {
+        let unchanged = match sector {
+            Some(_) => {
+                let mut unchanged = true;
+                for piece in &pieces {
+                    let mut actual = vec![0; piece.data.len()];
+                    core.read_8(piece.address, &mut actual)
+                        .map_err(RoverError::VerifyReadFailed)?;
+                    if actual != piece.data {
+                        unchanged = false;
+                        break;
+                    }
+                }
+                unchanged
+            }
+            None => false,
+        };
+
+        if unchanged {
+            changes.skipped_sectors += 1;
+            changes.skipped_bytes += pieces.iter().map(|piece| piece.data.len()).sum::<usize>();
+        } else {
+            changes.changed_sectors += 1;
+            changes.segments.extend(pieces);
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Finds the sector of the flash of the target which contains the given address.
+fn sector_containing(target: &Target, address: u32) -> Option<Range<u32>> {
+    let properties = &target
+        .flash_algorithms
+        .iter()
+        .find(|algorithm| algorithm.flash_properties.address_range.contains(&address))?
+        .flash_properties;
+
+    // Each sector description applies to all the sectors up to the next description.
+    let offset = address - properties.address_range.start;
+    let descriptions = &properties.sectors;
+    let (index, description) = descriptions
+        .iter()
+        .enumerate()
+        .filter(|(_, description)| description.address <= offset)
+        .last()?;
+    if description.size == 0 {
+        return None;
+    }
+    let end_of_description = descriptions
+        .get(index + 1)
+        .map(|next| next.address)
+        .unwrap_or(properties.address_range.end - properties.address_range.start);
+    if offset >= end_of_description {
+        return None;
+    }
+
+    let start =
+        description.address + (offset - description.address) / description.size * description.size;
+    let start = properties.address_range.start + start;
+    Some(start..start + description.size)
+}
+
+/// Splits the segments at the sector boundaries and groups the pieces by their sector.
+/// Pieces outside of any known sector are grouped on their own.
+fn split_by_sectors(
+    segments: Vec<Segment>,
+    sector_of: impl Fn(u32) -> Option<Range<u32>>,
+) -> Vec<(Option<Range<u32>>, Vec<Segment>)> {
+    let mut sectors: Vec<(Option<Range<u32>>, Vec<Segment>)> = vec![];
+    for segment in segments {
+        let mut address = segment.address;
+        let mut data = &segment.data[..];
+        while !data.is_empty() {
+            let sector = sector_of(address);
+            let length = match &sector {
+                Some(sector) => ((sector.end - address) as usize).min(data.len()),
+                None => data.len(),
+            };
+            let piece = Segment {
+                address,
+                data: data[..length].to_vec(),
+            };
+
+            match sectors
+                .iter_mut()
+                .find(|(known, _)| sector.is_some() && *known == sector)
+            {
+                Some((_, pieces)) => pieces.push(piece),
+                None => sectors.push((sector, vec![piece])),
+            }
+
+            address += length as u32;
+            data = &data[length..];
+        }
+    }
+    sectors
+}
+
+#[cfg(test)]
+mod test {
+    use super::split_by_sectors;
+    use crate::verify::Segment;
+
+    #[test]
+    fn segments_are_split_at_sector_boundaries() {
+        let sector_of = |address: u32| {
+            if address < 0x1000 {
+                let start = address / 0x400 * 0x400;
+                Some(start..start + 0x400)
+            } else {
+                None
+            }
+        };
+        let segments = vec![
+            Segment {
+                address: 0x300,
+                data: vec![1; 0x200],
+            },
+            Segment {
+                address: 0x580,
+                data: vec![2; 0x10],
+            },
+            Segment {
+                address: 0x1000,
+                data: vec![3; 0x20],
+            },
+        ];
+
+        let sectors = split_by_sectors(segments, sector_of);
+
+        assert_eq!(sectors.len(), 3);
+        assert_eq!(sectors[0].0, Some(0x000..0x400));
+        assert_eq!(sectors[0].1.len(), 1);
+        assert_eq!(sectors[0].1[0].address, 0x300);
+        assert_eq!(sectors[0].1[0].data.len(), 0x100);
+        // The second half of the first segment and the second segment share a sector.
+        assert_eq!(sectors[1].0, Some(0x400..0x800));
+        assert_eq!(sectors[1].1.len(), 2);
+        assert_eq!(sectors[1].1[0].address, 0x400);
+        assert_eq!(sectors[1].1[0].data.len(), 0x100);
+        assert_eq!(sectors[1].1[1].address, 0x580);
+        // Data outside of any known sector is kept as a whole.
+        assert_eq!(sectors[2].0, None);
+        assert_eq!(sectors[2].1[0].data.len(), 0x20);
+    }
+}
@@ -0,0 +1,368 @@
+//! Decoding of the ITM/DWT trace packet protocol as described in appendix D4 of the ARMv7-M architecture reference manual.
+
+use serde::Serialize;
+
+/// A single packet decoded from the SWO trace stream.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum TracePacket {
+    /// A synchronization packet.
+    Sync,
+    /// The ITM FIFO overflowed and packets were lost.
+    Overflow,
+    /// A local timestamp carrying the number of trace clock ticks since the previous local timestamp.
+    /// The relation describes how the timestamp relates to the packet it accompanies.
+    LocalTimestamp { delta: u32, relation: u8 },
+    /// The lower bits of a global timestamp.
+    GlobalTimestamp1 { value: u64 },
+    /// The upper bits of a global timestamp.
+    GlobalTimestamp2 { value: u64 },
+    /// An extension packet, for example to select the stimulus port page.
+    Extension { value: u32, hardware: bool },
+    /// A software source packet written to an ITM stimulus port.
+    Instrumentation { port: u8, payload: Vec<u8> },
+    /// A hardware source packet generated by the DWT.
+    HardwareSource { id: u8, payload: Vec<u8> },
+    /// A byte which does not start a known packet.
+    Unknown { header: u8 },
+}
+
+/// A streaming decoder for the ITM packet protocol.
+///
+/// Packets can be split across multiple reads from the probe, so incomplete packets are kept until the rest arrives.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    /// Creates a new decoder with an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feeds new bytes into the decoder and returns all the packets which are complete now.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<TracePacket> {
+        self.buffer.extend_from_slice(data);
+
+        let mut packets = vec![];
+        let mut offset = 0;
+        while let Some((packet, length)) = decode_packet(&self.buffer[offset..]) {
+            packets.push(packet);
+            offset += length;
+        }
+        self.buffer.drain(..offset);
+
+        packets
+    }
+}
+
+/// A packet which carries data, in the form it is forwarded as a JSON line.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JsonPacket {
+    /// `instrumentation` for stimulus port writes, `hardware` for DWT packets and `overflow` for lost packets.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The stimulus port or the ID of the hardware source.
+    pub port: Option<u8>,
+    pub payload: Vec<u8>,
+    /// The trace clock ticks of all the local timestamps received before the packet.
+    pub timestamp: u64,
+}
+
+/// Turns the decoded packets into JSON packets and keeps track of the time given by the local timestamps.
+#[derive(Debug, Default)]
+pub struct JsonEncoder {
+    timestamp: u64,
+}
+
+impl JsonEncoder {
+    /// Creates a new encoder which starts at timestamp zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the JSON packets of all the packets which carry data.
+    /// Timestamps advance the time of the following packets and are not forwarded themselves.
+    pub fn encode(&mut self, packets: &[TracePacket]) -> Vec<JsonPacket> {
+        let mut encoded = vec![];
+        for packet in packets {
+            let (kind, port, payload) = match packet {
+                TracePacket::LocalTimestamp { delta, .. } => {
+                    self.timestamp += *delta as u64;
+                    continue;
+                }
+                TracePacket::Instrumentation { port, payload } => {
+                    ("instrumentation", Some(*port), payload.clone())
+                }
+                TracePacket::HardwareSource { id, payload } => {
+                    ("hardware", Some(*id), payload.clone())
+                }
+                TracePacket::Overflow => ("overflow", None, vec![]),
+                _ => continue,
+            };
+            encoded.push(JsonPacket {
+                kind,
+                port,
+                payload,
+                timestamp: self.timestamp,
+            });
+        }
+        encoded
+    }
+}
+
+/// Decodes the packet at the start of `data`.
+/// Returns the packet and its length in bytes or `None` if the packet is not complete yet.
+fn decode_packet(data: &[u8]) -> Option<(TracePacket, usize)> {
+    let header = *data.first()?;
+
+    match header {
+        0x00 => {
+            // A synchronization packet is a run of zero bytes terminated by 0x80.
+            let zeros = data.iter().take_while(|b| **b == 0).count();
+            match data.get(zeros) {
+                Some(0x80) => Some((TracePacket::Sync, zeros + 1)),
+                Some(_) => Some((TracePacket::Unknown { header }, zeros)),
+                None => None,
+            }
+        }
+        0x70 => Some((TracePacket::Overflow, 1)),
+        0x94 => {
+            let (value, length) = read_continuation(&data[1..], 4)?;
+            Some((TracePacket::GlobalTimestamp1 { value }, length + 1))
+        }
+        0xB4 => {
+            let (value, length) = read_continuation(&data[1..], 6)?;
+            Some((TracePacket::GlobalTimestamp2 { value }, length + 1))
+        }
+        _ if header & 0x0F == 0x00 => {
+            if header & 0x80 == 0 {
+                // Local timestamp format 2 carries the delta in the header itself.
+                Some((
+                    TracePacket::LocalTimestamp {
+                        delta: ((header >> 4) & 0x07) as u32,
+                        relation: 0,
+                    },
+                    1,
+                ))
+            } else if header & 0xC0 == 0xC0 {
+                // Local timestamp format 1 carries the delta in up to four continuation bytes.
+                let (delta, length) = read_continuation(&data[1..], 4)?;
+                Some((
+                    TracePacket::LocalTimestamp {
+                        delta: delta as u32,
+                        relation: (header >> 4) & 0x03,
+                    },
+                    length + 1,
+                ))
+            } else {
+                Some((TracePacket::Unknown { header }, 1))
+            }
+        }
+        _ if header & 0x0B == 0x08 => {
+            let mut value = ((header >> 4) & 0x07) as u32;
+            let mut length = 1;
+            if header & 0x80 != 0 {
+                let (extension, extension_length) = read_continuation(&data[1..], 4)?;
+                value |= (extension as u32) << 3;
+                length += extension_length;
+            }
+            Some((
+                TracePacket::Extension {
+                    value,
+                    hardware: header & 0x04 != 0,
+                },
+                length,
+            ))
+        }
+        _ if header & 0x03 != 0 => {
+            let size = match header & 0x03 {
+                0x01 => 1,
+                0x02 => 2,
+                _ => 4,
+            };
+            let payload = data.get(1..=size)?.to_vec();
+            let address = header >> 3;
+            let packet = if header & 0x04 == 0 {
+                TracePacket::Instrumentation {
+                    port: address,
+                    payload,
+                }
+            } else {
+                TracePacket::HardwareSource {
+                    id: address,
+                    payload,
+                }
+            };
+            Some((packet, size + 1))
+        }
+        _ => Some((TracePacket::Unknown { header }, 1)),
+    }
+}
+
+/// Reads up to `max_length` continuation bytes, each contributing seven bits to the value.
+/// Returns the value and the number of bytes read or `None` if the bytes are not complete yet.
+fn read_continuation(data: &[u8], max_length: usize) -> Option<(u64, usize)> {
+    let mut value = 0;
+    for (i, byte) in data.iter().take(max_length).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 || i + 1 == max_length {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Decoder, JsonEncoder, JsonPacket, TracePacket};
+
+    #[test]
+    fn stimulus_packets_are_decoded_across_reads() {
+        let mut decoder = Decoder::new();
+
+        // Port 0 writes a byte and port 1 starts a word which is only complete with the next read.
+        assert_eq!(
+            decoder.feed(&[0x01, 0x61, 0x0B, 0x01]),
+            vec![TracePacket::Instrumentation {
+                port: 0,
+                payload: vec![0x61],
+            }]
+        );
+        // Port 31 writes a halfword after the rest of the word.
+        assert_eq!(
+            decoder.feed(&[0x02, 0x03, 0x04, 0xFA, 0x68, 0x69]),
+            vec![
+                TracePacket::Instrumentation {
+                    port: 1,
+                    payload: vec![0x01, 0x02, 0x03, 0x04],
+                },
+                TracePacket::Instrumentation {
+                    port: 31,
+                    payload: vec![0x68, 0x69],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hardware_source_packets_are_decoded() {
+        let packets = Decoder::new().feed(&[
+            // An event counter wrapped.
+            0x05, 0x20, //
+            // An exception was entered.
+            0x0E, 0x0F, 0x10, //
+            // A PC sample.
+            0x17, 0x00, 0x10, 0x00, 0x08,
+        ]);
+
+        assert_eq!(
+            packets,
+            vec![
+                TracePacket::HardwareSource {
+                    id: 0,
+                    payload: vec![0x20],
+                },
+                TracePacket::HardwareSource {
+                    id: 1,
+                    payload: vec![0x0F, 0x10],
+                },
+                TracePacket::HardwareSource {
+                    id: 2,
+                    payload: vec![0x00, 0x10, 0x00, 0x08],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn timestamp_packets_are_decoded() {
+        let mut decoder = Decoder::new();
+
+        let packets = decoder.feed(&[
+            // A synchronization packet.
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x80, //
+            // A local timestamp of 3 ticks in format 2.
+            0x30, //
+            // A delayed local timestamp of 0x85 ticks in format 1.
+            0xD0, 0x85, 0x01, //
+            // The lower and upper bits of a global timestamp.
+            0x94, 0x81, 0x02, //
+            0xB4, 0x03, //
+            // The FIFO overflowed.
+            0x70, //
+            // A local timestamp in format 1 which is only complete with the next read.
+            0xC0, 0x81,
+        ]);
+        assert_eq!(
+            packets,
+            vec![
+                TracePacket::Sync,
+                TracePacket::LocalTimestamp {
+                    delta: 3,
+                    relation: 0,
+                },
+                TracePacket::LocalTimestamp {
+                    delta: 0x85,
+                    relation: 1,
+                },
+                TracePacket::GlobalTimestamp1 { value: 0x101 },
+                TracePacket::GlobalTimestamp2 { value: 3 },
+                TracePacket::Overflow,
+            ]
+        );
+
+        assert_eq!(
+            decoder.feed(&[0x01]),
+            vec![TracePacket::LocalTimestamp {
+                delta: 0x81,
+                relation: 0,
+            }]
+        );
+    }
+
+    #[test]
+    fn packets_are_encoded_with_their_timestamps() {
+        let packets = Decoder::new().feed(&[
+            // Port 1 writes 'A'.
+            0x09, 0x41, //
+            // A local timestamp of 0x81 ticks in format 1.
+            0xC0, 0x81, 0x01, //
+            // The DWT reports the PC with hardware source 2.
+            0x17, 0x00, 0x10, 0x00, 0x08, //
+            // A local timestamp of 3 ticks in format 2.
+            0x30, //
+            // Port 0 writes a halfword.
+            0x02, 0x68, 0x69,
+        ]);
+
+        let encoded = JsonEncoder::new().encode(&packets);
+        assert_eq!(
+            encoded,
+            vec![
+                JsonPacket {
+                    kind: "instrumentation",
+                    port: Some(1),
+                    payload: vec![0x41],
+                    timestamp: 0,
+                },
+                JsonPacket {
+                    kind: "hardware",
+                    port: Some(2),
+                    payload: vec![0x00, 0x10, 0x00, 0x08],
+                    timestamp: 0x81,
+                },
+                JsonPacket {
+                    kind: "instrumentation",
+                    port: Some(0),
+                    payload: vec![0x68, 0x69],
+                    timestamp: 0x84,
+                },
+            ]
+        );
+        assert_eq!(
+            serde_json::to_string(&encoded[0]).unwrap(),
+            r#"{"type":"instrumentation","port":1,"payload":[65],"timestamp":0}"#
+        );
+    }
+}
@@ -1,75 +1,99 @@
+mod artifact;
 mod config;
 mod diagnostics;
+mod doctor;
 mod flashing;
 mod gdb;
+mod incremental;
+mod itm;
 mod logging;
+mod nrf;
+mod power;
+mod semihosting;
+mod speed;
+mod uf2;
 mod updater;
 mod util;
+mod verify;
+mod watch;
+mod watchdog;
 
 use crate::config::Config;
 use crate::config::Configs;
+use crate::config::{ErrorFormat, FlashTarget, Format, ListFormat, VerifyMode};
 use anyhow::Result;
 use colored::*;
-use diagnostics::{render_diagnostics, RoverError};
+use diagnostics::{render_diagnostics, render_diagnostics_json, RoverError};
 use logging::run_logging;
 use std::{
-    fs::File,
+    panic,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Mutex,
+    },
+};
+use std::{
     path::{Path, PathBuf},
     process::{self},
     sync::Arc,
-    time::Instant,
+    time::{Duration, Instant},
 };
-use std::{panic, sync::Mutex};
 
 use probe_rs::{
-    config::TargetSelector,
-    flashing::ProgressEvent,
-    flashing::{BinOptions, Format},
-    DebugProbeSelector, FakeProbe, Probe,
+    architecture::Architecture, config::TargetSelector, flashing::ProgressEvent, DebugProbeError,
+    DebugProbeInfo, DebugProbeSelector, FakeProbe, Probe, Session, Target, WireProtocol,
 };
 
-use probe_rs_cli_util::{
-    argument_handling, build_artifact, logging as probe_rs_logging, logging::Metadata,
-};
+use probe_rs_cli_util::{build_artifact, logging as probe_rs_logging};
+use serde::Serialize;
+
+/// Information about the current run which is printed when rover crashes.
+#[derive(Debug, Clone, Serialize)]
+pub struct Metadata {
+    pub release: String,
+    pub chip: Option<String>,
+    pub probe: Option<String>,
+    /// The name the probe driver reports for the probe. For some probes this includes the hardware version.
+    pub probe_driver: Option<String>,
+    pub speed: Option<String>,
+    pub commit: String,
+}
 
 lazy_static::lazy_static! {
     static ref METADATA: Arc<Mutex<Metadata>> = Arc::new(Mutex::new(Metadata {
         release: util::PACKAGE_VERSION.to_string(),
         chip: None,
         probe: None,
+        probe_driver: None,
         speed: None,
         commit: git_version::git_version!(fallback = "crates.io").to_string(),
     }));
+    /// How errors are reported. Errors before the config is loaded are always printed for humans.
+    static ref ERROR_FORMAT: Mutex<ErrorFormat> = Mutex::new(ErrorFormat::Pretty);
+    /// Where a crash report is written to. Panics before the config is loaded are not reported.
+    static ref CRASH_REPORT_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
 }
 
-const ARGUMENTS_TO_REMOVE: &[&str] = &[
-    "general.chip=",
-    "general.chip-descriptions=",
-    "general.file=",
-    "general.format=",
-    "general.work-dir=",
-    "general.connect-under-reset",
-    "probe.speed=",
-    "probe.protocol=",
-    "probe.selector=",
-    "probe.usb-vid=",
-    "probe.usb.pid=",
-    "probe.serial=",
-    "flashing.enabled=",
-    "flashing.restore-unwritten",
-    "flashing.flash-layout=",
-    "reset.halt-afterwards",
-    "list-chips",
-    "list-probes",
-    "disable-progressbars",
-    "log-level=",
-    "dry-run",
-];
+/// What is written to `general.crash_report_path` when rover panics.
+#[derive(Debug, Serialize)]
+struct CrashReport {
+    #[serde(flatten)]
+    metadata: Metadata,
+    message: String,
+    location: Option<String>,
+    backtrace: String,
+}
+
+/// How long to wait for the core to halt after connecting.
+const HALT_TIMEOUT: Duration = Duration::from_millis(500);
 
 pub fn entry(uses_cargo: bool) {
     let next = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
         log::info!("{:#?}", &METADATA.lock().unwrap());
+        if let Some(path) = CRASH_REPORT_PATH.lock().unwrap().as_ref() {
+            write_crash_report(path, info);
+        }
         next(info);
     }));
 
@@ -78,13 +102,51 @@ pub fn entry(uses_cargo: bool) {
         Err(e) => {
             log::info!("{:#?}", &METADATA.lock().unwrap());
 
-            render_diagnostics(e);
+            let exit_code = e.exit_code();
+            match *ERROR_FORMAT.lock().unwrap() {
+                ErrorFormat::Pretty => render_diagnostics(e),
+                ErrorFormat::Json => render_diagnostics_json(e),
+            }
 
-            process::exit(1);
+            process::exit(exit_code);
         }
     }
 }
 
+/// Writes the metadata of the run along with the panic message and a backtrace to a JSON file.
+/// Failing to do so is only printed, as the panic itself is what needs reporting.
+fn write_crash_report(path: &Path, info: &panic::PanicInfo) {
+    let message = if let Some(message) = info.payload().downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = info.payload().downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "Box<Any>".to_string()
+    };
+
+    let report = CrashReport {
+        metadata: METADATA.lock().unwrap().clone(),
+        message,
+        location: info.location().map(|location| location.to_string()),
+        backtrace: format!("{:?}", backtrace::Backtrace::new()),
+    };
+
+    let result = std::fs::File::create(path)
+        .map_err(anyhow::Error::from)
+        .and_then(|file| Ok(serde_json::to_writer_pretty(file, &report)?));
+    match result {
+        Ok(()) => eprintln!(
+            "A crash report was written to '{}'. Please attach it when reporting this bug.",
+            path.display()
+        ),
+        Err(error) => eprintln!(
+            "Failed to write the crash report to '{}': {}",
+            path.display(),
+            error
+        ),
+    }
+}
+
 fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
     let args = std::env::args();
 
@@ -98,23 +160,50 @@ fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
         args.remove(1);
     }
 
-    // TODO: Select name from commandline.
-    let config = Configs::try_new("default", &args).unwrap();
+    // The default config is used unless another one is selected with `--config`.
+    let config = Configs::try_new("default", &args).map_err(RoverError::FailedToLoadConfig)?;
+    *ERROR_FORMAT.lock().unwrap() = config.error_format();
+    *CRASH_REPORT_PATH.lock().unwrap() = config.general().crash_report_path().clone();
+
+    // Colors are noise in files and CI logs, see https://no-color.org.
+    // Without a terminal on stdout, `colored` already leaves them out on its own.
+    if config.no_color() || std::env::var_os("NO_COLOR").is_some() {
+        colored::control::set_override(false);
+    }
 
     // If the user instructed us to show the version, show the different info about the binary.
     if config.version() {
-        util::print_version();
+        util::print_version(config.version_format());
+        return Ok(());
+    }
+
+    // If the user wants to see the effective config, print it before anything is done with it.
+    if config.dump_config() {
+        serde_yaml::to_writer(std::io::stdout(), &config)
+            .map_err(RoverError::FailedToDumpConfig)?;
+        println!();
         return Ok(());
     }
 
     probe_rs_logging::init(Some(config.general().log_level()));
 
+    // If the user wants to check their setup, do that and exit.
+    if config.doctor() {
+        return doctor::run_doctor();
+    }
+
     // If someone wants to list the connected probes, just do that and exit.
     if config.list_probes() {
         list_connected_probes();
         return Ok(());
     }
 
+    // The channels are parsed along with the config, so listing them needs neither a build nor the probe.
+    if config.list_channels() {
+        print_channels(config.logging());
+        return Ok(());
+    }
+
     // Load the target description given in the cli parameters.
     for cdp in config.general().chip_descriptions() {
         probe_rs::config::add_target_from_yaml(&Path::new(cdp)).map_err(|error| {
@@ -127,26 +216,48 @@ fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
 
     // If we were instructed to list all available chips, print a list of all the available targets to the commandline.
     if config.list_chips() {
-        print_families()?;
+        print_families(
+            config.list_chips_format(),
+            config.list_chips_filter().as_deref(),
+        )?;
         return Ok(());
     }
 
-    // Determine what chip to use. If none was set in the config or the commandline, use auto.
-    let chip = if let Some(chip) = &config.general().chip() {
-        chip.into()
-    } else {
-        TargetSelector::Auto
-    };
+    // Several targets with their own probes are flashed on their own, without building or any of the services.
+    if !config.flashing().targets().is_empty() {
+        return flash_targets(config);
+    }
+
+    // Resolve the chip name of the config to a single chip.
+    // If it is ambiguous, the user gets to pick one of the matching chips.
+    let chip_name = config
+        .general()
+        .chip()
+        .as_deref()
+        .map(resolve_chip)
+        .transpose()?;
 
     // Store the chip name in the metadata stuct so we can print it as debug information when cargo-flash crashes.
-    METADATA.lock().unwrap().chip = Some(format!("{:?}", chip));
+    // An auto detected chip is recorded once it is known after attaching.
+    METADATA.lock().unwrap().chip = chip_name.clone();
 
-    // Always remove the first argument as it is the executable name (cargo-flash) and we don't need that.
-    // We cannot do this at the start as structopt will discard the first argument in it's internal parser so it needs to be present.
-    args.remove(0);
+    // Resetting needs neither a build nor a flashloader, so it is done right away.
+    if config.reset_only() {
+        return reset_only(&config, chip_name.as_deref());
+    }
 
-    // Remove all arguments that `cargo build` does not understand.
-    argument_handling::remove_arguments(ARGUMENTS_TO_REMOVE, &mut args);
+    // Leaving the target undisturbed rules out everything which resets, halts or writes to it.
+    if config.general().attach_running() {
+        if let Some(option) = attach_running_conflict(&config) {
+            return Err(RoverError::AttachRunningConflict {
+                option: option.to_string(),
+            });
+        }
+    }
+
+    // The `cargo build` invocation is built from the parsed config rather than the raw arguments,
+    // so the cargo options given in config files and profiles are honored as well.
+    let cargo_args = config.cargo_build_args();
 
     // Change the work dir if the user asked to do so. Otherwise use the current working directory
     let work_dir = PathBuf::from(if let Some(work_dir) = config.general().work_dir() {
@@ -163,23 +274,32 @@ fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
         dunce::canonicalize(".").unwrap()
     });
 
-    // Get the path to the ELF binary we want to flash.
-    // This can either be give from the arguments or can be a cargo build artifact.
-    let (path, format): (PathBuf, Format) = if let Some(path) = config.general().file() {
-        (
-            path.into(),
-            match config.general().format() {
-                Format::Bin(_) => Format::Bin(BinOptions {
-                    base_address: config.general().format_base_address(),
-                    skip: config.general().format_skip().unwrap_or(0),
-                }),
-                f => f,
-            },
-        )
+    // Get the paths to the binaries we want to flash.
+    // These can either be given from the arguments or can be a cargo build artifact.
+    let images: Vec<(PathBuf, Format)> = if !config.general().file().is_empty() {
+        config
+            .general()
+            .file()
+            .iter()
+            .enumerate()
+            .map(|(i, path)| (path.into(), image_format(&config, i, Path::new(path))))
+            .collect()
+    } else if !config.flashing().regions().is_empty() {
+        // The regions are flashed instead of the firmware, so there is nothing to build.
+        config
+            .flashing()
+            .regions()
+            .iter()
+            .map(flashing::region_image)
+            .collect()
+    } else if config.no_build() {
+        let path = artifact::find_artifact(&work_dir, &config)?;
+        log::info!("Using the prebuilt artifact {}", path.display());
+        vec![(path, Format::Elf)]
     } else {
         // Build the project, and extract the path of the built artifact.
-        (
-            build_artifact(&work_dir, &args).map_err(|error| {
+        vec![(
+            build_artifact(&work_dir, &cargo_args).map_err(|error| {
                 if let Some(ref work_dir) = config.general().work_dir() {
                     RoverError::FailedToBuildExternalCargoProject {
                         source: error,
@@ -195,106 +315,178 @@ fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
                 }
             })?,
             Format::Elf,
-        )
+        )]
     };
 
-    // Create a data buffer to be used by the flashloader.
-    let mut data_buffer = Vec::new();
+    // The artifacts of cargo are ELF files, so the options for binary files do not apply to them.
+    if config.general().file().is_empty() {
+        if let Some(ignored) = config.general().ignored_bin_options(0, &Format::Elf) {
+            log::warn!("{}", ignored);
+        }
+    }
+
+    // The first binary is the one the logging refers to.
+    let path = images[0].0.clone();
 
-    // Try to open the firmware file.
-    let mut file = File::open(&path).map_err(|error| RoverError::FailedToOpenElf {
-        source: error,
-        path: format!("{}", path.display()),
-    })?;
+    // Without a chip in the config, the firmware might name the chip it was built for.
+    let chip_name = match chip_name {
+        Some(chip_name) => {
+            log::info!("Using the chip {} from the config", chip_name);
+            Some(chip_name)
+        }
+        None => match elf_chip(&images) {
+            Some((chip, elf_path)) => {
+                log::info!(
+                    "Using the chip {} from the .probe-rs section of {}",
+                    chip,
+                    elf_path.display()
+                );
+                let chip = resolve_chip(&chip)?;
+                METADATA.lock().unwrap().chip = Some(chip.clone());
+                Some(chip)
+            }
+            None => {
+                log::info!("No chip was given, so it is detected automatically");
+                None
+            }
+        },
+    };
+
+    // Create the data buffers to be used by the flashloader.
+    let mut data_buffers = vec![Vec::new(); images.len()];
+
+    // Try to open the firmware files.
+    let mut files = flashing::open_images(&images)?;
 
-    println!("NAME: {:?}", &config.general().chip());
+    // Stdout is parsed by scripts, so this is only logged.
+    log::debug!("Selected chip {:?}", chip_name);
 
     // If we know our target yet (given by the commandline), try and create a flashloader with the firmware data.
     // If we do not know the target yet, try and auto detect and create the flashloader lateron.
-    let (target_selector, flash_loader) = if let Some(chip_name) = &config.general().chip() {
-        let target = probe_rs::config::get_target_by_name(chip_name).map_err(|error| {
-            RoverError::ChipNotFound {
-                source: error,
-                name: chip_name.clone(),
-            }
-        })?;
+    let (target_selector, flash_loader) = if let Some(chip_name) = &chip_name {
+        let target = specified_target(&config, chip_name)?;
 
-        let loader = flashing::build_flashloader(
-            &target,
-            &mut file,
-            &format,
-            &mut data_buffer,
-            config.flashing().restore_unwritten_bytes(),
-        )?;
-        (TargetSelector::Specified(target), Some(loader))
+        // The flash regions are loaded once the session is open as their files are only needed for flashing.
+        // In incremental mode, the loader is only built from the changed data once the flash was read back.
+        let loader = if config.flashing().regions().is_empty() && !config.flashing().incremental() {
+            Some(flashing::build_flashloader(
+                &target,
+                &images,
+                &mut files,
+                &mut data_buffers,
+                config.flashing().restore_unwritten_bytes(),
+                config.flashing().flash_range(),
+            )?)
+        } else {
+            None
+        };
+        (TargetSelector::Specified(target), loader)
     } else {
         (TargetSelector::Auto, None)
     };
 
-    // Try and prepare the probe by opening the probe and selecting the given protocol.
-    let mut probe = open_probe(&config)?;
-    probe
-        .select_protocol(config.probe().protocol())
-        .map_err(|error| RoverError::FailedToSelectProtocol {
-            source: error,
-            protocol: config.probe().protocol(),
-        })?;
-
-    // Set the protocol speed if some specific speed was given.
-    // Return the actual speed the probe has set afterwards.
-    // This can deviate from the speed we set as some probes just allow for a set of values and chose the closest one.
-    let protocol_speed = if let Some(speed) = config.probe().speed() {
-        let actual_speed =
-            probe
-                .set_speed(speed)
-                .map_err(|error| RoverError::FailedToSelectProtocolSpeed {
-                    source: error,
-                    speed,
-                })?;
+    // A known chip is enough to export the image, so it is exported before the probe is opened and works without one.
+    if let (Some(export_path), TargetSelector::Specified(target)) =
+        (config.export_image(), &target_selector)
+    {
+        flashing::export_image(target, &images, &config, export_path)?;
+    }
 
-        if actual_speed < speed {
-            log::warn!(
-                "Unable to use specified speed of {} kHz, actual speed used is {} kHz",
-                speed,
-                actual_speed
-            );
+    // The recovery sequence is chip specific, so it is checked before touching the probe.
+    let nrf_recover_chip = if config.nrf_recover() {
+        match &chip_name {
+            Some(chip) if nrf::is_supported(chip) => Some(chip.clone()),
+            chip => {
+                return Err(RoverError::NrfRecoverUnsupported {
+                    chip: chip.clone().unwrap_or_else(|| "auto".to_string()),
+                })
+            }
         }
-
-        actual_speed
     } else {
-        probe.speed_khz()
+        None
     };
+
+    // Try and prepare the probe by opening the probe and selecting the given protocol.
+    let mut probe = open_probe(&config)?;
+    // Store the probe driver in the metadata struct to be able to print it in case of a crash.
+    // Some flash failures are specific to a probe (version), so this makes reports actionable.
+    METADATA.lock().unwrap().probe_driver = Some(probe.get_name());
+    let protocol_speed = configure_probe(&mut probe, &config)?;
     // Store the speed in the metadata struct to be able to print it in case of a crash.
     METADATA.lock().unwrap().speed = Some(format!("{:?}", protocol_speed));
 
     // Log the probe speed.
     log::info!("Protocol speed {} kHz", protocol_speed);
 
+    // A locked chip cannot be attached to, so it is erased through the CTRL-AP first.
+    if let Some(chip) = &nrf_recover_chip {
+        probe_rs_logging::println(format!("    {} {}", "Recovering".green().bold(), chip));
+        probe = nrf::recover(probe, chip)?;
+    }
+
     // Create a new session.
     // If we wanto attach under reset, we do this with a special function call.
     // In this case we assume the target to be known.
     // If we do an attach without a hard reset, we also try to automatically detect the chip at hand to improve the userexperience.
-    let mut session = if config.general().connect_under_reset() {
-        probe.attach_under_reset(target_selector)
-    } else {
-        probe.attach(target_selector)
+    let mut session = attach(probe, target_selector, &config)?;
+    METADATA.lock().unwrap().chip = Some(session.target().name.clone());
+
+    // Stop the core as it is, e.g. to inspect a hung target, before anything else touches it.
+    if config.general().halt_after_connect() {
+        let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+        core.halt(HALT_TIMEOUT)
+            .map_err(RoverError::TargetHaltFailed)?;
+        log::info!("Halted the core after connecting.");
     }
-    .map_err(|error| RoverError::AttachingFailed {
-        source: error,
-        connect_under_reset: config.general().connect_under_reset(),
-    })?;
 
-    if config.flashing().enabled() {
+    // An auto-detected chip is only known now. The image is still exported before anything is flashed.
+    if let (Some(export_path), None) = (config.export_image(), &chip_name) {
+        flashing::export_image(session.target(), &images, &config, export_path)?;
+    }
+
+    // Verifying only leaves the target as it is, so nothing else is done afterwards.
+    if config.verify_only() {
+        return flashing::run_verify_only(&mut session, &images, &config);
+    }
+
+    // A ready file left over from a previous run must not signal readiness before this run is up.
+    if let Some(ready_file) = config.ready_file() {
+        let _ = std::fs::remove_file(ready_file);
+    }
+
+    // A running target is only observed, so nothing is written to it and it is not reset.
+    let attach_running = config.general().attach_running();
+    if attach_running {
+        log::info!("Attached to the running target. Skipping flashing and reset.");
+    }
+
+    // Option bytes and peripheral registers some boards need are written before the main flash.
+    if !config.pre_write().is_empty() && !config.dry_run() {
+        flashing::run_pre_writes(&mut session, config.pre_write())?;
+    }
+
+    if config.flashing().enabled() && !attach_running {
         // Start the timer to measure how long flashing took.
         let instant = Instant::now();
 
-        probe_rs_logging::println(format!(
-            "    {} {}",
-            "Flashing".green().bold(),
-            path.display()
-        ));
+        let flashed = if !config.flashing().regions().is_empty() {
+            format!("{} regions", config.flashing().regions().len())
+        } else if images.len() > 1 {
+            format!("{} files", images.len())
+        } else {
+            format!("{}", path.display())
+        };
+        probe_rs_logging::println(format!("    {} {}", "Flashing".green().bold(), flashed));
 
-        flashing::run_flash_download(&mut session, &path, &format, &config, flash_loader)?;
+        flashing::run_flash_cycles(
+            &mut session,
+            &images,
+            config.flashing().regions(),
+            &config,
+            flash_loader,
+            true,
+            protocol_speed,
+        )?;
         // .map_err(|e| handle_flash_error(e, session.target(), opt.chip.as_deref()))?;
 
         // Stop timer.
@@ -306,50 +498,339 @@ fn main_try(_uses_cargo: bool) -> Result<(), RoverError> {
         ));
     }
 
-    if config.reset().enabled() {
-        let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
-        if config.reset().halt_afterwards() {
-            core.reset_and_halt(std::time::Duration::from_millis(500))
-                .map_err(RoverError::TargetResetFailed)?;
-        } else {
-            core.reset().map_err(RoverError::TargetResetHaltFailed)?;
-        }
+    if config.reading().enabled() {
+        // Read the memory after flashing so the dump contains what was just written.
+        let range = config.reading().range();
+        probe_rs_logging::println(format!(
+            "    {} {}",
+            "Reading".green().bold(),
+            range
+                .map(|range| format!("{:#010x}..{:#010x}", range.start, range.end))
+                .unwrap_or_default(),
+        ));
+
+        flashing::run_flash_read(&mut session, &config)?;
+    }
+
+    if config.reset().enabled() && !attach_running {
+        let elf = match images[0].1 {
+            Format::Elf => Some(path.as_path()),
+            _ => None,
+        };
+        reset_target(&mut session, &config, elf)?;
     }
 
     let session = Arc::new(Mutex::new(session));
 
-    let mut handles = vec![];
+    // Signals all the running services to shut down.
+    let halt = Arc::new(AtomicBool::new(false));
 
-    if config.gdb().enabled() {
-        let link = config.gdb().socket().clone();
-        let session = session.clone();
-        handles.push(gdb::run_gdb(session, link));
+    if config.gdb().enabled() || config.logging().enabled() || config.watch() {
+        let halt = halt.clone();
+        if let Err(error) = ctrlc::set_handler(move || {
+            log::info!("Shutting down.");
+            halt.store(true, Ordering::Relaxed);
+        }) {
+            log::warn!("Failed to install the Ctrl-C handler: {}", error);
+        }
     }
 
+    // Signals logging to attach to RTT again after a reflash.
+    let reattach = Arc::new(AtomicBool::new(false));
+
+    let mut handles = vec![];
+    let (ready_sender, ready_receiver) = mpsc::channel();
+
+    let gdb_handle = if config.gdb().enabled() {
+        Some(gdb::run_gdb(
+            session.clone(),
+            config.gdb().socket().clone(),
+            config.gdb().bind_address().clone(),
+            config.gdb().port(),
+            halt.clone(),
+            ready_sender.clone(),
+        ))
+    } else {
+        None
+    };
+
     if config.logging().enabled() {
         handles.push(run_logging(
-            session,
+            session.clone(),
             path,
-            config.logging().channels().clone(),
+            config.logging().clone(),
+            protocol_speed,
+            config.gdb().enabled(),
+            halt.clone(),
+            reattach.clone(),
+            ready_sender,
         )?);
+    } else {
+        drop(ready_sender);
+    }
+
+    if let Some(ready_file) = config.ready_file() {
+        // Wait until GDB is listening and logging is attached. If a service ends before, nothing is ready.
+        let services = config.gdb().enabled() as usize + config.logging().enabled() as usize;
+        if services > 0 {
+            if ready_receiver.iter().take(services).count() < services {
+                log::warn!("A service stopped before it was ready. Not writing the ready file.");
+            } else {
+                write_ready_file(ready_file, &config)?;
+            }
+        }
+    }
+
+    // Reflash on every change until a shutdown is requested. The services keep running meanwhile.
+    if config.watch() {
+        if config.general().file().is_empty() && config.flashing().regions().is_empty() {
+            watch::run_watch(
+                session,
+                &config,
+                &work_dir,
+                &cargo_args,
+                protocol_speed,
+                &halt,
+                &reattach,
+            )?;
+        } else {
+            log::warn!("Only cargo projects can be watched. Not watching the given files.");
+        }
+    }
+
+    // Keep running until all the services have shut down.
+    // A panic in one of the services is forwarded so it gets reported like any other panic.
+    // The GDB stub is waited for last, as a failing logging link is reported right away while GDB keeps running.
+    for handle in handles.into_iter().chain(gdb_handle) {
+        match handle.join() {
+            Ok(result) => result?,
+            Err(panic) => panic::resume_unwind(panic),
+        }
     }
 
     Ok(())
 }
 
+/// The contents of the ready file.
+#[derive(Debug, Serialize)]
+struct Ready {
+    pid: u32,
+    gdb: Option<String>,
+    logging: bool,
+}
+
+/// Writes the ready file which signals orchestration scripts that GDB and logging are up.
+fn write_ready_file(path: &Path, config: &Config) -> Result<(), RoverError> {
+    let ready = Ready {
+        pid: process::id(),
+        gdb: if config.gdb().enabled() {
+            Some(gdb::gdb_link(
+                config.gdb().socket().as_deref(),
+                config.gdb().bind_address().as_deref(),
+                config.gdb().port(),
+            ))
+        } else {
+            None
+        },
+        logging: config.logging().enabled(),
+    };
+    // This unwrap is fine as the struct only contains plain values.
+    let content = serde_json::to_string(&ready).unwrap();
+    std::fs::write(path, content + "\n").map_err(|error| RoverError::FailedToWriteReadyFile {
+        source: error,
+        path: format!("{}", path.display()),
+    })
+}
+
+/// Looks up the chip with the given name and checks that it can be reached with the configured protocol,
+/// so attaching does not fail in an obscure way. Attaching fails anyways otherwise, so the check is skipped in fast mode.
+fn specified_target(config: &Config, chip_name: &str) -> Result<Target, RoverError> {
+    let target = probe_rs::config::get_target_by_name(chip_name).map_err(|error| {
+        RoverError::ChipNotFound {
+            source: error,
+            name: chip_name.to_string(),
+        }
+    })?;
+    if !config.fast() {
+        validate_protocol(&target, config.probe().protocol())?;
+    }
+    Ok(target)
+}
+
+/// Checks that the given protocol can be used to debug the target.
+fn validate_protocol(target: &Target, protocol: WireProtocol) -> Result<(), RoverError> {
+    // The chip descriptions do not declare the protocols of a chip, so only what holds for the whole architecture is checked.
+    // SWD is an ARM protocol, so RISC-V targets are only debugged via JTAG. Whether an ARM chip supports JTAG
+    // besides SWD depends on the chip, so the protocol is not checked for them.
+    let supported = match target.architecture() {
+        Architecture::Arm => return Ok(()),
+        Architecture::Riscv => vec![WireProtocol::Jtag],
+    };
+
+    if supported.contains(&protocol) {
+        Ok(())
+    } else {
+        Err(RoverError::UnsupportedProtocol {
+            chip: target.name.clone(),
+            protocol,
+            supported,
+        })
+    }
+}
+
+/// Reads the chip name from the `.probe-rs` section of the first ELF file among the images.
+/// Returns the chip name along with the file it was read from.
+fn elf_chip(images: &[(PathBuf, Format)]) -> Option<(String, &Path)> {
+    let (path, _) = images
+        .iter()
+        .find(|(_, format)| matches!(format, Format::Elf))?;
+    let content = std::fs::read(path).ok()?;
+    let chip = verify::chip_from_elf(&content)?;
+    Some((chip, path.as_path()))
+}
+
+/// Resolves the given chip name to a single chip.
+/// If the name matches multiple chips, the user is asked to select one of them when running interactively.
+fn resolve_chip(name: &str) -> Result<String, RoverError> {
+    let matching_chips =
+        probe_rs::config::search_chips(name).map_err(|error| RoverError::ChipNotFound {
+            source: error,
+            name: name.to_string(),
+        })?;
+
+    if matching_chips.len() <= 1
+        || matching_chips
+            .iter()
+            .any(|chip| chip.eq_ignore_ascii_case(name))
+    {
+        return Ok(name.to_string());
+    }
+
+    if !atty::is(atty::Stream::Stdin) || !atty::is(atty::Stream::Stderr) {
+        return Err(RoverError::AmbiguousChip {
+            name: name.to_string(),
+            matching_chips,
+        });
+    }
+
+    probe_rs_logging::eprintln(format!(
+        "The chip '{}' matches multiple possible targets:",
+        name
+    ));
+    for (i, chip) in matching_chips.iter().enumerate() {
+        probe_rs_logging::eprintln(format!("[{}]: {}", i, chip));
+    }
+
+    loop {
+        eprint!("Select a chip [0-{}]: ", matching_chips.len() - 1);
+        let mut input = String::new();
+        match std::io::stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => {
+                return Err(RoverError::AmbiguousChip {
+                    name: name.to_string(),
+                    matching_chips,
+                })
+            }
+            Ok(_) => match input.trim().parse::<usize>() {
+                Ok(i) if i < matching_chips.len() => return Ok(matching_chips[i].clone()),
+                _ => probe_rs_logging::eprintln("Invalid selection."),
+            },
+        }
+    }
+}
+
+/// A chip family as it is listed with `--list-chips-format json`.
+#[derive(Debug, Serialize)]
+struct FamilyListing {
+    family: String,
+    variants: Vec<String>,
+}
+
 /// Print all the available families and their contained chips to the commandline.
-fn print_families() -> Result<(), RoverError> {
-    probe_rs_logging::println("Available chips:");
+fn print_families(format: ListFormat, filter: Option<&str>) -> Result<(), RoverError> {
+    let filter = filter.map(str::to_lowercase);
+    let matches = |name: &str| {
+        filter
+            .as_ref()
+            .map_or(true, |filter| name.to_lowercase().contains(filter))
+    };
+
+    // If the family matches, all of its variants are listed. Otherwise only the matching variants are.
+    let mut listings = vec![];
     for family in probe_rs::config::families().map_err(RoverError::FailedToReadFamilies)? {
-        probe_rs_logging::println(&family.name);
-        probe_rs_logging::println("    Variants:");
-        for variant in family.variants() {
-            probe_rs_logging::println(format!("        {}", variant.name));
+        let family_matches = matches(&family.name);
+        let variants = family
+            .variants()
+            .iter()
+            .map(|variant| variant.name.clone())
+            .filter(|name| family_matches || matches(name))
+            .collect::<Vec<_>>();
+        if family_matches || !variants.is_empty() {
+            listings.push(FamilyListing {
+                family: family.name.clone(),
+                variants,
+            });
+        }
+    }
+
+    match format {
+        ListFormat::Text => {
+            probe_rs_logging::println("Available chips:");
+            for listing in listings {
+                probe_rs_logging::println(&listing.family);
+                probe_rs_logging::println("    Variants:");
+                for variant in listing.variants {
+                    probe_rs_logging::println(format!("        {}", variant));
+                }
+            }
+        }
+        ListFormat::Json => {
+            // This unwrap is fine as the listing only contains strings.
+            println!("{}", serde_json::to_string_pretty(&listings).unwrap());
         }
     }
     Ok(())
 }
 
+/// Lists the configured logging channels as a table with a row for each of their kinds.
+fn print_channels(logging: &config::Logging) {
+    if logging.channels().is_empty() {
+        probe_rs_logging::println(
+            "No logging channels are configured. All RTT up channels are printed to stdout.",
+        );
+    } else {
+        let header = ["Channel", "Source", "Mode", "Link", "Target"];
+        let mut rows = vec![header.iter().map(|h| h.to_string()).collect::<Vec<_>>()];
+        for (index, channel) in logging.channels().iter().enumerate() {
+            for kind in channel.kinds() {
+                rows.push(vec![
+                    index.to_string(),
+                    kind.source(),
+                    kind.mode().unwrap_or_else(|| "-".into()),
+                    channel.link().name().to_string(),
+                    channel.link().target(),
+                ]);
+            }
+        }
+
+        let widths = (0..header.len())
+            .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+            .collect::<Vec<_>>();
+        for row in rows {
+            let cells = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>();
+            probe_rs_logging::println(cells.join("  ").trim_end());
+        }
+    }
+
+    if !logging.enabled() {
+        probe_rs_logging::println("Logging is disabled. Enable it with `logging.enabled`.");
+    }
+}
+
 /// Lists all connected debug probes.
 fn list_connected_probes() {
     let probes = Probe::list_all();
@@ -365,6 +846,336 @@ fn list_connected_probes() {
     }
 }
 
+/// The format of the image with the given index, including the options given for it.
+/// Warns about the options for binary files which do not apply to the image.
+fn image_format(config: &Config, index: usize, path: &Path) -> Format {
+    let format = config.general().image_format(index, path);
+    if let Some(ignored) = config.general().ignored_bin_options(index, &format) {
+        log::warn!("{}", ignored);
+    }
+    format
+}
+
+/// Selects the configured protocol and speed on the probe.
+/// Returns the actual speed the probe uses.
+fn configure_probe(probe: &mut Probe, config: &Config) -> Result<u32, RoverError> {
+    probe
+        .select_protocol(config.probe().protocol())
+        .map_err(|error| RoverError::FailedToSelectProtocol {
+            source: error,
+            protocol: config.probe().protocol(),
+        })?;
+
+    // Set the protocol speed if some specific speed was given.
+    // Return the actual speed the probe has set afterwards.
+    // This can deviate from the speed we set as some probes just allow for a set of values and chose the closest one.
+    if let Some(speed) = config.probe().speed() {
+        // Speeds far outside of what the probe supports are rejected instead of running at an unexpected clock.
+        if let Some(supported) = speed::supported_speeds(&probe.get_name()) {
+            speed::check(supported, speed).map_err(|nearest| {
+                RoverError::FailedToSelectProtocolSpeed {
+                    source: DebugProbeError::UnsupportedSpeed(speed),
+                    speed,
+                    nearest,
+                }
+            })?;
+        }
+
+        let actual_speed =
+            probe
+                .set_speed(speed)
+                .map_err(|error| RoverError::FailedToSelectProtocolSpeed {
+                    source: error,
+                    speed,
+                    nearest: vec![],
+                })?;
+
+        // This is only informational, so it is skipped in fast mode.
+        if actual_speed < speed && !config.fast() {
+            log::warn!(
+                "Unable to use specified speed of {} kHz, actual speed used is {} kHz",
+                speed,
+                actual_speed
+            );
+        }
+
+        Ok(actual_speed)
+    } else {
+        Ok(probe.speed_khz())
+    }
+}
+
+/// Attaches to the target, under reset if configured.
+fn attach(
+    mut probe: Probe,
+    target_selector: impl Into<TargetSelector>,
+    config: &Config,
+) -> Result<Session, RoverError> {
+    let connect_under_reset = config.general().connect_under_reset();
+    let session = if connect_under_reset {
+        // Attaching asserts the reset itself, so asserting it early only extends the reset.
+        if let Some(assert_ms) = config.general().connect_under_reset_assert_ms() {
+            probe
+                .target_reset_assert()
+                .map_err(|error| RoverError::AttachingFailed {
+                    source: error.into(),
+                    connect_under_reset,
+                })?;
+            std::thread::sleep(Duration::from_millis(assert_ms));
+        }
+        probe.attach_under_reset(target_selector)
+    } else {
+        probe.attach(target_selector)
+    };
+    session.map_err(|error| RoverError::AttachingFailed {
+        source: error,
+        connect_under_reset,
+    })
+}
+
+/// Returns the first configured option which would disturb a target `general.attach_running` leaves running.
+fn attach_running_conflict(config: &Config) -> Option<&'static str> {
+    if config.general().connect_under_reset() {
+        Some("general.connect_under_reset")
+    } else if config.general().halt_after_connect() {
+        Some("general.halt_after_connect")
+    } else if config.nrf_recover() {
+        Some("nrf_recover")
+    } else if !config.pre_write().is_empty() {
+        Some("pre_write")
+    } else if config.watch() {
+        Some("watch")
+    } else if config.verify_only() && config.flashing().verify() == VerifyMode::Crc {
+        // The CRC is computed by code which is loaded into the RAM of the target and run there.
+        Some("flashing.verify: crc")
+    } else if config.logging().reset_afterwards() {
+        Some("logging.reset_afterwards")
+    } else {
+        None
+    }
+}
+
+/// Resets the target and halts it afterwards if configured.
+fn reset_target(
+    session: &mut Session,
+    config: &Config,
+    elf: Option<&Path>,
+) -> Result<(), RoverError> {
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+    if config.reset().halt_afterwards() {
+        let timeout = Duration::from_millis(config.reset().halt_timeout_ms());
+        core.reset_and_halt(timeout)
+            .map_err(RoverError::TargetResetHaltFailed)?;
+
+        if config.reset().run_to_main() {
+            let main = elf
+                .and_then(|elf| std::fs::File::open(elf).ok())
+                .and_then(|mut file| logging::get_symbol(&mut file, "main"));
+            match main {
+                Some(address) => {
+                    // The lowest bit of Thumb function symbols is set, but the instruction starts at the even address.
+                    let address = address as u32 & !1;
+                    log::info!("Running to main at {:#010x}", address);
+                    core.set_hw_breakpoint(address)
+                        .map_err(RoverError::RunToMainFailed)?;
+                    let run_to_main_timeout =
+                        Duration::from_millis(config.reset().run_to_main_timeout_ms());
+                    let reached = core
+                        .run()
+                        .and_then(|_| core.wait_for_core_halted(run_to_main_timeout));
+                    // The breakpoint is cleared even if `main` was not reached, so it is not left behind for GDB or a later run.
+                    let cleared = core.clear_hw_breakpoint(address);
+                    reached.and(cleared).map_err(RoverError::RunToMainFailed)?;
+                }
+                None => log::warn!(
+                    "No `main` symbol was found in the ELF file. The target stays halted after the reset."
+                ),
+            }
+        }
+    } else {
+        core.reset().map_err(RoverError::TargetResetFailed)?;
+    }
+    Ok(())
+}
+
+/// Attaches to the target and resets it according to the reset config without building or flashing anything.
+fn reset_only(config: &Config, chip_name: Option<&str>) -> Result<(), RoverError> {
+    let target_selector = if let Some(chip_name) = chip_name {
+        TargetSelector::Specified(specified_target(config, chip_name)?)
+    } else {
+        TargetSelector::Auto
+    };
+
+    let mut probe = open_probe(config)?;
+    METADATA.lock().unwrap().probe_driver = Some(probe.get_name());
+    let protocol_speed = configure_probe(&mut probe, config)?;
+    METADATA.lock().unwrap().speed = Some(format!("{:?}", protocol_speed));
+
+    let mut session = attach(probe, target_selector, config)?;
+
+    // Nothing is built, so `main` can only be looked up in a given ELF file.
+    let elf = config
+        .general()
+        .file()
+        .first()
+        .map(PathBuf::from)
+        .filter(|path| matches!(image_format(config, 0, path), Format::Elf));
+    probe_rs_logging::println(format!("    {} target", "Resetting".green().bold()));
+    reset_target(&mut session, config, elf.as_deref())
+}
+
+/// Flashes all the configured targets concurrently, each with its own probe.
+/// All targets are flashed even if some of them fail. The failures are reported together.
+fn flash_targets(config: Config) -> Result<(), RoverError> {
+    let targets = config.flashing().targets().to_vec();
+
+    // The probes are all opened upfront, so a missing probe is reported before any target is touched.
+    let selectors = targets
+        .iter()
+        .map(|target| target.probe.clone())
+        .collect::<Vec<_>>();
+    let probes = open_probes(&config, &selectors)?;
+
+    let instant = Instant::now();
+    probe_rs_logging::println(format!(
+        "    {} {} targets",
+        "Flashing".green().bold(),
+        targets.len()
+    ));
+
+    let config = Arc::new(config);
+    let handles = targets
+        .into_iter()
+        .zip(probes)
+        .map(|(target, probe)| {
+            let config = config.clone();
+            let name = format!("{} ({})", target.chip, target.file);
+            (
+                name,
+                std::thread::spawn(move || flash_target(&target, probe, &config)),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut failures = vec![];
+    for (name, handle) in handles {
+        match handle.join() {
+            Ok(Ok(())) => {
+                probe_rs_logging::println(format!("    {} {}", "Finished".green().bold(), name))
+            }
+            Ok(Err(error)) => failures.push((name, error)),
+            Err(panic) => panic::resume_unwind(panic),
+        }
+    }
+
+    if !failures.is_empty() {
+        return Err(RoverError::FailedToFlashTargets { failures });
+    }
+
+    probe_rs_logging::println(format!(
+        "    {} in {}s",
+        "Finished".green().bold(),
+        instant.elapsed().as_millis() as f32 / 1000.0,
+    ));
+    Ok(())
+}
+
+/// Flashes a single target of `flashing.targets` with the given probe.
+/// Only the file of the target is flashed and verified. The `flashing.regions` of the config are not applied to it.
+fn flash_target(target: &FlashTarget, mut probe: Probe, config: &Config) -> Result<(), RoverError> {
+    let chip = specified_target(config, &target.chip)?;
+    let protocol_speed = configure_probe(&mut probe, config)?;
+    let mut session = attach(probe, TargetSelector::Specified(chip), config)?;
+
+    let path = PathBuf::from(&target.file);
+    let images = vec![(path.clone(), image_format(config, 0, &path))];
+    flashing::run_flash_cycles(
+        &mut session,
+        &images,
+        &[],
+        config,
+        None,
+        false,
+        protocol_speed,
+    )?;
+
+    if config.reset().enabled() {
+        let elf = match images[0].1 {
+            Format::Elf => Some(images[0].0.as_path()),
+            _ => None,
+        };
+        reset_target(&mut session, config, elf)?;
+    }
+    Ok(())
+}
+
+/// Opens the debug probes matching the given selectors, in the same order.
+/// Each probe is retried as configured for a single probe.
+fn open_probes(
+    config: &Config,
+    selectors: &[DebugProbeSelector],
+) -> Result<Vec<Probe>, RoverError> {
+    selectors
+        .iter()
+        .map(|selector| {
+            if config.dry_run() {
+                Ok(Probe::from_specific_probe(Box::new(FakeProbe::new())))
+            } else {
+                open_with_retries(config, || Probe::open(selector.clone()))
+            }
+        })
+        .collect()
+}
+
+/// Opens a probe, retrying as often as configured because some USB hubs fail the first attempts right after plugging in.
+fn open_with_retries(
+    config: &Config,
+    open: impl Fn() -> Result<Probe, DebugProbeError>,
+) -> Result<Probe, RoverError> {
+    let retries = config.probe().open_retries();
+    let delay = Duration::from_millis(config.probe().open_retry_delay_ms());
+    let mut attempt = 0;
+    loop {
+        match open() {
+            Ok(probe) => return Ok(probe),
+            Err(err) if attempt < retries => {
+                attempt += 1;
+                log::debug!(
+                    "Opening the probe failed: {}. Retrying ({}/{}) in {:?}.",
+                    err,
+                    attempt,
+                    retries,
+                    delay
+                );
+                std::thread::sleep(delay);
+            }
+            Err(err) => return Err(RoverError::FailedToOpenProbe(err)),
+        }
+    }
+}
+
+/// Lists the connected probes, retrying as often as configured while none are found.
+/// A probe which was just plugged in might not be listed right away.
+fn list_with_retries(config: &Config) -> Vec<DebugProbeInfo> {
+    let retries = config.probe().open_retries();
+    let delay = Duration::from_millis(config.probe().open_retry_delay_ms());
+    let mut attempt = 0;
+    loop {
+        let list = Probe::list_all();
+        if !list.is_empty() || attempt >= retries {
+            return list;
+        }
+        attempt += 1;
+        log::debug!(
+            "No probes found. Retrying ({}/{}) in {:?}.",
+            attempt,
+            retries,
+            delay
+        );
+        std::thread::sleep(delay);
+    }
+}
+
 /// Tries to open the debug probe from the given commandline arguments.
 /// This ensures that there is only one probe connected or if multiple probes are found,
 /// a single one is specified via the commandline parameters.
@@ -373,9 +1184,14 @@ fn open_probe(config: &Config) -> Result<Probe, RoverError> {
         return Ok(Probe::from_specific_probe(Box::new(FakeProbe::new())));
     }
 
+    // The power is switched through a separate connection to the probe, so it has to happen before the probe is opened.
+    if let Some(target_power) = config.probe().target_power() {
+        power::switch_target_power(config, target_power);
+    }
+
     // If we got a probe selector as an argument, open the probe matching the selector if possible.
     match &config.probe().selector() {
-        Some(selector) => Probe::open(selector.clone()).map_err(RoverError::FailedToOpenProbe),
+        Some(selector) => open_with_retries(config, || Probe::open(selector.clone())),
         None => {
             match (config.probe().usb_vid(), config.probe().usb_pid()) {
                 (Some(vid), Some(pid)) => {
@@ -387,7 +1203,7 @@ fn open_probe(config: &Config) -> Result<Probe, RoverError> {
                         serial_number: config.probe().serial().clone(),
                     };
                     // if two probes with the same VID:PID pair exist we just choose one
-                    Probe::open(selector).map_err(RoverError::FailedToOpenProbe)
+                    open_with_retries(config, || Probe::open(selector.clone()))
                 }
                 _ => {
                     if config.probe().usb_vid().is_some() {
@@ -397,22 +1213,54 @@ fn open_probe(config: &Config) -> Result<Probe, RoverError> {
                         log::warn!("USB PID ignored, because VID is not specified.");
                     }
 
+                    let list = list_with_retries(config);
+
+                    // A probe of the list can be picked by its index instead of a full selector.
+                    if let Some(index) = config.probe().index() {
+                        let info =
+                            list.get(index)
+                                .ok_or_else(|| RoverError::ProbeIndexOutOfRange {
+                                    index,
+                                    count: list.len(),
+                                })?;
+                        METADATA.lock().unwrap().probe = Some(format!("{:?}", info.probe_type));
+                        return open_with_retries(config, || Probe::open(info));
+                    }
+
+                    // Identical probes can be told apart by their serial number alone.
+                    if let Some(serial) = config.probe().serial() {
+                        let matches = list
+                            .into_iter()
+                            .filter(|info| info.serial_number.as_ref() == Some(serial))
+                            .collect::<Vec<_>>();
+                        return match matches.len() {
+                            0 => Err(RoverError::NoProbeWithSerial {
+                                serial: serial.clone(),
+                            }),
+                            1 => {
+                                let info = &matches[0];
+                                METADATA.lock().unwrap().probe =
+                                    Some(format!("{:?}", info.probe_type));
+                                open_with_retries(config, || Probe::open(info))
+                            }
+                            _ => Err(RoverError::MultipleProbesFound { list: matches }),
+                        };
+                    }
+
                     // Only automatically select a probe if there is only
                     // a single probe detected.
-                    let list = Probe::list_all();
                     if list.len() > 1 {
                         Err(RoverError::MultipleProbesFound { list })
                     } else {
-                        Probe::open(
-                            list.first()
-                                .map(|info| {
-                                    METADATA.lock().unwrap().probe =
-                                        Some(format!("{:?}", info.probe_type));
-                                    info
-                                })
-                                .ok_or_else(|| RoverError::NoProbesFound)?,
-                        )
-                        .map_err(RoverError::FailedToOpenProbe)
+                        let info = list
+                            .first()
+                            .map(|info| {
+                                METADATA.lock().unwrap().probe =
+                                    Some(format!("{:?}", info.probe_type));
+                                info
+                            })
+                            .ok_or_else(|| RoverError::NoProbesFound)?;
+                        open_with_retries(config, || Probe::open(info))
                     }
                 }
             }
@@ -1,45 +1,562 @@
 use std::{
     collections::{BTreeMap, HashMap},
     fs::{self, File},
-    io::{Read, Seek},
+    io::{Read, Seek, Write},
     path::Path,
-    process::Command,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::Sender,
+        Arc, Mutex,
+    },
     thread::JoinHandle,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
+use colored::Colorize;
+use defmt_decoder::DecodeError;
 use defmt_elf2table::{Location, Table};
-use probe_rs::Session;
-use probe_rs_rtt::{DownChannel, Rtt, ScanRegion, UpChannel};
+use probe_rs::{architecture::arm::SwoConfig, Session};
+use probe_rs_cli_util::logging;
+use probe_rs_rtt::{ChannelMode, DownChannel, Rtt, ScanRegion, UpChannel};
+use sha1::{Digest, Sha1};
 
+#[cfg(unix)]
+use crate::updater::unix::UnixSocketUpdater;
 use crate::{
-    config::{Channel, ChannelKind, LinkKind, RttMode},
+    config::{
+        Channel, ChannelKind, ItmMode, LevelPrefix, LinkKind, Logging, RttChannel, RttMode,
+        RttScanRegion, TimestampFormat,
+    },
     diagnostics::RoverError,
+    itm, semihosting,
     updater::{
-        stdio::StdioUpdater, tcp::TcpUpdater, websocket::WebsocketUpdater, Updater, UpdaterChannel,
-        Value,
+        file::FileUpdater,
+        serial::SerialUpdater,
+        stdio::StdioUpdater,
+        stdout::StdoutUpdater,
+        tcp::TcpUpdater,
+        terminal::{Screen, TerminalUpdater},
+        udp::UdpUpdater,
+        websocket::{self, WebsocketUpdater},
+        Updater, UpdaterChannel, Value, DEFAULT_POLL_INTERVAL,
     },
 };
 
+/// Starts forwarding the configured channels to their links in a separate thread.
+/// Without any configured channels, the lines of all RTT up channels are printed to stdout instead.
+///
+/// The thread runs until `halt` is set.
+/// `ready` is signaled once the thread is attached to the target and starts forwarding.
+/// With `gdb` set, the GDB stub owns the halted states of the core, so semihosting calls are not answered.
+/// Setting `reattach` makes the thread attach to RTT again, which is needed once the firmware was reflashed.
+///
+/// The session is shared with the GDB stub. The logging only locks it for a single read or write at a time
+/// and yields afterwards, so the stub can take it in between. The stub of probe-rs needs a plain `Mutex`,
+/// which is not fair, so this makes room for the stub but does not guarantee it a turn.
 pub fn run_logging(
     session: Arc<Mutex<Session>>,
     elf_path: impl AsRef<Path>,
-    channels: Vec<Channel>,
+    config: Logging,
+    protocol_speed: u32,
+    gdb: bool,
+    halt: Arc<AtomicBool>,
+    reattach: Arc<AtomicBool>,
+    ready: Sender<()>,
 ) -> Result<JoinHandle<Result<(), RoverError>>, RoverError> {
-    let mut updaters: HashMap<LinkKind, UpdaterChannel<(), ()>> = HashMap::new();
-    for channel in &channels {
+    // Without a configured interval, the links are polled far more often than the target as they are cheap to poll.
+    let updater_poll_interval = config
+        .poll_interval_us()
+        .map(Duration::from_micros)
+        .unwrap_or(DEFAULT_POLL_INTERVAL);
+    let rtt_poll_interval = config
+        .poll_interval_us()
+        .map(Duration::from_micros)
+        .unwrap_or_else(|| Duration::from_millis(10));
+
+    // SWV is set up before any link starts, so a failure is reported before a terminal link takes over the terminal.
+    // The SWO stream is read even if the links of all ITM channels fail to start then.
+    let has_itm = config.channels().iter().any(|channel| {
+        channel
+            .kinds()
+            .iter()
+            .any(|kind| matches!(kind, ChannelKind::Itm { .. }))
+    });
+
+    // Enable the ITM trace output if necessary.
+    // The SWO baud rate is derived from the probe speed.
+    if has_itm {
+        let baud = protocol_speed * 1000;
+        let tpiu_clock = config.itm_tpiu_clock().unwrap_or_else(|| {
+            log::warn!(
+                "No TPIU clock was configured. Assuming it runs at the SWO baud rate of {} Hz.",
+                baud
+            );
+            baud
+        });
+        with_session(&session, |session| {
+            session.setup_swv(&SwoConfig::new(tpiu_clock).set_baud(baud))
+        })
+        .map_err(RoverError::FailedToSetupSwv)?;
+    }
+
+    let Forwarding {
+        mut updaters,
+        links,
+        channels,
+        mut defmt_state,
+        mut defmt_capture,
+    } = start_forwarding(elf_path.as_ref(), &config, &halt, updater_poll_interval)?;
+
+    // Without configured channels, everything the firmware writes to any up channel is shown on stdout.
+    let forward_all = config.channels().is_empty();
+    if forward_all {
+        logging::println("Logging all RTT up channels to stdout as no channels are configured.");
+    } else {
+        print_channel_summary(config.channels(), &updaters);
+    }
+
+    let has_rtt = forward_all
+        || channels.iter().any(|channel| {
+            channel
+                .kinds()
+                .iter()
+                .any(|kind| matches!(kind, ChannelKind::Rtt { .. }))
+        });
+    let mut has_semihosting = channels.iter().any(|channel| {
+        channel
+            .kinds()
+            .iter()
+            .any(|kind| matches!(kind, ChannelKind::Semihosting))
+    });
+
+    // Answering a semihosting call resumes the core, which would run it behind the back of a GDB client
+    // that halted it at the call.
+    if has_semihosting && gdb {
+        log::warn!("Semihosting channels are not forwarded while the GDB stub is enabled.");
+        has_semihosting = false;
+    }
+
+    let elf_path = elf_path.as_ref().to_path_buf();
+
+    Ok(std::thread::spawn(move || {
+        let start = Instant::now();
+
+        let mut rtt =
+            match attach_rtt_channels(&session, &elf_path, &config, &channels, has_rtt, &halt) {
+                Ok(rtt) => rtt,
+                Err(error) => {
+                    drop(updaters);
+                    drop(links);
+                    // The target is reset on every exit, including a timeout while attaching.
+                    if let Err(reset_error) = reset_afterwards(&session, &config) {
+                        log::error!("{}", reset_error);
+                    }
+                    return Err(error);
+                }
+            };
+
+        // Nobody might be waiting for the signal, so a failed send is fine.
+        let _ = ready.send(());
+
+        // Partial lines per (channel, kind) which are kept across polls.
+        let mut line_buffers: HashMap<(usize, usize), LineBuffer> = HashMap::new();
+        // Undecoded defmt bytes per (channel, kind) which are kept across polls.
+        let mut defmt_buffers: HashMap<(usize, usize), Vec<u8>> = HashMap::new();
+        let colors = config.colors();
+        let timestamp_format = config.timestamp_format();
+        let mut itm_decoder = itm::Decoder::new();
+        let mut itm_encoder = itm::JsonEncoder::new();
+        // The up channels are all read into the same buffer, which is only allocated once.
+        let mut rtt_buffer = vec![0; config.rtt_read_buffer()];
+        // The overflow episodes per up channel.
+        let mut overflows: HashMap<usize, Overflow> = HashMap::new();
+        // Partial lines per up channel when all of them are forwarded.
+        let mut forwarded_buffers: HashMap<usize, LineBuffer> = HashMap::new();
+
+        while !halt.load(Ordering::Relaxed) {
+            // A reflashed firmware has a new control block, so the channels are attached to from scratch.
+            if reattach.swap(false, Ordering::Relaxed) {
+                log::info!("Reattaching to RTT after the target was reflashed.");
+                line_buffers.clear();
+                defmt_buffers.clear();
+                overflows.clear();
+                forwarded_buffers.clear();
+                if let Some(state) = &mut defmt_state {
+                    match create_defmt_state(&elf_path) {
+                        Ok(new_state) => *state = new_state,
+                        Err(error) => log::error!("Reloading the defmt table failed: {}", error),
+                    }
+                }
+                rtt = match attach_rtt_channels(
+                    &session, &elf_path, &config, &channels, has_rtt, &halt,
+                ) {
+                    Ok(rtt) => rtt,
+                    Err(error) => {
+                        log::error!("Reattaching to RTT failed: {}", error);
+                        RttChannels::default()
+                    }
+                };
+            }
+
+            // All ITM channels share the same SWO stream, so it is only read once per iteration.
+            let (swo_data, itm_packets) = if has_itm {
+                poll_swo(&session, &mut itm_decoder)
+            } else {
+                (vec![], vec![])
+            };
+
+            // The timestamps are tracked across the whole stream, so the packets are encoded once for all channels.
+            let itm_json = itm_encoder.encode(&itm_packets);
+
+            // A semihosting call keeps the core halted until it is answered, so it is answered once per iteration.
+            let semihosting_data = if has_semihosting {
+                poll_semihosting(&session)
+            } else {
+                vec![]
+            };
+
+            if forward_all {
+                for (number, up_channel) in rtt.up.iter_mut().enumerate() {
+                    let overflow = overflows.entry(number).or_default();
+                    // Binary channels are still read, so firmware which blocks on a full channel keeps running.
+                    let data = poll_rtt(up_channel, &mut rtt_buffer, overflow);
+                    std::thread::yield_now();
+                    let label = match &rtt.up_labels[number] {
+                        Some(label) => label,
+                        None => continue,
+                    };
+                    let lines = forwarded_buffers.entry(number).or_default().push(&data);
+                    // Lines which are not UTF-8 were decoded with replacement characters.
+                    if lines
+                        .iter()
+                        .any(|line| line.contains(std::char::REPLACEMENT_CHARACTER))
+                    {
+                        log::warn!(
+                            "RTT up channel {} sends data which is not UTF-8 text, so it is not printed anymore. Configure a channel with the matching mode for it.",
+                            number
+                        );
+                        rtt.up_labels[number] = None;
+                        forwarded_buffers.remove(&number);
+                        continue;
+                    }
+                    for line in lines {
+                        logging::println(format!("{}{}", label, line));
+                    }
+                }
+            }
+
+            for (channel_index, channel) in channels.iter().enumerate() {
+                // Other links might end up in files or other programs, which should not get escape sequences.
+                let colors = colors && channel.link().shows_colors();
+                for (kind_index, kind) in channel.kinds().iter().enumerate() {
+                    match kind {
+                        ChannelKind::Rtt { mode, prefix, .. } => {
+                            // After a failed reattach, the channels of the firmware are unknown.
+                            let (up, down) = match rtt.resolved.get(&(channel_index, kind_index)) {
+                                Some(&resolved) => resolved,
+                                None => continue,
+                            };
+
+                            // Forward everything the clients of the link sent to the down channel.
+                            if let Some(updater) = updaters.get_mut(channel.link()) {
+                                while let Ok(value) = updater.rx().try_recv() {
+                                    let data = match value {
+                                        Value::Bytes(bytes) => bytes,
+                                        Value::String(string) => string.into_bytes(),
+                                        // Structured updates have no representation the target understands.
+                                        Value::StructuredString(_) => continue,
+                                    };
+                                    match rtt.down.get_mut(down) {
+                                        Some(down_channel) => push_rtt(down_channel, &data),
+                                        None => {
+                                            log::warn!("RTT down channel {} does not exist.", down)
+                                        }
+                                    }
+                                }
+                            }
+
+                            let mut up_channel = rtt.up.get_mut(up);
+                            let data = if let Some(up_channel) = &mut up_channel {
+                                let overflow = overflows.entry(up).or_default();
+                                poll_rtt(up_channel, &mut rtt_buffer, overflow)
+                            } else {
+                                log::warn!("RTT up channel {} does not exist.", up);
+                                vec![]
+                            };
+                            // The channels lock the session for every access on their own.
+                            // Yielding between the channels gives a waiting GDB stub a chance to take the session in between.
+                            std::thread::yield_now();
+
+                            match mode {
+                                RttMode::Raw => {
+                                    if let Some(updater) = updaters.get_mut(channel.link()) {
+                                        updater.send(Value::Bytes(data));
+                                    }
+                                }
+                                RttMode::String { timestamps } => {
+                                    let colored = colors && !config.level_prefixes().is_empty();
+                                    if prefix.is_some() || colored || *timestamps {
+                                        // The prefix, the colors and the timestamps can only be applied to complete lines.
+                                        // Otherwise the data is forwarded as it is read, without waiting for the line to end.
+                                        let stamps = if *timestamps {
+                                            Some((timestamp_format, start))
+                                        } else {
+                                            None
+                                        };
+                                        let lines = format_lines(
+                                            line_buffers
+                                                .entry((channel_index, kind_index))
+                                                .or_default(),
+                                            &data,
+                                            stamps,
+                                            prefix.as_deref(),
+                                            config.level_prefixes(),
+                                            colors,
+                                        );
+                                        if let Some(updater) = updaters.get_mut(channel.link()) {
+                                            for line in lines {
+                                                updater.send(Value::String(line + "\n"));
+                                            }
+                                        }
+                                    } else {
+                                        let incoming = String::from_utf8_lossy(&data).to_string();
+                                        if let Some(updater) = updaters.get_mut(channel.link()) {
+                                            updater.send(Value::String(incoming));
+                                        }
+                                    }
+                                }
+                                RttMode::StringJson => {
+                                    let lines = line_buffers
+                                        .entry((channel_index, kind_index))
+                                        .or_default()
+                                        .push(&data);
+                                    if let Some(updater) = updaters.get_mut(channel.link()) {
+                                        for line in lines {
+                                            updater.send(parse_json_line(line, prefix.as_deref()));
+                                        }
+                                    }
+                                }
+                                RttMode::Defmt => {
+                                    if let Some(capture) = &mut defmt_capture {
+                                        capture.write((channel_index, kind_index), &data);
+                                    }
+                                    if let Some((table, locs)) = &defmt_state {
+                                        // Frames can be split across polls, so the undecoded rest is kept.
+                                        let frames = defmt_buffers
+                                            .entry((channel_index, kind_index))
+                                            .or_default();
+                                        frames.extend_from_slice(&data);
+                                        let lines =
+                                            decode_defmt(frames, table, locs.as_ref(), colors);
+                                        if let Some(updater) = updaters.get_mut(channel.link()) {
+                                            for line in lines {
+                                                updater.send(Value::String(line));
+                                            }
+                                        }
+                                    }
+                                }
+                                RttMode::DefmtJson => {
+                                    if let Some(capture) = &mut defmt_capture {
+                                        capture.write((channel_index, kind_index), &data);
+                                    }
+                                }
+                            }
+                        }
+                        ChannelKind::Semihosting => {
+                            if semihosting_data.is_empty() {
+                                continue;
+                            }
+                            if let Some(updater) = updaters.get_mut(channel.link()) {
+                                let text = String::from_utf8_lossy(&semihosting_data).to_string();
+                                updater.send(Value::String(text));
+                            }
+                        }
+                        ChannelKind::Itm { mode } => {
+                            let updater = match updaters.get_mut(channel.link()) {
+                                Some(updater) => updater,
+                                None => continue,
+                            };
+
+                            match mode {
+                                ItmMode::Raw => {
+                                    if !swo_data.is_empty() {
+                                        updater.send(Value::Bytes(swo_data.clone()));
+                                    }
+                                }
+                                ItmMode::String { timestamps } => {
+                                    // Stimulus port 0 carries the text output by convention.
+                                    let text = itm_packets
+                                        .iter()
+                                        .filter_map(|packet| match packet {
+                                            itm::TracePacket::Instrumentation {
+                                                port: 0,
+                                                payload,
+                                            } => Some(payload.as_slice()),
+                                            _ => None,
+                                        })
+                                        .flatten()
+                                        .copied()
+                                        .collect::<Vec<_>>();
+                                    let lines = line_buffers
+                                        .entry((channel_index, kind_index))
+                                        .or_default()
+                                        .push(&text);
+                                    for line in lines {
+                                        let line = if *timestamps {
+                                            format!(
+                                                "{}{}\n",
+                                                timestamp(timestamp_format, start),
+                                                line
+                                            )
+                                        } else {
+                                            line + "\n"
+                                        };
+                                        updater.send(Value::String(line));
+                                    }
+                                }
+                                ItmMode::DecodedJson => {
+                                    for packet in &itm_json {
+                                        // This unwrap is fine as JSON packets always serialize.
+                                        let packet = serde_json::to_value(packet).unwrap();
+                                        updater.send(Value::StructuredString(packet));
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            std::thread::sleep(rtt_poll_interval);
+        }
+
+        drop(updaters);
+        drop(links);
+
+        reset_afterwards(&session, &config)
+    }))
+}
+
+/// Leaves the target in a known state for the next user if `logging.reset_afterwards` is set.
+fn reset_afterwards(session: &Mutex<Session>, config: &Logging) -> Result<(), RoverError> {
+    if config.reset_afterwards() {
+        log::info!("Resetting the target after logging.");
+        with_session(session, |session| {
+            let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+            core.reset().map_err(RoverError::TargetResetFailed)
+        })?;
+    }
+    Ok(())
+}
+
+/// The started links and the state the channels are decoded with.
+struct Forwarding {
+    updaters: HashMap<LinkKind, UpdaterChannel<(), serde_json::Value>>,
+    links: Links,
+    /// The configured channels whose link started.
+    channels: Vec<Channel>,
+    defmt_state: Option<DefmtState>,
+    defmt_capture: Option<DefmtCapture>,
+}
+
+/// The decoding table of defmt and the locations of its log statements, if they are complete.
+type DefmtState = (Table, Option<BTreeMap<u64, Location>>);
+
+/// Starts the links of the configured channels and loads what is needed to decode them.
+///
+/// The links which were started are stopped again if anything fails afterwards.
+fn start_forwarding(
+    elf_path: &Path,
+    config: &Logging,
+    halt: &Arc<AtomicBool>,
+    updater_poll_interval: Duration,
+) -> Result<Forwarding, RoverError> {
+    let mut updaters: HashMap<LinkKind, UpdaterChannel<(), serde_json::Value>> = HashMap::new();
+    // The running updaters are kept so they can be stopped on shutdown.
+    let mut links = Links(vec![]);
+    let mut screen = None;
+    for channel in config.channels() {
         let link = channel.link().clone();
-        updaters.insert(
-            link.clone(),
-            match link {
-                LinkKind::Command(command) => StdioUpdater::new(Command::new(command)).start(),
-                LinkKind::Tcp(socket) => TcpUpdater::new(socket).start(),
-                LinkKind::WebSocket(socket) => WebsocketUpdater::new(socket).start(),
+        // Multiple channels can share the same link. Only start it once.
+        if updaters.contains_key(&link) {
+            continue;
+        }
+
+        let mut updater: Box<dyn Updater<(), serde_json::Value> + Send> = match link.clone() {
+            LinkKind::Stdout => Box::new(StdoutUpdater::new()),
+            LinkKind::Command(command) => {
+                Box::new(StdioUpdater::new(command, config.command_restarts()))
+            }
+            LinkKind::Tcp(socket, token) => Box::new(TcpUpdater::new(socket, token)),
+            LinkKind::WebSocket(socket, token) => Box::new(WebsocketUpdater::new(socket, token)),
+            LinkKind::WebSocketTls {
+                socket,
+                cert,
+                key,
+                token,
+            } => match websocket::load_tls_acceptor(Path::new(&cert), Path::new(&key)) {
+                Ok(acceptor) => Box::new(WebsocketUpdater::new_tls(socket, acceptor, token)),
+                Err(error) if config.continue_on_channel_error() => {
+                    log::warn!(
+                            "Failed to load the TLS certificate '{}' or key '{}': {}. Skipping all channels using the websocket on '{}'.",
+                            cert,
+                            key,
+                            error,
+                            socket
+                        );
+                    continue;
+                }
+                Err(error) => {
+                    return Err(RoverError::FailedToLoadTlsIdentity {
+                        source: error,
+                        cert,
+                        key,
+                    })
+                }
             },
-        );
+            LinkKind::File(path) => Box::new(FileUpdater::new(path)),
+            LinkKind::Serial { path, baud } => Box::new(SerialUpdater::new(path, baud)),
+            #[cfg(unix)]
+            LinkKind::Unix(path) => Box::new(UnixSocketUpdater::new(path)),
+            LinkKind::Terminal(title) => {
+                // All terminal links share the same screen, each one in its own pane.
+                let screen = screen.get_or_insert_with(|| Screen::new(halt.clone()));
+                Box::new(TerminalUpdater::new(screen.clone(), title))
+            }
+            LinkKind::Udp(address) => Box::new(UdpUpdater::new(address)),
+        };
+
+        match updater.start(config.channel_buffer(), updater_poll_interval) {
+            Ok(mut channel) => {
+                channel.set_label(format!("the {} link '{}'", link.name(), link.target()));
+                updaters.insert(link, channel);
+                links.0.push(updater);
+            }
+            Err(error) => {
+                if config.continue_on_channel_error() {
+                    log::warn!(
+                        "Failed to start the {} logging link '{}': {}. Skipping all channels using it.",
+                        link.name(),
+                        link.target(),
+                        error
+                    );
+                } else {
+                    return Err(RoverError::FailedToStartUpdater {
+                        source: error,
+                        link,
+                    });
+                }
+            }
+        }
     }
 
+    // Only keep the channels whose link started successfully.
+    let channels = config
+        .channels()
+        .iter()
+        .filter(|channel| updaters.contains_key(channel.link()))
+        .cloned()
+        .collect::<Vec<_>>();
+
     // Initialize defmt if necessary.
     let mut defmt_state = None;
     for channel in &channels {
@@ -49,10 +566,11 @@ pub fn run_logging(
                     up: _up,
                     down: _down,
                     mode,
+                    prefix: _prefix,
                 } => match mode {
                     RttMode::Defmt | RttMode::DefmtJson => {
                         if defmt_state.is_none() {
-                            defmt_state = Some(create_defmt_state(elf_path.as_ref())?);
+                            defmt_state = Some(create_defmt_state(elf_path)?);
                             break;
                         }
                     }
@@ -63,96 +581,421 @@ pub fn run_logging(
         }
     }
 
-    let elf_path = elf_path.as_ref().to_path_buf();
+    // Capture the undecoded stream of the first defmt channel if requested.
+    let defmt_up = channels
+        .iter()
+        .enumerate()
+        .flat_map(|(channel_index, channel)| {
+            channel
+                .kinds()
+                .iter()
+                .enumerate()
+                .map(move |(kind_index, kind)| ((channel_index, kind_index), kind))
+        })
+        .find_map(|(key, kind)| match kind {
+            ChannelKind::Rtt { up, mode, .. }
+                if matches!(mode, RttMode::Defmt | RttMode::DefmtJson) =>
+            {
+                Some((key, up.clone()))
+            }
+            _ => None,
+        });
+    let defmt_capture = match (config.defmt_raw_capture(), defmt_up) {
+        (Some(path), Some((key, up))) => Some(DefmtCapture::create(path, elf_path, key, &up)?),
+        (Some(_), None) => {
+            log::warn!("No defmt channel is configured, so there is nothing to capture.");
+            None
+        }
+        _ => None,
+    };
 
-    Ok(std::thread::spawn(move || {
-        let _t = std::time::Instant::now();
-        // let mut error = None;
+    Ok(Forwarding {
+        updaters,
+        links,
+        channels,
+        defmt_state,
+        defmt_capture,
+    })
+}
 
-        let mut i = 1;
+/// The running links. They are stopped once they are dropped, so they are closed on every way out of the logging.
+struct Links(Vec<Box<dyn Updater<(), serde_json::Value> + Send>>);
 
-        // t.elapsed().as_millis() as usize) < config.rtt.timeout
-        loop {
-            log::info!("Initializing RTT (attempt {})...", i);
-            i += 1;
+impl Drop for Links {
+    /// Closes all the links so child processes are killed and sockets are closed.
+    fn drop(&mut self) {
+        for link in &mut self.0 {
+            if link.stop().is_err() {
+                log::warn!("A logging link did not shut down cleanly.");
+            }
+        }
+    }
+}
 
-            let rtt_header_address = if let Ok(mut file) = File::open(elf_path.as_path()) {
-                if let Some(address) = get_rtt_symbol(&mut file) {
-                    log::info!("RTT symbol found at address {:x}", address);
-                    ScanRegion::Exact(address as u32)
-                } else {
-                    log::warn!("RTT symbol not found in ELF binary. Scanning RAM for RTT symbols.");
-                    ScanRegion::Ram
-                }
+/// The RTT channels of the firmware which are polled.
+#[derive(Default)]
+struct RttChannels {
+    up: Vec<UpChannel>,
+    down: Vec<DownChannel>,
+    /// The up and down channel of every RTT kind, by the index of its channel and of the kind.
+    resolved: HashMap<(usize, usize), (usize, usize)>,
+    /// The labels of the up channels when all of them are forwarded.
+    /// Channels with binary data, like defmt, have none and are not forwarded.
+    up_labels: Vec<Option<String>>,
+}
+
+/// Attaches to the RTT control block of the firmware and looks up the channels the configured ones use.
+fn attach_rtt_channels(
+    session: &Arc<Mutex<Session>>,
+    elf_path: &Path,
+    config: &Logging,
+    channels: &[Channel],
+    has_rtt: bool,
+    halt: &AtomicBool,
+) -> Result<RttChannels, RoverError> {
+    let (up_channels, down_channels) = if has_rtt {
+        let timeout = config.rtt_timeout_ms().map(Duration::from_millis);
+        match attach_rtt(session, elf_path, config.rtt_scan_region(), timeout, halt)? {
+            Some(mut rtt) => (
+                rtt.up_channels().drain().collect::<Vec<_>>(),
+                rtt.down_channels().drain().collect::<Vec<_>>(),
+            ),
+            None => (vec![], vec![]),
+        }
+    } else {
+        (vec![], vec![])
+    };
+
+    // The channels given by name are looked up among the channels the firmware announces.
+    // Halting while attaching leaves no channels to look them up in, but nothing is polled then either.
+    let rtt_channels = if halt.load(Ordering::Relaxed) {
+        HashMap::new()
+    } else {
+        let up_names = up_channels
+            .iter()
+            .map(|channel| channel.name().map(str::to_string))
+            .collect::<Vec<_>>();
+        let down_names = down_channels
+            .iter()
+            .map(|channel| channel.name().map(str::to_string))
+            .collect::<Vec<_>>();
+        resolve_rtt_channels(channels, &up_names, &down_names)?
+    };
+
+    // Dropped data is only noticed through its symptoms, so the way the firmware handles full buffers is logged up front.
+    for channel in &up_channels {
+        let mode = match channel.mode() {
+            Ok(mode) => format!("{:?}", mode),
+            Err(_) => "an unknown mode".to_string(),
+        };
+        log::info!(
+            "RTT up channel {} ({}) has a buffer of {} bytes and runs in {}",
+            channel.number(),
+            channel.name().unwrap_or("unnamed"),
+            channel.buffer_size(),
+            mode
+        );
+    }
+
+    let up_labels = up_channels
+        .iter()
+        .enumerate()
+        .map(|(number, channel)| up_label(number, channel.name()))
+        .collect::<Vec<_>>();
+
+    Ok(RttChannels {
+        up: up_channels,
+        down: down_channels,
+        resolved: rtt_channels,
+        up_labels,
+    })
+}
+
+/// The label of an up channel whose lines are printed when all of them are forwarded.
+/// The lines are labeled with the channel name, or its number if it has none.
+/// A channel named `defmt` carries binary frames, so it is not printed and has no label.
+fn up_label(number: usize, name: Option<&str>) -> Option<String> {
+    match name {
+        Some(name) if name.eq_ignore_ascii_case("defmt") => {
+            log::info!(
+                "RTT up channel {} carries defmt frames, which are only decoded by a defmt channel. It is not printed.",
+                number
+            );
+            None
+        }
+        Some(name) => Some(format!("[{}] ", name)),
+        None => Some(format!("[{}] ", number)),
+    }
+}
+
+/// Tries to attach to the RTT control block of the target until it succeeds or `halt` is set.
+///
+/// If no `region` is given, the address of the control block is taken from the ELF or all of RAM is scanned.
+/// Returns `None` if `halt` was set before and an error if attaching did not succeed within `timeout`.
+fn attach_rtt(
+    session: &Arc<Mutex<Session>>,
+    elf_path: &Path,
+    region: Option<RttScanRegion>,
+    timeout: Option<Duration>,
+    halt: &AtomicBool,
+) -> Result<Option<Rtt>, RoverError> {
+    let t = Instant::now();
+
+    let mut i = 1;
+
+    while !halt.load(Ordering::Relaxed) {
+        if let Some(timeout) = timeout {
+            if t.elapsed() > timeout {
+                return Err(RoverError::RttInitTimeout(timeout.as_millis() as u64));
+            }
+        }
+
+        log::info!("Initializing RTT (attempt {})...", i);
+        i += 1;
+
+        let rtt_header_address = if let Some(region) = region {
+            match region {
+                RttScanRegion::Exact(address) => ScanRegion::Exact(address),
+                RttScanRegion::Ram => ScanRegion::Ram,
+                RttScanRegion::Range(start, end) => ScanRegion::Range(start..end),
+            }
+        } else if let Ok(mut file) = File::open(elf_path) {
+            if let Some(address) = get_rtt_symbol(&mut file) {
+                log::info!("RTT symbol found at address {:x}", address);
+                ScanRegion::Exact(address as u32)
             } else {
-                log::warn!("ELF binary could not be opened. Scanning RAM for RTT symbols.");
+                log::warn!("RTT symbol not found in ELF binary. Scanning RAM for RTT symbols.");
                 ScanRegion::Ram
-            };
+            }
+        } else {
+            log::warn!("ELF binary could not be opened. Scanning RAM for RTT symbols.");
+            ScanRegion::Ram
+        };
 
-            match Rtt::attach_region(session.clone(), &rtt_header_address) {
-                Ok(mut rtt) => {
-                    log::info!("RTT synbols found.");
-
-                    let mut up_channels = rtt.up_channels().drain().collect::<Vec<_>>();
-
-                    loop {
-                        for channel in &channels {
-                            for kind in channel.kinds() {
-                                match kind {
-                                    ChannelKind::Rtt {
-                                        up,
-                                        down: _down,
-                                        mode,
-                                    } => {
-                                        let mut up_channel = up_channels.get_mut(*up);
-                                        let data = if let Some(up_channel) = &mut up_channel {
-                                            poll_rtt(up_channel)
-                                        } else {
-                                            log::warn!("RTT up channel {} does not exist.", up);
-                                            vec![]
-                                        };
+        match Rtt::attach_region(session.clone(), &rtt_header_address) {
+            Ok(rtt) => {
+                log::info!("RTT symbols found.");
+                return Ok(Some(rtt));
+            }
+            Err(error) => {
+                log::warn!("Failed to initialize RTT: {}. Retrying.", error);
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        };
+    }
 
-                                        match mode {
-                                            RttMode::Raw => {
-                                                updaters
-                                                    .get_mut(channel.link())
-                                                    .map(|v| v.tx().send(Value::Bytes(data)));
-                                            }
-                                            RttMode::String { timestamps: _ts } => {
-                                                let incoming =
-                                                    String::from_utf8_lossy(&data).to_string();
-                                                updaters
-                                                    .get_mut(channel.link())
-                                                    .map(|v| v.tx().send(Value::String(incoming)));
-                                            }
-                                            RttMode::StringJson => {}
-                                            RttMode::Defmt => {}
-                                            RttMode::DefmtJson => {}
-                                        }
-                                    }
-                                    ChannelKind::Itm { mode: _mode } => {}
-                                }
-                            }
-                        }
-                        std::thread::sleep(Duration::from_millis(10));
-                    }
-                }
-                Err(_err) => {
-                    log::warn!("Failed to initialize RTT. Retrying.");
-                }
+    Ok(None)
+}
+
+/// Runs `f` with the session locked and yields once it is unlocked again.
+///
+/// The lock is held for the duration of `f` only, so `f` should be a single short operation.
+/// Yielding afterwards gives other threads waiting for the session, like the GDB stub, a chance to take it
+/// before it is locked again. The `Mutex` is not fair, so they are not guaranteed to get it.
+fn with_session<T, R>(session: &Mutex<T>, f: impl FnOnce(&mut T) -> R) -> R {
+    let result = f(&mut session.lock().unwrap());
+    std::thread::yield_now();
+    result
+}
+
+/// Reads all new data from the SWO stream and decodes the ITM packets contained in it.
+fn poll_swo(
+    session: &Arc<Mutex<Session>>,
+    decoder: &mut itm::Decoder,
+) -> (Vec<u8>, Vec<itm::TracePacket>) {
+    match with_session(session, |session| session.read_swo()) {
+        Ok(data) => {
+            let packets = decoder.feed(&data);
+            (data, packets)
+        }
+        Err(err) => {
+            log::error!("Error reading from SWO: {}", err);
+            (vec![], vec![])
+        }
+    }
+}
+
+/// Answers a pending semihosting call and returns the text it wrote.
+fn poll_semihosting(session: &Arc<Mutex<Session>>) -> Vec<u8> {
+    match with_session(session, semihosting::poll) {
+        Ok(data) => data,
+        Err(err) => {
+            log::error!("Error answering a semihosting call: {}", err);
+            vec![]
+        }
+    }
+}
+
+/// Accumulates bytes and splits them into complete lines.
+/// Incomplete lines are retained until the rest of the line arrives.
+#[derive(Debug, Default)]
+struct LineBuffer {
+    buffer: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Appends the data to the buffer and returns all lines which are complete now, without their line terminators.
+    fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(data);
+
+        let mut lines = vec![];
+        while let Some(position) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=position).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line[..line.len() - 1]);
+            lines.push(line.trim_end_matches('\r').to_string());
+        }
+        lines
+    }
+}
+
+/// Formats the host side timestamp of a line which was completed just now.
+fn timestamp(format: TimestampFormat, start: Instant) -> String {
+    match format {
+        TimestampFormat::Elapsed => format!("[{:>12.6}] ", start.elapsed().as_secs_f64()),
+        TimestampFormat::Rfc3339 => format!(
+            "[{}] ",
+            humantime::format_rfc3339_micros(std::time::SystemTime::now())
+        ),
+    }
+}
+
+/// Splits the data of a string channel into complete lines and formats them with their timestamp and prefix.
+/// Lines starting with one of the level prefixes are colored by their level if `colors` is set.
+fn format_lines(
+    buffer: &mut LineBuffer,
+    data: &[u8],
+    stamps: Option<(TimestampFormat, Instant)>,
+    prefix: Option<&str>,
+    level_prefixes: &[LevelPrefix],
+    colors: bool,
+) -> Vec<String> {
+    buffer
+        .push(data)
+        .into_iter()
+        .map(|line| {
+            let level = level_prefixes
+                .iter()
+                .find(|level| line.trim_start().starts_with(&level.prefix))
+                .map(|level| level.level);
+            let stamp = match stamps {
+                Some((format, start)) => timestamp(format, start),
+                None => String::new(),
             };
+            let line = format!("{}{}{}", stamp, prefix.unwrap_or(""), line);
+            match level {
+                Some(level) if colors => colorize(line, level),
+                _ => line,
+            }
+        })
+        .collect()
+}
 
-            log::warn!("Failed to initialize RTT. Retrying.");
-            std::thread::sleep(Duration::from_millis(10));
+/// Tries to parse a line as JSON and falls back to the raw line if that fails.
+/// The prefix is only prepended to lines which are forwarded as text.
+fn parse_json_line(line: String, prefix: Option<&str>) -> Value<serde_json::Value> {
+    match serde_json::from_str(&line) {
+        Ok(json) => Value::StructuredString(json),
+        Err(error) => {
+            log::debug!("Failed to parse JSON: {:#?}", error);
+            Value::String(format!("{}{}\n", prefix.unwrap_or(""), line))
         }
-    }))
+    }
+}
+
+/// Looks up the up and down channel of every RTT channel kind among the channels the firmware announces.
+/// Returns the indices of the channels keyed by the (channel, kind) indices.
+fn resolve_rtt_channels(
+    channels: &[Channel],
+    up_names: &[Option<String>],
+    down_names: &[Option<String>],
+) -> Result<HashMap<(usize, usize), (usize, usize)>, RoverError> {
+    let mut resolved = HashMap::new();
+    for (channel_index, channel) in channels.iter().enumerate() {
+        for (kind_index, kind) in channel.kinds().iter().enumerate() {
+            if let ChannelKind::Rtt { up, down, .. } = kind {
+                let up = resolve_rtt_channel(up, up_names, "up")?;
+                let down = resolve_rtt_channel(down, down_names, "down")?;
+                resolved.insert((channel_index, kind_index), (up, down));
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Finds the index of the channel among the announced ones. Numbers are taken as they are.
+fn resolve_rtt_channel(
+    channel: &RttChannel,
+    names: &[Option<String>],
+    direction: &str,
+) -> Result<usize, RoverError> {
+    match channel {
+        RttChannel::Number(number) => Ok(*number),
+        RttChannel::Name(name) => names
+            .iter()
+            .position(|announced| announced.as_deref() == Some(name.as_str()))
+            .ok_or_else(|| RoverError::RttChannelNotFound {
+                name: name.clone(),
+                direction: direction.to_string(),
+                available: names
+                    .iter()
+                    .enumerate()
+                    .map(|(number, announced)| match announced {
+                        Some(announced) => format!("'{}' ({})", announced, number),
+                        None => format!("{} (unnamed)", number),
+                    })
+                    .collect(),
+            }),
+    }
+}
+
+/// Prints which of the configured channels are active and which were skipped because their link failed to start.
+fn print_channel_summary(
+    channels: &[Channel],
+    updaters: &HashMap<LinkKind, UpdaterChannel<(), serde_json::Value>>,
+) {
+    for line in channel_summary(channels, updaters) {
+        logging::println(line);
+    }
+}
+
+/// The lines of the channel summary.
+/// The links are listed by their kind and target only, as they might hold a token.
+fn channel_summary(
+    channels: &[Channel],
+    updaters: &HashMap<LinkKind, UpdaterChannel<(), serde_json::Value>>,
+) -> Vec<String> {
+    let active = channels
+        .iter()
+        .filter(|channel| updaters.contains_key(channel.link()))
+        .count();
+
+    let mut lines = vec![format!(
+        "Logging to {} of {} configured channels:",
+        active,
+        channels.len()
+    )];
+    for (i, channel) in channels.iter().enumerate() {
+        lines.push(format!(
+            "    [{}]: {} '{}' ({})",
+            i,
+            channel.link().name(),
+            channel.link().target(),
+            if updaters.contains_key(channel.link()) {
+                "active"
+            } else {
+                "skipped"
+            }
+        ));
+    }
+    lines
 }
 
 /// Creates a new defmt state which holds all the information about the defmt symbols.
-fn create_defmt_state(
-    elf_path: impl AsRef<Path>,
-) -> Result<(Table, Option<BTreeMap<u64, Location>>), RoverError> {
-    let elf = fs::read(elf_path).unwrap();
+fn create_defmt_state(elf_path: &Path) -> Result<DefmtState, RoverError> {
+    let elf = fs::read(elf_path).map_err(|error| RoverError::FailedToOpenElf {
+        source: error,
+        path: format!("{}", elf_path.display()),
+    })?;
     let table = defmt_elf2table::parse(&elf);
 
     let table = match table {
@@ -179,14 +1022,138 @@ fn create_defmt_state(
     Ok((table, locs))
 }
 
+/// Decodes all complete defmt frames in `frames` and removes them from it.
+///
+/// Each frame is rendered as one line. If the location info is complete, the line is prefixed with the `file:line` it was logged from.
+/// If `colors` is set, the line is colored by the level of the frame.
+fn decode_defmt(
+    frames: &mut Vec<u8>,
+    table: &Table,
+    locs: Option<&BTreeMap<u64, Location>>,
+    colors: bool,
+) -> Vec<String> {
+    let mut lines = vec![];
+    loop {
+        match defmt_decoder::decode(frames, table) {
+            Ok((frame, consumed)) => {
+                let mut line = match locs.and_then(|locs| locs.get(&frame.index())) {
+                    Some(location) => {
+                        // Paths relative to the working directory are shorter and still clickable in editors.
+                        let file = std::env::current_dir()
+                            .ok()
+                            .and_then(|dir| location.file.strip_prefix(dir).ok())
+                            .unwrap_or(&location.file);
+                        format!(
+                            "{}:{} {}",
+                            file.display(),
+                            location.line,
+                            frame.display(false)
+                        )
+                    }
+                    None => frame.display(false).to_string(),
+                };
+                if colors {
+                    // defmt and log name their levels the same.
+                    if let Ok(level) = frame.level().as_str().parse() {
+                        line = colorize(line, level);
+                    }
+                }
+                lines.push(line + "\n");
+                frames.drain(..consumed);
+            }
+            Err(DecodeError::UnexpectedEof) => break,
+            Err(DecodeError::Malformed) => {
+                log::error!("Failed to decode defmt data: {:x?}", frames);
+                frames.clear();
+                break;
+            }
+        }
+    }
+    lines
+}
+
+/// Colors a line by its level.
+fn colorize(line: String, level: log::Level) -> String {
+    match level {
+        log::Level::Error => line.red(),
+        log::Level::Warn => line.yellow(),
+        log::Level::Info => line.green(),
+        log::Level::Debug => line.blue(),
+        log::Level::Trace => line.dimmed(),
+    }
+    .to_string()
+}
+
+/// Writes the undecoded bytes of a defmt channel to a file.
+///
+/// The file starts with a header of text lines terminated by an empty line, so the capture can be matched with its ELF later.
+struct DefmtCapture {
+    file: File,
+    /// The (channel, kind) indices of the captured channel kind.
+    key: (usize, usize),
+}
+
+impl DefmtCapture {
+    fn create(
+        path: &str,
+        elf_path: &Path,
+        key: (usize, usize),
+        up: &RttChannel,
+    ) -> Result<Self, RoverError> {
+        let elf = fs::read(elf_path).map_err(|error| RoverError::FailedToOpenElf {
+            source: error,
+            path: format!("{}", elf_path.display()),
+        })?;
+
+        let mut file =
+            File::create(path).map_err(|error| RoverError::FailedToCreateDefmtCapture {
+                source: error,
+                path: path.to_string(),
+            })?;
+        write!(
+            file,
+            "rover defmt raw capture\nrover: {}\nelf: {}\nelf-sha1: {:x}\nchannel: {}\n\n",
+            crate::util::PACKAGE_VERSION,
+            elf_path.display(),
+            Sha1::digest(&elf),
+            up
+        )
+        .map_err(|error| RoverError::FailedToCreateDefmtCapture {
+            source: error,
+            path: path.to_string(),
+        })?;
+
+        Ok(Self { file, key })
+    }
+
+    /// Appends the data if it was read by the captured channel kind.
+    fn write(&mut self, key: (usize, usize), data: &[u8]) {
+        if key != self.key || data.is_empty() {
+            return;
+        }
+        if let Err(error) = self.file.write_all(data) {
+            log::error!("Failed to write to the defmt capture: {}", error);
+        }
+    }
+}
+
 /// Finds and returns the address of the RTT header in the flash region of the ELF binary.
 fn get_rtt_symbol<T: Read + Seek>(file: &mut T) -> Option<u64> {
+    let address = get_symbol(file, "_SEGGER_RTT");
+    if address.is_none() {
+        log::warn!("No RTT header info was present in the ELF file. Does your firmware run RTT?");
+    }
+    address
+}
+
+/// Finds and returns the address of the symbol with the given name in the ELF binary.
+pub(crate) fn get_symbol<T: Read + Seek>(file: &mut T, name: &str) -> Option<u64> {
     let mut buffer = Vec::new();
     if file.read_to_end(&mut buffer).is_ok() {
         if let Ok(binary) = goblin::elf::Elf::parse(&buffer.as_slice()) {
             for sym in &binary.syms {
-                if let Some(Ok(name)) = binary.strtab.get(sym.st_name) {
-                    if name == "_SEGGER_RTT" {
+                if let Some(Ok(sym_name)) = binary.strtab.get(sym.st_name) {
+                    if sym_name == name {
                         return Some(sym.st_value);
                     }
                 }
@@ -194,29 +1161,244 @@ fn get_rtt_symbol<T: Read + Seek>(file: &mut T) -> Option<u64> {
         }
     }
 
-    log::warn!("No RTT header info was present in the ELF file. Does your firmware run RTT?");
     None
 }
 
 /// Polls the RTT target for new data on the specified channel.
 ///
-/// Processes all the new data and adds it to the linebuffer of the respective channel.
-pub fn poll_rtt(channel: &mut UpChannel) -> Vec<u8> {
+/// The data is read into the given buffer, which is reused across polls, so only the data which was read is copied.
+/// At most as many bytes as fit into the buffer are read per poll.
+/// A warning is logged once a read finds the channel full, as the firmware might have dropped data then.
+/// Another one is logged once a read fills the buffer while the channel holds more, as the rest is only read with the next poll.
+pub fn poll_rtt(channel: &mut UpChannel, buffer: &mut [u8], overflow: &mut Overflow) -> Vec<u8> {
     // TODO: Proper error handling.
-    let mut buffer = vec![0; 1 << 16];
-    let count = match channel.read(&mut buffer) {
+    let count = match channel.read(buffer) {
         Ok(count) => count,
         Err(err) => {
             log::error!("\nError reading from RTT: {}", err);
             return vec![];
         }
     };
-    buffer.truncate(count);
 
-    return buffer;
+    // The ring buffer of the target holds one byte less than its size.
+    let capacity = channel.buffer_size().saturating_sub(1);
+    let limited = count == buffer.len() && buffer.len() < capacity;
+    if overflow.update_limited(limited) {
+        log::warn!(
+            "Reading RTT up channel {} filled `logging.rtt_read_buffer` of {} bytes, which is smaller than the channel. The rest is read with the next poll. Raise `logging.rtt_read_buffer` to read the channel out faster.",
+            channel.number(),
+            buffer.len()
+        );
+    }
+
+    // A channel which blocks when it is full makes the firmware wait instead of dropping data.
+    // Its mode is read from the target, so it is only looked up once the channel was full.
+    let full = count >= capacity && !matches!(channel.mode(), Ok(ChannelMode::BlockIfFull));
+    if overflow.update(full) {
+        log::warn!(
+            "RTT up channel {} was full when it was read, so the firmware might have dropped data. Poll more often with `logging.poll_interval_us` or enlarge the buffer in the firmware.",
+            channel.number()
+        );
+    }
+
+    buffer[..count].to_vec()
+}
+
+/// Tracks whether an RTT up channel is overflowing or read out slower than it could be,
+/// so a warning is only given once per episode.
+#[derive(Debug, Default)]
+pub struct Overflow {
+    overflowing: bool,
+    limited: bool,
+}
+
+impl Overflow {
+    /// Records whether the channel was full when it was read.
+    /// Returns whether a new episode started, which is when the channel is full after it was not.
+    fn update(&mut self, full: bool) -> bool {
+        episode_started(&mut self.overflowing, full)
+    }
+
+    /// Records whether the read filled the buffer of the host while the channel held more.
+    /// Returns whether a new episode started.
+    fn update_limited(&mut self, limited: bool) -> bool {
+        episode_started(&mut self.limited, limited)
+    }
+}
+
+/// Updates whether a condition holds and returns whether it holds now after it did not before.
+fn episode_started(state: &mut bool, now: bool) -> bool {
+    let started = now && !*state;
+    *state = now;
+    started
 }
 
 /// Sends data back to the target.
+///
+/// Data which does not fit into the buffer of the down channel is dropped.
 pub fn push_rtt(channel: &mut DownChannel, data: &[u8]) {
-    channel.write(data).unwrap();
+    match channel.write(data) {
+        Ok(count) if count < data.len() => log::warn!(
+            "RTT down channel {} is full. Dropped {} bytes.",
+            channel.number(),
+            data.len() - count
+        ),
+        Ok(_) => (),
+        Err(err) => log::error!("\nError writing to RTT: {}", err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        net::TcpListener,
+        path::Path,
+        sync::{atomic::AtomicBool, Arc},
+        time::Instant,
+    };
+
+    use super::{
+        channel_summary, format_lines, resolve_rtt_channel, start_forwarding, up_label, LineBuffer,
+        Overflow,
+    };
+    use crate::{
+        config::{Channel, Logging, RttChannel, TimestampFormat},
+        diagnostics::RoverError,
+        updater::DEFAULT_POLL_INTERVAL,
+    };
+
+    #[test]
+    fn links_are_stopped_when_the_defmt_table_fails_to_load() {
+        // Take a free port for the link.
+        let address = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let channel: Channel = format!("rtt:0:defmt@tcp:{}", address).parse().unwrap();
+        let config: Logging =
+            serde_json::from_value(serde_json::json!({ "channels": [channel] })).unwrap();
+        let halt = Arc::new(AtomicBool::new(false));
+
+        let result = start_forwarding(
+            Path::new("missing.elf"),
+            &config,
+            &halt,
+            DEFAULT_POLL_INTERVAL,
+        );
+
+        assert!(matches!(result, Err(RoverError::FailedToOpenElf { .. })));
+        // The link was started before the ELF was loaded. Its listener is closed again, so the port is free.
+        TcpListener::bind(address).unwrap();
+    }
+
+    #[test]
+    fn lines_split_across_reads_are_joined() {
+        let mut buffer = LineBuffer::default();
+
+        assert!(buffer.push(b"hel").is_empty());
+        assert_eq!(buffer.push(b"lo\r\nwor"), vec!["hello"]);
+        // A character split across reads is only decoded once its line is complete.
+        assert!(buffer.push(&[b'l', b'd', b' ', 0xC3]).is_empty());
+        assert_eq!(buffer.push(&[0xA9, b'\n', b'\n']), vec!["world \u{e9}", ""]);
+        assert!(buffer.push(b"").is_empty());
+    }
+
+    #[test]
+    fn split_line_is_stamped_once() {
+        let start = Instant::now();
+        let mut buffer = LineBuffer::default();
+
+        // A line split across several RTT reads only gets its timestamp once it is complete.
+        let mut stamped = vec![];
+        for data in &[&b"hel"[..], b"lo wo", b"rld\nnext"] {
+            stamped.extend(format_lines(
+                &mut buffer,
+                data,
+                Some((TimestampFormat::Elapsed, start)),
+                Some("[APP] "),
+                &[],
+                false,
+            ));
+        }
+
+        assert_eq!(stamped.len(), 1);
+        assert!(stamped[0].starts_with('['));
+        assert!(stamped[0].ends_with("] [APP] hello world"));
+        assert_eq!(stamped[0].matches('[').count(), 2);
+    }
+
+    #[test]
+    fn channel_summary_hides_tokens() {
+        let channels = vec![
+            "(kinds: [Rtt(up: 0, down: 0, mode: String(timestamps: false))], link: Tcp(\"127.0.0.1:8080\", Some(\"secret\")))"
+                .parse::<Channel>()
+                .unwrap(),
+            "rtt:1:string@stdout".parse::<Channel>().unwrap(),
+        ];
+
+        let summary = channel_summary(&channels, &HashMap::new());
+
+        assert_eq!(
+            summary,
+            vec![
+                "Logging to 0 of 2 configured channels:",
+                "    [0]: Tcp '127.0.0.1:8080' (skipped)",
+                "    [1]: Stdout 'stdout' (skipped)",
+            ]
+        );
+        assert!(summary.iter().all(|line| !line.contains("secret")));
+    }
+
+    #[test]
+    fn rtt_channels_are_resolved_by_name() {
+        let names = vec![
+            Some("Terminal".to_string()),
+            None,
+            Some("defmt".to_string()),
+        ];
+
+        assert_eq!(
+            resolve_rtt_channel(&RttChannel::Name("defmt".into()), &names, "up").unwrap(),
+            2
+        );
+        assert_eq!(
+            resolve_rtt_channel(&RttChannel::Number(1), &names, "up").unwrap(),
+            1
+        );
+        assert_eq!(
+            resolve_rtt_channel(&RttChannel::Name("log".into()), &names, "up")
+                .unwrap_err()
+                .to_string(),
+            "The RTT up channel 'log' does not exist."
+        );
+    }
+
+    #[test]
+    fn forwarded_channels_are_labeled_unless_binary() {
+        assert_eq!(
+            up_label(0, Some("Terminal")),
+            Some("[Terminal] ".to_string())
+        );
+        assert_eq!(up_label(1, None), Some("[1] ".to_string()));
+        assert_eq!(up_label(2, Some("defmt")), None);
+    }
+
+    #[test]
+    fn overflows_are_reported_once_per_episode() {
+        let mut overflow = Overflow::default();
+
+        assert!(!overflow.update(false));
+        assert!(overflow.update(true));
+        assert!(!overflow.update(true));
+        assert!(!overflow.update(false));
+        assert!(overflow.update(true));
+
+        // Hitting the limit of the host buffer is tracked separately.
+        assert!(overflow.update_limited(true));
+        assert!(!overflow.update(true));
+        assert!(!overflow.update_limited(true));
+        assert!(!overflow.update_limited(false));
+        assert!(overflow.update_limited(true));
+    }
 }
@@ -0,0 +1,83 @@
+//! Recovery of locked nRF52 chips by erasing them through their CTRL-AP.
+
+use std::time::{Duration, Instant};
+
+use probe_rs::{architecture::arm::DapAccess, DebugProbeError, Probe};
+
+use crate::diagnostics::RoverError;
+
+/// The access port of the nRF52 which controls the erase and reset independently of the core.
+const CTRL_AP: u8 = 1;
+/// The identification register of an access port and the value it holds for the CTRL-AP.
+const IDR: u8 = 0xfc;
+const CTRL_AP_IDR: u32 = 0x0288_0000;
+/// The CTRL-AP registers used to erase the chip.
+const RESET: u8 = 0x000;
+const ERASEALL: u8 = 0x004;
+const ERASEALLSTATUS: u8 = 0x008;
+
+/// How long erasing the whole chip may take. The datasheets specify up to about 300 ms.
+const ERASE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Whether the recovery sequence applies to the given chip.
+/// Only the nRF52 family has its CTRL-AP at this port with these registers.
+pub fn is_supported(chip: &str) -> bool {
+    chip.to_lowercase().starts_with("nrf52")
+}
+
+/// Erases the whole chip including the UICR through the CTRL-AP, which also removes the access port protection.
+/// All flash contents are lost.
+pub fn recover(probe: Probe, chip: &str) -> Result<Probe, RoverError> {
+    let interface = probe
+        .try_into_arm_interface()
+        .map_err(|(_, error)| RoverError::NrfRecoverFailed(error.into()))?;
+    let mut interface = interface
+        .initialize()
+        .map_err(|(_, error)| RoverError::NrfRecoverFailed(error))?;
+
+    let idr = interface
+        .read_raw_ap_register(CTRL_AP, IDR)
+        .map_err(|error| RoverError::NrfRecoverFailed(error.into()))?;
+    if idr != CTRL_AP_IDR {
+        return Err(RoverError::NrfRecoverUnsupported {
+            chip: chip.to_string(),
+        });
+    }
+
+    erase_all(&mut *interface).map_err(|error| RoverError::NrfRecoverFailed(error.into()))?;
+    log::info!("Erased {} through the CTRL-AP.", chip);
+
+    Ok(interface.close())
+}
+
+/// Runs the erase all sequence of the CTRL-AP and resets the chip afterwards.
+fn erase_all<I: DapAccess + ?Sized>(interface: &mut I) -> Result<(), DebugProbeError> {
+    interface.write_raw_ap_register(CTRL_AP, ERASEALL, 1)?;
+
+    let start = Instant::now();
+    while interface.read_raw_ap_register(CTRL_AP, ERASEALLSTATUS)? != 0 {
+        if start.elapsed() > ERASE_TIMEOUT {
+            return Err(DebugProbeError::Timeout);
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    // The protection is only lifted after a reset.
+    interface.write_raw_ap_register(CTRL_AP, RESET, 1)?;
+    interface.write_raw_ap_register(CTRL_AP, RESET, 0)?;
+    interface.write_raw_ap_register(CTRL_AP, ERASEALL, 0)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::is_supported;
+
+    #[test]
+    fn only_nrf52_chips_are_recovered() {
+        assert!(is_supported("nRF52840_xxAA"));
+        assert!(is_supported("nrf52832_xxAB"));
+        assert!(!is_supported("nRF51822_xxAA"));
+        assert!(!is_supported("STM32F407VGTx"));
+    }
+}
@@ -0,0 +1,145 @@
+//! Switching the power the probe supplies to the target.
+
+use std::time::Duration;
+
+use jaylink::JayLink;
+use probe_rs::{DebugProbeInfo, DebugProbeSelector, DebugProbeType, Probe};
+
+use crate::config::{Config, TargetPower};
+
+/// Switches the power the probe supplies to the target and waits for it to settle.
+///
+/// Only J-Link probes can switch the target power, through their 5V kickstart supply.
+/// CMSIS-DAP has no standard command for it and probe-rs does not support the vendor specific ones.
+/// The J-Link is opened through a separate connection by its serial number, so the power is only switched
+/// if the probe the config selects is a J-Link. Otherwise an unrelated J-Link could power off another target.
+/// Failing to switch the power is only reported as a warning, as the target might be powered otherwise.
+pub fn switch_target_power(config: &Config, target_power: TargetPower) {
+    let settle = Duration::from_millis(config.probe().target_power_settle_ms());
+
+    let result = selected_jlink_serial(config, &Probe::list_all()).and_then(|serial| {
+        JayLink::open_by_serial(Some(&serial))
+            .and_then(|mut probe| {
+                match target_power {
+                    TargetPower::On => probe.set_kickstart_power(true)?,
+                    TargetPower::Off => probe.set_kickstart_power(false)?,
+                    TargetPower::Cycle => {
+                        probe.set_kickstart_power(false)?;
+                        std::thread::sleep(settle);
+                        probe.set_kickstart_power(true)?;
+                    }
+                }
+                Ok(())
+            })
+            .map_err(|error| error.to_string())
+    });
+
+    match result {
+        Ok(()) => {
+            let switched = match target_power {
+                TargetPower::On => "on",
+                TargetPower::Off => "off",
+                TargetPower::Cycle => "off and on again",
+            };
+            log::info!("Switched the target power {}.", switched);
+            std::thread::sleep(settle);
+        }
+        Err(error) => log::warn!(
+            "Failed to switch the target power: {}. Only J-Link probes can supply power to the target, so make sure it is powered otherwise or remove `probe.target_power`.",
+            error
+        ),
+    }
+}
+
+/// Finds the probe the config selects among the connected probes, the same way the probe is opened,
+/// and returns its serial number if it is a J-Link.
+fn selected_jlink_serial(config: &Config, probes: &[DebugProbeInfo]) -> Result<String, String> {
+    let probe = config.probe();
+    let selector = match (probe.selector(), probe.usb_vid(), probe.usb_pid()) {
+        (Some(selector), _, _) => Some(selector.clone()),
+        (None, Some(vid), Some(pid)) => Some(DebugProbeSelector {
+            vendor_id: u16::from_str_radix(vid, 16).map_err(|error| error.to_string())?,
+            product_id: u16::from_str_radix(pid, 16).map_err(|error| error.to_string())?,
+            serial_number: probe.serial().clone(),
+        }),
+        _ => None,
+    };
+
+    let candidates = match (&selector, probe.index(), probe.serial()) {
+        (Some(selector), _, _) => probes
+            .iter()
+            .filter(|info| {
+                info.vendor_id == selector.vendor_id
+                    && info.product_id == selector.product_id
+                    && (selector.serial_number.is_none()
+                        || info.serial_number == selector.serial_number)
+            })
+            .collect::<Vec<_>>(),
+        (None, Some(index), _) => probes.get(index).into_iter().collect(),
+        (None, None, Some(serial)) => probes
+            .iter()
+            .filter(|info| info.serial_number.as_ref() == Some(serial))
+            .collect(),
+        (None, None, None) => probes.iter().collect(),
+    };
+
+    match candidates.as_slice() {
+        [] => Err("the selected probe was not found".into()),
+        [info] if !matches!(info.probe_type, DebugProbeType::JLink) => {
+            Err(format!("the selected probe is a {:?}", info.probe_type))
+        }
+        [info] => info
+            .serial_number
+            .clone()
+            .ok_or_else(|| "the selected J-Link does not report a serial number".into()),
+        _ => Err("several probes match, so select the J-Link with `probe.serial`".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use probe_rs::{DebugProbeInfo, DebugProbeType};
+    use structopt::StructOpt;
+
+    use super::selected_jlink_serial;
+    use crate::config::Config;
+
+    fn probes() -> Vec<DebugProbeInfo> {
+        vec![
+            DebugProbeInfo::new(
+                "J-Link",
+                0x1366,
+                0x0101,
+                Some("000123".into()),
+                DebugProbeType::JLink,
+            ),
+            DebugProbeInfo::new(
+                "ST-Link",
+                0x0483,
+                0x374b,
+                Some("0671FF".into()),
+                DebugProbeType::StLink,
+            ),
+        ]
+    }
+
+    #[test]
+    fn power_is_only_switched_through_the_selected_jlink() {
+        let select = |args: &[&str]| {
+            let config = Config::from_iter(std::iter::once(&"rover").chain(args));
+            selected_jlink_serial(&config, &probes())
+        };
+
+        assert_eq!(select(&["--probe.index", "0"]), Ok("000123".into()));
+        assert_eq!(select(&["--probe.serial", "000123"]), Ok("000123".into()));
+        assert_eq!(
+            select(&["--probe.selector", "1366:0101"]),
+            Ok("000123".into())
+        );
+        // The ST-Link is selected, so the J-Link next to it must not be touched.
+        assert!(select(&["--probe.index", "1"]).is_err());
+        assert!(select(&["--probe.selector", "0483:374b"]).is_err());
+        // Without a selection, either of the two probes might be the one which is opened.
+        assert!(select(&[]).is_err());
+    }
+}
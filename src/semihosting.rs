@@ -0,0 +1,160 @@
+//! Capturing the text output of ARM semihosting calls.
+//!
+//! A semihosting call is a `BKPT 0xAB` instruction, which halts the core with the operation in r0 and its
+//! parameter in r1. The call is answered by the debugger, which then resumes the core behind the instruction.
+
+use probe_rs::{config::MemoryRegion, Core, CoreRegisterAddress, MemoryInterface, Session};
+
+/// The encoding of the Thumb `BKPT 0xAB` instruction, little endian.
+const BKPT_SEMIHOSTING: [u8; 2] = [0xab, 0xbe];
+
+/// Writes the character r1 points to.
+const SYS_WRITEC: u32 = 0x03;
+/// Writes the NUL terminated string r1 points to.
+const SYS_WRITE0: u32 = 0x04;
+
+/// Strings longer than this are cut off, in case the firmware passes a string which is not terminated.
+const MAX_STRING_LENGTH: usize = 4096;
+const READ_CHUNK_SIZE: usize = 64;
+
+const R0: CoreRegisterAddress = CoreRegisterAddress(0);
+const R1: CoreRegisterAddress = CoreRegisterAddress(1);
+const PC: CoreRegisterAddress = CoreRegisterAddress(15);
+
+/// Answers a pending semihosting call of the first core and returns the text it wrote.
+///
+/// Returns no text if the core is running or halted for any other reason, e.g. a breakpoint of GDB,
+/// in which case the core is left as it is.
+pub fn poll(session: &mut Session) -> Result<Vec<u8>, probe_rs::Error> {
+    let memory_map = session.target().memory_map.clone();
+    let mut core = session.core(0)?;
+    if !core.core_halted()? {
+        return Ok(vec![]);
+    }
+
+    let pc = core.read_core_reg(PC)?;
+    let mut instruction = [0; 2];
+    core.read_8(pc, &mut instruction)?;
+    if !is_semihosting_call(instruction) {
+        return Ok(vec![]);
+    }
+
+    let operation = core.read_core_reg(R0)?;
+    let parameter = core.read_core_reg(R1)?;
+    let text = match operation {
+        SYS_WRITEC => {
+            let mut character = [0];
+            core.read_8(parameter, &mut character)?;
+            character.to_vec()
+        }
+        SYS_WRITE0 => match region_end(&memory_map, parameter) {
+            Some(end) => read_string(&mut core, parameter, end)?,
+            None => {
+                log::warn!(
+                    "The semihosting string at {:#010x} is outside of the memory of the target.",
+                    parameter
+                );
+                vec![]
+            }
+        },
+        _ => {
+            log::warn!(
+                "The semihosting operation {:#04x} is not supported and fails on the target.",
+                operation
+            );
+            core.write_core_reg(R0, u32::MAX)?;
+            vec![]
+        }
+    };
+
+    // Resume behind the breakpoint instruction.
+    core.write_core_reg(PC, pc + 2)?;
+    core.run()?;
+
+    Ok(text)
+}
+
+/// Whether the instruction the core halted at is a semihosting call.
+fn is_semihosting_call(instruction: [u8; 2]) -> bool {
+    instruction == BKPT_SEMIHOSTING
+}
+
+/// The exclusive end of the memory region the address lies in.
+fn region_end(memory_map: &[MemoryRegion], address: u32) -> Option<u32> {
+    memory_map.iter().find_map(|region| {
+        let range = match region {
+            MemoryRegion::Ram(ram) => &ram.range,
+            MemoryRegion::Generic(generic) => &generic.range,
+            MemoryRegion::Nvm(flash) => &flash.range,
+        };
+        if range.contains(&address) {
+            Some(range.end)
+        } else {
+            None
+        }
+    })
+}
+
+/// The length of the next chunk of a string at `address` of which `read` bytes were read already.
+/// The chunks never reach past the `end` of the memory region or past `MAX_STRING_LENGTH`.
+fn chunk_length(address: u32, read: usize, end: u32) -> usize {
+    let left_in_region = (end - address) as usize - read;
+    READ_CHUNK_SIZE
+        .min(left_in_region)
+        .min(MAX_STRING_LENGTH - read)
+}
+
+/// Reads the NUL terminated string at the address, without reading past the `end` of its memory region.
+fn read_string(core: &mut Core, address: u32, end: u32) -> Result<Vec<u8>, probe_rs::Error> {
+    let mut string = vec![];
+    loop {
+        let length = chunk_length(address, string.len(), end);
+        if length == 0 {
+            return Ok(string);
+        }
+
+        let mut chunk = vec![0; length];
+        core.read_8(address + string.len() as u32, &mut chunk)?;
+        match until_nul(&chunk) {
+            Some(end) => {
+                string.extend_from_slice(end);
+                return Ok(string);
+            }
+            None => string.extend_from_slice(&chunk),
+        }
+    }
+}
+
+/// Returns the data up to the first NUL, if there is one.
+fn until_nul(data: &[u8]) -> Option<&[u8]> {
+    data.iter()
+        .position(|byte| *byte == 0)
+        .map(|end| &data[..end])
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chunk_length, is_semihosting_call, until_nul, MAX_STRING_LENGTH};
+
+    #[test]
+    fn semihosting_calls_are_recognized() {
+        assert!(is_semihosting_call([0xab, 0xbe]));
+        // A plain `BKPT 0x00`, as GDB places them.
+        assert!(!is_semihosting_call([0x00, 0xbe]));
+
+        assert_eq!(until_nul(b"Hello\n\0rest"), Some(&b"Hello\n"[..]));
+        assert_eq!(until_nul(b"Hello"), None);
+    }
+
+    #[test]
+    fn strings_are_not_read_past_their_region() {
+        assert_eq!(chunk_length(0x2000_0000, 0, 0x2000_1000), 64);
+        // Only 10 bytes are left before the end of the RAM.
+        assert_eq!(chunk_length(0x2000_0ff0, 6, 0x2000_1000), 10);
+        assert_eq!(chunk_length(0x2000_0ff0, 16, 0x2000_1000), 0);
+        assert_eq!(
+            chunk_length(0x2000_0000, MAX_STRING_LENGTH - 8, 0x3000_0000),
+            8
+        );
+    }
+}
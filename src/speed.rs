@@ -0,0 +1,47 @@
+//! The protocol speeds of the debug probes, as far as they are known up front.
+
+/// The SWD speeds in kHz the ST-Link generations support. Other speeds are snapped to the closest lower one.
+const STLINK_SPEEDS: &[u32] = &[
+    5, 15, 25, 50, 100, 125, 200, 240, 480, 950, 1000, 1200, 1800, 3300, 4000, 8000, 24000,
+];
+
+/// Returns the speeds in kHz the probe with the given driver name supports, in ascending order.
+/// Probes which support arbitrary speeds, or whose speeds are not known, return `None`.
+pub fn supported_speeds(driver: &str) -> Option<&'static [u32]> {
+    if driver.to_lowercase().replace('-', "").contains("stlink") {
+        Some(STLINK_SPEEDS)
+    } else {
+        None
+    }
+}
+
+/// Checks whether the requested speed lies within the range of the supported speeds.
+/// Returns the two supported speeds closest to the requested one if it does not.
+pub fn check(supported: &[u32], requested: u32) -> Result<(), Vec<u32>> {
+    match (supported.first(), supported.last()) {
+        (Some(&min), Some(&max)) if requested < min || requested > max => {
+            let mut nearest = supported.to_vec();
+            nearest.sort_by_key(|speed| (*speed as i64 - requested as i64).abs());
+            nearest.truncate(2);
+            Err(nearest)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, supported_speeds};
+
+    #[test]
+    fn speeds_outside_of_the_range_are_rejected() {
+        let speeds = supported_speeds("ST-Link V2").unwrap();
+
+        assert_eq!(check(speeds, 1800), Ok(()));
+        // Speeds between the supported ones are snapped by the probe.
+        assert_eq!(check(speeds, 2000), Ok(()));
+        assert_eq!(check(speeds, 50_000), Err(vec![24000, 8000]));
+        assert_eq!(check(speeds, 1), Err(vec![5, 15]));
+        assert_eq!(supported_speeds("CMSIS-DAP"), None);
+    }
+}
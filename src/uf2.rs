@@ -0,0 +1,151 @@
+//! Parsing of the UF2 file format as described in https://github.com/microsoft/uf2.
+
+use std::convert::TryInto;
+
+/// The size of a single UF2 block.
+const BLOCK_SIZE: usize = 512;
+/// The maximum number of payload bytes in a single block.
+const MAX_PAYLOAD_SIZE: usize = 476;
+
+const MAGIC_START_0: u32 = 0x0A32_4655;
+const MAGIC_START_1: u32 = 0x9E5D_5157;
+const MAGIC_END: u32 = 0x0AB1_6F30;
+
+/// The block is not meant to be written to the main flash.
+const FLAG_NOT_MAIN_FLASH: u32 = 0x0000_0001;
+/// The block is part of a file container and not plain flash contents.
+const FLAG_FILE_CONTAINER: u32 = 0x0000_1000;
+/// The block carries a family ID instead of the file size.
+const FLAG_FAMILY_ID_PRESENT: u32 = 0x0000_2000;
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum Uf2Error {
+    #[error("The file size of {0} bytes is not a multiple of the UF2 block size of 512 bytes.")]
+    InvalidLength(usize),
+    #[error("Block {0} does not start and end with the UF2 magic numbers.")]
+    InvalidMagic(usize),
+    #[error("Block {block} declares a payload of {size} bytes which does not fit into a block.")]
+    InvalidPayloadSize { block: usize, size: usize },
+}
+
+/// A contiguous block of data decoded from a UF2 file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Decodes the data of all the flash blocks in the given UF2 file.
+///
+/// If a family is given, blocks which are tagged with a different family are skipped.
+/// Consecutive blocks are merged into a single segment.
+pub fn parse(content: &[u8], family: Option<u32>) -> Result<Vec<Segment>, Uf2Error> {
+    if content.len() % BLOCK_SIZE != 0 {
+        return Err(Uf2Error::InvalidLength(content.len()));
+    }
+
+    let mut segments: Vec<Segment> = vec![];
+
+    for (index, block) in content.chunks(BLOCK_SIZE).enumerate() {
+        // This unwrap is fine as every block has a fixed size of 512 bytes.
+        let word =
+            |offset: usize| u32::from_le_bytes(block[offset..offset + 4].try_into().unwrap());
+
+        if word(0) != MAGIC_START_0 || word(4) != MAGIC_START_1 || word(508) != MAGIC_END {
+            return Err(Uf2Error::InvalidMagic(index));
+        }
+
+        let flags = word(8);
+        if flags & (FLAG_NOT_MAIN_FLASH | FLAG_FILE_CONTAINER) != 0 {
+            continue;
+        }
+        if let Some(family) = family {
+            if flags & FLAG_FAMILY_ID_PRESENT != 0 && word(28) != family {
+                continue;
+            }
+        }
+
+        let address = word(12);
+        let size = word(16) as usize;
+        if size > MAX_PAYLOAD_SIZE {
+            return Err(Uf2Error::InvalidPayloadSize { block: index, size });
+        }
+        let data = &block[32..32 + size];
+
+        match segments.last_mut() {
+            Some(last) if last.address as u64 + last.data.len() as u64 == address as u64 => {
+                last.data.extend_from_slice(data)
+            }
+            _ => segments.push(Segment {
+                address,
+                data: data.to_vec(),
+            }),
+        }
+    }
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, Segment, Uf2Error, FLAG_FAMILY_ID_PRESENT};
+
+    fn block(address: u32, data: &[u8], family: Option<u32>) -> Vec<u8> {
+        let mut block = vec![0u8; 512];
+        let mut put = |offset: usize, value: u32| {
+            block[offset..offset + 4].copy_from_slice(&value.to_le_bytes())
+        };
+        put(0, 0x0A32_4655);
+        put(4, 0x9E5D_5157);
+        put(8, family.map_or(0, |_| FLAG_FAMILY_ID_PRESENT));
+        put(12, address);
+        put(16, data.len() as u32);
+        put(28, family.unwrap_or(0));
+        put(508, 0x0AB1_6F30);
+        block[32..32 + data.len()].copy_from_slice(data);
+        block
+    }
+
+    #[test]
+    fn parse_merges_consecutive_blocks() {
+        let mut content = block(0x1000, &[1, 2], None);
+        content.extend(block(0x1002, &[3, 4], None));
+        content.extend(block(0x2000, &[5], None));
+
+        assert_eq!(
+            parse(&content, None),
+            Ok(vec![
+                Segment {
+                    address: 0x1000,
+                    data: vec![1, 2, 3, 4],
+                },
+                Segment {
+                    address: 0x2000,
+                    data: vec![5],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_filters_families() {
+        let mut content = block(0x1000, &[1], Some(0xE48B_FF56));
+        content.extend(block(0x1000, &[2], Some(0xADA5_2840)));
+
+        assert_eq!(
+            parse(&content, Some(0xADA5_2840)),
+            Ok(vec![Segment {
+                address: 0x1000,
+                data: vec![2],
+            }])
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_magic() {
+        let mut content = block(0x1000, &[1], None);
+        content[0] = 0;
+
+        assert_eq!(parse(&content, None), Err(Uf2Error::InvalidMagic(0)));
+    }
+}
@@ -0,0 +1,171 @@
+use std::sync::mpsc::{channel, sync_channel, RecvTimeoutError, Sender};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use std::{
+    fmt::Debug,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// An updater which appends all its updates to a file.
+/// Nothing is ever read back, so the channel to the user never yields any updates.
+pub struct FileUpdater {
+    path: PathBuf,
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl FileUpdater {
+    /// Creates a new file updater.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            thread_handle: None,
+        }
+    }
+
+    /// Writes a message to the file.
+    /// Bytes and strings are written as they are while structured updates are written as one line of JSON each.
+    fn write_to_file<O>(file: &mut File, update: &Value<O>) -> std::io::Result<()>
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        match update {
+            Value::StructuredString(update) => {
+                writeln!(file, "{}", serde_json::to_string(update).unwrap())?
+            }
+            Value::Bytes(bytes) => file.write_all(bytes)?,
+            Value::String(string) => file.write_all(string.as_bytes())?,
+        }
+        file.flush()
+    }
+
+    fn write_or_log<O>(file: &mut File, path: &Path, update: &Value<O>)
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        if let Err(err) = Self::write_to_file(file, update) {
+            log::error!(
+                "Writing to file '{}' experienced an error: {:?}",
+                path.display(),
+                err
+            )
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for FileUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        // The updates are awaited instead of polled, so there is nothing to pause.
+        _poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        // There is no read side, so the sending end is dropped right away.
+        let (_, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        log::info!("Opening file '{}'", self.path.display());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let path = self.path.clone();
+
+        self.thread_handle = Some((
+            spawn(move || loop {
+                // If a halt was requested, write what is still queued and cease operations.
+                if halt_rx.try_recv().is_ok() {
+                    for update in inbound.try_iter() {
+                        Self::write_or_log(&mut file, &path, &update);
+                    }
+                    return;
+                }
+
+                match inbound.recv_timeout(Duration::from_millis(100)) {
+                    Ok(update) => Self::write_or_log(&mut file, &path, &update),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        match thread_handle.map(|h| {
+            // If the thread already ended because the channel was closed, nobody receives the request anymore.
+            let _ = h.1.send(());
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::FileUpdater;
+    use crate::updater::{Updater, UpdaterChannel, Value};
+
+    #[test]
+    fn append_to_file() {
+        let path = std::env::temp_dir().join(format!("rover-file-{}.log", std::process::id()));
+        std::fs::write(&path, "existing\n").unwrap();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+
+        FileUpdater::write_to_file::<serde_json::Value>(&mut file, &Value::String("line\n".into()))
+            .unwrap();
+        FileUpdater::write_to_file(
+            &mut file,
+            &Value::StructuredString(serde_json::json!({ "answer": 42 })),
+        )
+        .unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, "existing\nline\n{\"answer\":42}\n");
+    }
+
+    #[test]
+    fn queued_updates_are_written_on_stop() {
+        let path = std::env::temp_dir().join(format!("rover-file-stop-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut updater = FileUpdater::new(&path);
+        let mut channel: UpdaterChannel<(), serde_json::Value> =
+            updater.start(16, Duration::from_micros(100)).unwrap();
+        for i in 0..10 {
+            channel
+                .tx()
+                .send(Value::String(format!("{}\n", i)))
+                .unwrap();
+        }
+        Updater::<(), serde_json::Value>::stop(&mut updater).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    }
+}
@@ -1,22 +1,65 @@
+pub mod file;
+pub mod serial;
 pub mod stdio;
+pub mod stdout;
 pub mod tcp;
+pub mod terminal;
+pub mod udp;
+#[cfg(unix)]
+pub mod unix;
 pub mod websocket;
 
 use std::fmt::Debug;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::{Receiver, SyncSender, TrySendError};
+use std::time::{Duration, Instant};
 
 use serde::{de::DeserializeOwned, Serialize};
 
+/// How long the updaters pause between polls of their link by default.
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_micros(100);
+
+/// How long an updater keeps delivering the updates queued before it was stopped.
+pub const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a client of a link with a token has to send it before it is dropped.
+pub const AUTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often at most a link which drops updates is warned about.
+const DROP_WARNING_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Whether the first message of a client matches the token of the link.
+/// A trailing line break is ignored. The comparison takes the same time wherever the message differs.
+pub fn token_matches(message: &[u8], token: &str) -> bool {
+    let message = message
+        .strip_suffix(b"\n")
+        .map(|message| message.strip_suffix(b"\r").unwrap_or(message))
+        .unwrap_or(message);
+    message.len() == token.len()
+        && message
+            .iter()
+            .zip(token.as_bytes())
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
 /// The `Updater` trait specifies an interface for a statemachine updater.
 /// An `Updater` is basically a self contained unit that runs asynchronously and pushes/receives events to/from mpscs.
 pub trait Updater<I, O> {
     /// Starts the `Updater`.
     /// This should never block and run the `Updater` asynchronously.
-    fn start(&mut self) -> UpdaterChannel<I, O>
+    /// At most `buffer` updates are queued for the `Updater`. Once the queue is full, `UpdaterChannel::send` drops further updates until the `Updater` catches up.
+    /// The `Updater` pauses for `poll_interval` between polls of its link.
+    /// Returns `Err` if the underlying link could not be opened.
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
     where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
         O: Serialize + Send + Sync + Debug + 'static;
     /// Stops the `Updater` if currently running.
+    /// The updates queued before are delivered first, as far as the link takes them.
     /// Returns `Ok` if everything went smooth during the run of the `Updater`.
     /// Returns `Err` if something went wrong during the run of the `Updater`.
     fn stop(&mut self) -> Result<(), ()>;
@@ -38,7 +81,12 @@ where
     /// The rx where the user reads data from.
     rx: Receiver<Value<I>>,
     /// The tx where the user sends data to.
-    tx: Sender<Value<O>>,
+    tx: SyncSender<Value<O>>,
+    /// The name of the link in the warnings about dropped updates.
+    label: String,
+    /// The updates dropped since the last warning about them.
+    dropped: usize,
+    last_warning: Option<Instant>,
 }
 
 impl<I, O> UpdaterChannel<I, O>
@@ -49,8 +97,43 @@ where
     /// Creates a new `UpdaterChannel` where crossover is done internally.
     ///
     /// The argument naming is done from the `Updater`s view. Where as the member naming is done from a user point of view.
-    pub fn new(rx: Sender<Value<O>>, tx: Receiver<Value<I>>) -> Self {
-        Self { rx: tx, tx: rx }
+    pub fn new(rx: SyncSender<Value<O>>, tx: Receiver<Value<I>>) -> Self {
+        Self {
+            rx: tx,
+            tx: rx,
+            label: "a logging link".into(),
+            dropped: 0,
+            last_warning: None,
+        }
+    }
+
+    /// Sets the name of the link the warnings about dropped updates refer to.
+    pub fn set_label(&mut self, label: impl Into<String>) {
+        self.label = label.into();
+    }
+
+    /// Queues an update for the `Updater` without blocking.
+    ///
+    /// If the queue is full, the update is dropped, so a link which does not keep up cannot hold up the others.
+    /// The dropped updates are counted and warned about at most once per `DROP_WARNING_INTERVAL`.
+    /// Updates for an `Updater` which already ended are discarded silently.
+    pub fn send(&mut self, update: Value<O>) {
+        if let Err(TrySendError::Full(_)) = self.tx.try_send(update) {
+            self.dropped += 1;
+        }
+
+        let warn = self.last_warning.map_or(true, |last_warning| {
+            last_warning.elapsed() >= DROP_WARNING_INTERVAL
+        });
+        if self.dropped > 0 && warn {
+            log::warn!(
+                "Dropped {} updates for {} as it does not keep up with the target.",
+                self.dropped,
+                self.label
+            );
+            self.dropped = 0;
+            self.last_warning = Some(Instant::now());
+        }
     }
 
     /// Returns the rx end of the channel.
@@ -59,7 +142,58 @@ where
     }
 
     /// Returns the tx end of the channel.
-    pub fn tx(&mut self) -> &mut Sender<Value<O>> {
+    pub fn tx(&mut self) -> &mut SyncSender<Value<O>> {
         &mut self.tx
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc::{channel, sync_channel},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::{UpdaterChannel, Value};
+
+    #[test]
+    fn sending_to_a_stalled_link_does_not_block() {
+        // The link never takes any of its updates.
+        let (tx, inbound) = sync_channel(2);
+        let (_outbound, rx) = channel::<Value<()>>();
+        let mut channel = UpdaterChannel::<(), ()>::new(tx, rx);
+        let halt = Arc::new(AtomicBool::new(false));
+
+        // Forward updates until halted, like the logging does.
+        let forwarding = {
+            let halt = halt.clone();
+            thread::spawn(move || {
+                let mut sent = 0;
+                while !halt.load(Ordering::Relaxed) {
+                    channel.send(Value::String(sent.to_string()));
+                    sent += 1;
+                    thread::sleep(Duration::from_millis(1));
+                }
+                sent
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        halt.store(true, Ordering::Relaxed);
+
+        assert!(forwarding.join().unwrap() > 2);
+        // The queued updates are kept and the newer ones are dropped.
+        let queued = inbound
+            .try_iter()
+            .map(|update| match update {
+                Value::String(update) => update,
+                _ => panic!("Only strings were sent."),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(queued, vec!["0", "1"]);
+    }
+}
@@ -0,0 +1,350 @@
+use std::sync::mpsc::{channel, sync_channel, Sender};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{fmt::Debug, io::Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+use serialport::SerialPort;
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// How long a read from the serial port waits for new data before the loop carries on.
+/// The port applies it to writes as well, so writes which time out are retried.
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// How long a write waits for the port to take more bytes before it gives up.
+/// Slow ports only take a few bytes per millisecond, but any port takes some within this time.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// An updater which receives and sends it's updates from and to a serial port on the host.
+/// If the port disconnects, all further updates are dropped.
+pub struct SerialUpdater {
+    path: String,
+    baud: u32,
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl SerialUpdater {
+    /// Creates a new serial port updater.
+    pub fn new(path: impl Into<String>, baud: u32) -> Self {
+        Self {
+            path: path.into(),
+            baud,
+            thread_handle: None,
+        }
+    }
+
+    /// Writes a message to the serial port.
+    /// Returns `false` once the port was disconnected.
+    fn write_to_port<O, W>(port: &mut W, path: &str, update: &Value<O>) -> bool
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+        W: Write + ?Sized,
+    {
+        let update = match update {
+            Value::StructuredString(update) => write_patiently(
+                port,
+                serde_json::to_string(update).unwrap().as_bytes(),
+                WRITE_TIMEOUT,
+            ),
+            Value::Bytes(bytes) => write_patiently(port, bytes, WRITE_TIMEOUT),
+            Value::String(string) => write_patiently(port, string.as_bytes(), WRITE_TIMEOUT),
+        };
+
+        match update {
+            Ok(_) => true,
+            Err(err) => match err.kind() {
+                std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::NotFound => {
+                    log::info!("Serial port {} was disconnected", path);
+                    false
+                }
+                _ => {
+                    log::error!(
+                        "Writing to serial port {} experienced an error: {:?}",
+                        path,
+                        err
+                    );
+                    true
+                }
+            },
+        }
+    }
+
+    /// Reads the next message from the serial port.
+    /// Returns `false` once the port was disconnected.
+    fn read_from_port<I>(
+        port: &mut Box<dyn SerialPort>,
+        path: &str,
+        sender: &Sender<Value<I>>,
+    ) -> bool
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+    {
+        let mut buffer = vec![0u8; 1 << 16];
+        match port.read(&mut buffer) {
+            // A serial port has no notion of a closed connection, so an empty read just means there is no data.
+            Ok(0) => true,
+            Ok(count) => {
+                buffer.truncate(count);
+                match String::from_utf8(buffer.clone()) {
+                    Ok(string) => {
+                        let _ = sender.send(Value::String(string));
+                    }
+                    Err(error) => {
+                        log::debug!("Failed to parse string: {:#?}", error);
+                        let _ = sender.send(Value::Bytes(buffer));
+                    }
+                }
+                true
+            }
+            Err(err) => match err.kind() {
+                // There is no new data available.
+                std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock => true,
+                std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::NotFound => {
+                    log::info!("Serial port {} was disconnected", path);
+                    false
+                }
+                _ => {
+                    log::error!(
+                        "Reading from serial port {} experienced an error: {:?}",
+                        path,
+                        err
+                    );
+                    true
+                }
+            },
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for SerialUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        let (outbound, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        log::info!("Opening serial port {} at {} baud", self.path, self.baud);
+        let port = serialport::new(&self.path, self.baud)
+            .timeout(READ_TIMEOUT)
+            .open()?;
+        let path = self.path.clone();
+
+        self.thread_handle = Some((
+            spawn(move || {
+                // The port is dropped once it disconnects. Updates are still drained so they do not pile up.
+                let mut port = Some(port);
+                loop {
+                    // If a halt was requested, write what is still queued and cease operations.
+                    if halt_rx.try_recv().is_ok() {
+                        for update in inbound.try_iter() {
+                            if let Some(p) = port.as_mut() {
+                                if !Self::write_to_port(&mut **p, &path, &update) {
+                                    port = None;
+                                }
+                            }
+                        }
+                        if let Some(p) = port.as_mut() {
+                            let _ = p.flush();
+                        }
+                        return;
+                    }
+
+                    // Read at max one new message from the port.
+                    if let Some(p) = port.as_mut() {
+                        if !Self::read_from_port(p, &path, &outbound) {
+                            port = None;
+                        }
+                    }
+
+                    // Send at max one pending message to the port.
+                    if let Ok(update) = inbound.try_recv() {
+                        if let Some(p) = port.as_mut() {
+                            if !Self::write_to_port(&mut **p, &path, &update) {
+                                port = None;
+                            }
+                        }
+                    }
+
+                    // Pause the current thread to not use CPU for no reason.
+                    sleep(poll_interval);
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        match thread_handle.map(|h| {
+            // If we have a running thread, send the request to stop it and then wait for a join.
+            // If this unwrap fails the thread has already been destroyed.
+            // This cannot be assumed under normal operation conditions. Even with normal fault handling this should never happen.
+            // So this unwarp is fine.
+            h.1.send(()).unwrap();
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Writes all of the data to the port.
+/// Writes which time out are retried until the port did not take any bytes for `timeout`.
+fn write_patiently<W>(port: &mut W, mut data: &[u8], timeout: Duration) -> std::io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    let mut progress = Instant::now();
+    while !data.is_empty() {
+        match port.write(data) {
+            Ok(0) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "The serial port did not take any bytes.",
+                ))
+            }
+            Ok(count) => {
+                data = &data[count..];
+                progress = Instant::now();
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    std::io::ErrorKind::TimedOut
+                        | std::io::ErrorKind::WouldBlock
+                        | std::io::ErrorKind::Interrupted
+                ) && progress.elapsed() < timeout => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, time::Duration};
+
+    use super::{write_patiently, SerialUpdater};
+    use crate::{
+        config::{Channel, LinkKind},
+        updater::Value,
+    };
+
+    /// A port which takes at most two bytes per write and times out every other write, like a slow UART.
+    #[derive(Default)]
+    struct SlowPort {
+        written: Vec<u8>,
+        writes: usize,
+    }
+
+    impl Write for SlowPort {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.writes += 1;
+            if self.writes % 2 == 0 {
+                return Err(std::io::ErrorKind::TimedOut.into());
+            }
+            let count = data.len().min(2);
+            self.written.extend_from_slice(&data[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A port which fails every write with the given error.
+    struct FailingPort(std::io::ErrorKind);
+
+    impl Write for FailingPort {
+        fn write(&mut self, _data: &[u8]) -> std::io::Result<usize> {
+            Err(self.0.into())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serial_links_are_parsed() {
+        let link = |shorthand: &str| {
+            format!("rtt:0:string@serial:{}", shorthand)
+                .parse::<Channel>()
+                .map(|channel| channel.link().clone())
+        };
+
+        assert_eq!(
+            link("COM3:9600"),
+            Ok(LinkKind::Serial {
+                path: "COM3".into(),
+                baud: 9600,
+            })
+        );
+        // The baud rate is split off at the end, so paths can contain colons.
+        assert_eq!(
+            link("/dev/serial/by-path/pci-0:1.0:115200"),
+            Ok(LinkKind::Serial {
+                path: "/dev/serial/by-path/pci-0:1.0".into(),
+                baud: 115200,
+            })
+        );
+        assert!(link("/dev/ttyUSB0").is_err());
+        assert!(link("/dev/ttyUSB0:fast").is_err());
+    }
+
+    #[test]
+    fn timed_out_writes_are_retried() {
+        let mut port = SlowPort::default();
+
+        assert!(SerialUpdater::write_to_port(
+            &mut port,
+            "COM3",
+            &Value::<()>::String("hello world\n".into())
+        ));
+        assert_eq!(port.written, b"hello world\n");
+    }
+
+    #[test]
+    fn writes_give_up_once_the_port_stalls() {
+        let mut port = FailingPort(std::io::ErrorKind::TimedOut);
+
+        let error = write_patiently(&mut port, b"hello", Duration::from_millis(10)).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::TimedOut);
+        // The update is lost, but the port is kept for the next one.
+        assert!(SerialUpdater::write_to_port(
+            &mut FailingPort(std::io::ErrorKind::Other),
+            "COM3",
+            &Value::<()>::Bytes(vec![1, 2, 3])
+        ));
+    }
+
+    #[test]
+    fn disconnected_ports_are_dropped() {
+        let mut port = FailingPort(std::io::ErrorKind::BrokenPipe);
+
+        assert!(!SerialUpdater::write_to_port(
+            &mut port,
+            "COM3",
+            &Value::<()>::String("hello".into())
+        ));
+    }
+}
@@ -1,47 +1,106 @@
 use std::io::Read;
-use std::{fmt::Debug, io::Write};
-use std::{process::Child, time::Duration};
+use std::{ffi::OsString, fmt::Debug, io::Write};
 use std::{
     process::Command,
-    sync::mpsc::{channel, Sender},
+    sync::mpsc::{channel, sync_channel, RecvTimeoutError, Sender},
 };
 use std::{
     process::Stdio,
     thread::{sleep, spawn, JoinHandle},
 };
+use std::{
+    process::{Child, ChildStdout},
+    time::{Duration, Instant},
+};
 
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::{Updater, UpdaterChannel, Value};
+use super::{Updater, UpdaterChannel, Value, SHUTDOWN_TIMEOUT};
 
-/// An updater which receives and sends it's updates from and to a TCP socket.
-/// It supports concurrent connections from multiple clients and handles disconnects and errors gracefully.
+/// An updater which writes its updates to the stdin of a child process and forwards what the child writes to its stdout.
+/// If the child exits, it is spawned again up to `restarts` times in a row with a growing pause in between.
 pub struct StdioUpdater {
-    command: Command,
+    program: OsString,
+    args: Vec<OsString>,
+    restarts: u32,
     thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
 }
 
+/// The pause before the first restart of a child. It doubles with every further restart.
+const RESTART_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How long a child has to run before its exit does not count as a crash in a row with the ones before.
+const HEALTHY_RUN: Duration = Duration::from_secs(10);
+
 impl StdioUpdater {
-    /// Creates a new TCP socket updater.
-    pub fn new(command: impl Into<Command>) -> Self {
+    /// Creates a new updater which runs `program` and spawns it again up to `restarts` times in a row.
+    pub fn new(program: impl Into<OsString>, restarts: u32) -> Self {
         Self {
-            command: command.into(),
+            program: program.into(),
+            args: vec![],
+            restarts,
             thread_handle: None,
         }
     }
 
-    /// Writes a message to all connected TCP sockets and removes TCP sockets that are no longer connected.
-    fn write_to_all_sockets<O>(child: &mut Child, update: &Value<O>) -> bool
+    /// Sets the arguments the child is spawned with.
+    #[cfg(test)]
+    pub fn args<A, S>(mut self, args: A) -> Self
+    where
+        A: IntoIterator<Item = S>,
+        S: Into<OsString>,
+    {
+        self.args = args.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Spawns the child and a thread which forwards everything the child writes to its stdout.
+    fn spawn_child<I>(
+        program: &OsString,
+        args: &[OsString],
+        outbound: &Sender<Value<I>>,
+    ) -> std::io::Result<Child>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+    {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        // Reading from stdout blocks, so it is done in its own thread which ends once the child closes its stdout.
+        // This unwrap is fine as stdout was just piped.
+        let mut stdout = child.stdout.take().unwrap();
+        let outbound = outbound.clone();
+        spawn(move || while Self::read_from_child(&mut stdout, &outbound) {});
+
+        Ok(child)
+    }
+
+    /// The number of restarts in a row after a child exited which ran for `ran_for`.
+    /// Children which ran long enough start over, so only crashes in a row use up the restarts.
+    fn restarts_in_a_row(restarts: u32, ran_for: Duration) -> u32 {
+        if ran_for >= HEALTHY_RUN {
+            0
+        } else {
+            restarts
+        }
+    }
+
+    /// Writes a message to the stdin of the child.
+    /// Returns `false` once the child closed its stdin.
+    fn write_to_child<O>(child: &mut Child, update: &Value<O>) -> bool
     where
         O: Serialize + Send + Sync + Debug + 'static,
     {
         let stdin = child.stdin.as_mut().unwrap();
         let update = match update {
             Value::StructuredString(update) => {
-                stdin.write(serde_json::to_string(update).unwrap().as_bytes())
+                stdin.write_all(serde_json::to_string(update).unwrap().as_bytes())
             }
-            Value::Bytes(bytes) => stdin.write(bytes),
-            Value::String(string) => stdin.write(string.as_bytes()),
+            Value::Bytes(bytes) => stdin.write_all(bytes),
+            Value::String(string) => stdin.write_all(string.as_bytes()),
         };
 
         match update {
@@ -62,14 +121,41 @@ impl StdioUpdater {
         }
     }
 
-    /// Reads all messages from all connected TCP sockets and removes TCP sockets that are no longer connected.
-    fn read_from_all_sockets<I>(child: &mut Child, sender: Sender<Value<I>>) -> bool
+    /// Closes the stdin of the child so it sees the end of its input and gives it `SHUTDOWN_TIMEOUT` to exit on its own.
+    /// Kills the child if it is still running afterwards.
+    fn shut_down(child: &mut Child, name: &str) {
+        drop(child.stdin.take());
+
+        let start = Instant::now();
+        while start.elapsed() < SHUTDOWN_TIMEOUT {
+            match child.try_wait() {
+                Ok(None) => sleep(Duration::from_millis(10)),
+                _ => return,
+            }
+        }
+
+        log::info!(
+            "The command '{}' did not exit within {:?} after its input was closed. Killing it.",
+            name,
+            SHUTDOWN_TIMEOUT
+        );
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+
+    /// Reads the next message from the stdout of the child process.
+    /// Returns `false` once stdout was closed.
+    fn read_from_child<I>(stdout: &mut ChildStdout, sender: &Sender<Value<I>>) -> bool
     where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
     {
-        let stdout = child.stdout.as_mut().unwrap();
-        let mut buffer = Vec::with_capacity(1 << 16);
+        let mut buffer = vec![0u8; 1 << 16];
         match stdout.read(&mut buffer) {
+            Ok(0) => {
+                // A read of zero bytes means the child has closed its stdout.
+                log::info!("Stdout was closed");
+                false
+            }
             Ok(count) => {
                 buffer.truncate(count);
                 match String::from_utf8(buffer.clone()) {
@@ -113,65 +199,123 @@ impl StdioUpdater {
 }
 
 impl<I, O> Updater<I, O> for StdioUpdater {
-    fn start(&mut self) -> UpdaterChannel<I, O>
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
     where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
         O: Serialize + Send + Sync + Debug + 'static,
     {
-        let (rx, inbound) = channel::<Value<O>>();
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
         let (outbound, tx) = channel::<Value<I>>();
         let (halt_tx, halt_rx) = channel::<()>();
 
-        let mut child = self
-            .command
-            .stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .spawn()
-            .expect("Command could not be executed");
+        let program = self.program.clone();
+        let args = self.args.clone();
+        let max_restarts = self.restarts;
+        let mut child = Self::spawn_child(&program, &args, &outbound)?;
 
         self.thread_handle = Some((
             spawn(move || {
+                let name = program.to_string_lossy();
+                let mut restarts = 0;
+                let mut started = Instant::now();
                 loop {
-                    // If a halt was requested, cease operations.
+                    // If a halt was requested, write what is still queued and cease operations.
                     if halt_rx.try_recv().is_ok() {
-                        child.kill().unwrap();
-                    }
-
-                    // Read at max one new message from each socket.
-                    let keep_running = Self::read_from_all_sockets(&mut child, outbound.clone());
-                    if !keep_running {
+                        for update in inbound.try_iter() {
+                            if !Self::write_to_child(&mut child, &update) {
+                                break;
+                            }
+                        }
+                        Self::shut_down(&mut child, &name);
                         return;
                     }
 
-                    // Send at max one pending message to each socket.
-                    match inbound.try_recv() {
-                        Ok(update) => {
-                            let keep_running = Self::write_to_all_sockets(&mut child, &update);
-                            if !keep_running {
+                    // Spawn the child again if it exited.
+                    match child.try_wait() {
+                        Ok(Some(status)) => {
+                            log::warn!("The command '{}' exited with {}", name, status);
+                            restarts = Self::restarts_in_a_row(restarts, started.elapsed());
+                            if max_restarts == 0 {
+                                log::error!(
+                                    "The command '{}' is not restarted as `logging.command_restarts` is 0.",
+                                    name
+                                );
+                                return;
+                            }
+                            if restarts >= max_restarts {
+                                log::error!(
+                                    "The command '{}' was restarted {} times in a row. Giving up.",
+                                    name,
+                                    restarts
+                                );
                                 return;
                             }
+
+                            let backoff = RESTART_BACKOFF * 2u32.pow(restarts.min(6));
+                            restarts += 1;
+                            log::info!(
+                                "Restarting the command '{}' in {:?} ({}/{})",
+                                name,
+                                backoff,
+                                restarts,
+                                max_restarts
+                            );
+
+                            // Wait for the backoff while still honoring halt requests.
+                            match halt_rx.recv_timeout(backoff) {
+                                Err(RecvTimeoutError::Timeout) => (),
+                                _ => return,
+                            }
+
+                            match Self::spawn_child(&program, &args, &outbound) {
+                                Ok(new_child) => {
+                                    child = new_child;
+                                    started = Instant::now();
+                                }
+                                // The old child stays in place, so the next iteration retries the spawn.
+                                Err(err) => log::error!(
+                                    "Restarting the command '{}' experienced an error: {:?}",
+                                    name,
+                                    err
+                                ),
+                            }
+                            continue;
+                        }
+                        Ok(None) => (),
+                        Err(err) => log::error!(
+                            "Checking the state of the command '{}' experienced an error: {:?}",
+                            name,
+                            err
+                        ),
+                    }
+
+                    // Send at max one pending message to the child.
+                    if let Ok(update) = inbound.try_recv() {
+                        if !Self::write_to_child(&mut child, &update) {
+                            // The child does not listen anymore, so it is killed to get it restarted.
+                            let _ = child.kill();
                         }
-                        _ => (),
                     }
 
                     // Pause the current thread to not use CPU for no reason.
-                    sleep(Duration::from_micros(100));
+                    sleep(poll_interval);
                 }
             }),
             halt_tx,
         ));
 
-        UpdaterChannel::new(rx, tx)
+        Ok(UpdaterChannel::new(rx, tx))
     }
 
     fn stop(&mut self) -> Result<(), ()> {
         let thread_handle = self.thread_handle.take();
         match thread_handle.map(|h| {
-            // If we have a running thread, send the request to stop it and then wait for a join.
-            // If this unwrap fails the thread has already been destroyed.
-            // This cannot be assumed under normal operation conditions. Even with normal fault handling this should never happen.
-            // So this unwarp is fine.
-            h.1.send(()).unwrap();
+            // If the thread already ended because the child could not be restarted, nobody receives the request anymore.
+            let _ = h.1.send(());
             h.0.join()
         }) {
             Some(Err(err)) => {
@@ -182,3 +326,98 @@ impl<I, O> Updater<I, O> for StdioUpdater {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{thread::sleep, time::Duration};
+
+    use super::StdioUpdater;
+    use crate::updater::{Updater, UpdaterChannel, Value};
+
+    #[test]
+    fn read_json_from_child() {
+        let mut updater = StdioUpdater::new("cat", 0);
+        let mut channel: UpdaterChannel<serde_json::Value, ()> =
+            updater.start(16, Duration::from_micros(100)).unwrap();
+
+        channel
+            .tx()
+            .send(Value::String("{\"answer\": 42}\n".into()))
+            .unwrap();
+
+        // The child might not have echoed the data yet, so poll for a while.
+        for _ in 0..100 {
+            if let Ok(value) = channel.rx().try_recv() {
+                match value {
+                    Value::StructuredString(json) => {
+                        assert_eq!(json["answer"], 42);
+                        Updater::<serde_json::Value, ()>::stop(&mut updater).unwrap();
+                        return;
+                    }
+                    _ => panic!("The received data was not parsed as JSON."),
+                }
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        panic!("No data was received from the child.");
+    }
+
+    #[test]
+    fn restart_exited_child() {
+        let mut updater = StdioUpdater::new("sh", 2).args(vec!["-c", "echo '{\"answer\": 42}'"]);
+        let mut channel: UpdaterChannel<serde_json::Value, ()> =
+            updater.start(16, Duration::from_micros(100)).unwrap();
+
+        // Every run of the child prints one line, so the restarts show up as further lines.
+        let mut received = 0;
+        for _ in 0..200 {
+            if let Ok(Value::StructuredString(json)) = channel.rx().try_recv() {
+                assert_eq!(json["answer"], 42);
+                received += 1;
+                if received == 3 {
+                    Updater::<serde_json::Value, ()>::stop(&mut updater).unwrap();
+                    return;
+                }
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        panic!("Only {} of 3 runs of the child were received.", received);
+    }
+
+    #[test]
+    fn restarts_start_over_after_a_healthy_run() {
+        assert_eq!(
+            StdioUpdater::restarts_in_a_row(2, Duration::from_millis(50)),
+            2
+        );
+        assert_eq!(StdioUpdater::restarts_in_a_row(2, super::HEALTHY_RUN), 0);
+    }
+
+    #[test]
+    fn queued_updates_are_written_on_stop() {
+        let path =
+            std::env::temp_dir().join(format!("rover-stdio-stop-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        // The child only finishes writing the file once it sees the end of its input.
+        let mut updater = StdioUpdater::new("sh", 0).args(vec![
+            "-c".to_string(),
+            format!("cat > '{}'", path.display()),
+        ]);
+        let mut channel: UpdaterChannel<serde_json::Value, ()> =
+            updater.start(16, Duration::from_micros(100)).unwrap();
+        for i in 0..10 {
+            channel
+                .tx()
+                .send(Value::String(format!("{}\n", i)))
+                .unwrap();
+        }
+        Updater::<serde_json::Value, ()>::stop(&mut updater).unwrap();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(content, "0\n1\n2\n3\n4\n5\n6\n7\n8\n9\n");
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::mpsc::{channel, sync_channel, RecvTimeoutError, Sender};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use std::{fmt::Debug, io::Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// An updater which prints all its updates to the stdout of rover.
+/// Nothing is ever read back, so the channel to the user never yields any updates.
+#[derive(Default)]
+pub struct StdoutUpdater {
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl StdoutUpdater {
+    /// Creates a new stdout updater.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes an update to the writer.
+    /// Bytes and strings are written as they are while structured updates are written as one line of JSON each.
+    fn write_update<O>(writer: &mut impl Write, update: &Value<O>) -> std::io::Result<()>
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        match update {
+            Value::StructuredString(update) => {
+                writeln!(writer, "{}", serde_json::to_string(update).unwrap())?
+            }
+            Value::Bytes(bytes) => writer.write_all(bytes)?,
+            Value::String(string) => writer.write_all(string.as_bytes())?,
+        }
+        writer.flush()
+    }
+
+    fn write_or_log<O>(update: &Value<O>)
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        if let Err(err) = Self::write_update(&mut std::io::stdout().lock(), update) {
+            log::error!("Writing to stdout experienced an error: {:?}", err)
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for StdoutUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        // The updates are awaited instead of polled, so there is nothing to pause.
+        _poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        // There is no read side, so the sending end is dropped right away.
+        let (_, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        self.thread_handle = Some((
+            spawn(move || loop {
+                // If a halt was requested, print what is still queued and cease operations.
+                if halt_rx.try_recv().is_ok() {
+                    for update in inbound.try_iter() {
+                        Self::write_or_log(&update);
+                    }
+                    return;
+                }
+
+                match inbound.recv_timeout(Duration::from_millis(100)) {
+                    Ok(update) => Self::write_or_log(&update),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        match thread_handle.map(|h| {
+            // If the thread already ended because the channel was closed, nobody receives the request anymore.
+            let _ = h.1.send(());
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StdoutUpdater;
+    use crate::updater::Value;
+
+    #[test]
+    fn updates_are_written_as_they_are() {
+        let mut output = vec![];
+
+        StdoutUpdater::write_update::<serde_json::Value>(
+            &mut output,
+            &Value::String("line\n".into()),
+        )
+        .unwrap();
+        StdoutUpdater::write_update::<serde_json::Value>(&mut output, &Value::Bytes(vec![1, 2]))
+            .unwrap();
+        StdoutUpdater::write_update(
+            &mut output,
+            &Value::StructuredString(serde_json::json!({ "answer": 42 })),
+        )
+        .unwrap();
+
+        assert_eq!(output, b"line\n\x01\x02{\"answer\":42}\n");
+    }
+}
@@ -1,6 +1,6 @@
-use std::sync::mpsc::{channel, Sender};
+use std::sync::mpsc::{channel, sync_channel, Sender};
 use std::thread::{sleep, spawn, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt::Debug, io::Write};
 use std::{
     io::Read,
@@ -9,24 +9,105 @@ use std::{
 
 use serde::{de::DeserializeOwned, Serialize};
 
-use super::{Updater, UpdaterChannel, Value};
+use super::{token_matches, Updater, UpdaterChannel, Value, AUTH_TIMEOUT};
 
 /// An updater which receives and sends it's updates from and to a TCP socket.
 /// It supports concurrent connections from multiple clients and handles disconnects and errors gracefully.
 pub struct TcpUpdater {
     connection_string: String,
+    /// The line a client has to send first before it gets any updates.
+    token: Option<String>,
     thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
 }
 
+/// A client which connected but has not sent the token yet.
+struct PendingClient {
+    stream: TcpStream,
+    addr: SocketAddr,
+    received: Vec<u8>,
+    connected: Instant,
+}
+
+/// What becomes of a client which has not sent the token yet.
+enum Authentication {
+    Pending,
+    Accepted,
+    Rejected,
+}
+
 impl TcpUpdater {
     /// Creates a new TCP socket updater.
-    pub fn new(connection_string: impl Into<String>) -> Self {
+    /// If a `token` is given, clients only get updates once they sent it as their first line.
+    pub fn new(connection_string: impl Into<String>, token: Option<String>) -> Self {
         Self {
             connection_string: connection_string.into(),
+            token,
             thread_handle: None,
         }
     }
 
+    /// Reads the first line of all clients which have not sent the token yet.
+    /// Clients which sent the token are moved to the connected sockets. All other clients are dropped once
+    /// they sent a different line or did not send one within `AUTH_TIMEOUT`.
+    /// Anything a client sends along with the token is discarded.
+    fn authenticate_pending(
+        pending: &mut Vec<PendingClient>,
+        sockets: &mut Vec<(TcpStream, SocketAddr)>,
+        token: &str,
+    ) {
+        for mut client in std::mem::take(pending) {
+            match Self::authenticate(&mut client, token) {
+                Authentication::Pending => pending.push(client),
+                Authentication::Accepted => {
+                    log::info!("Accepted a new TCP socket connection from {}", client.addr);
+                    sockets.push((client.stream, client.addr));
+                }
+                Authentication::Rejected => (),
+            }
+        }
+    }
+
+    fn authenticate(client: &mut PendingClient, token: &str) -> Authentication {
+        let mut buffer = [0u8; 256];
+        match client.stream.read(&mut buffer) {
+            Ok(0) => {
+                log::info!(
+                    "Socket connection to {} was closed before it sent the token",
+                    client.addr
+                );
+                return Authentication::Rejected;
+            }
+            Ok(count) => client.received.extend_from_slice(&buffer[..count]),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => (),
+            Err(err) => {
+                log::info!("Socket connection to {} was closed: {:?}", client.addr, err);
+                return Authentication::Rejected;
+            }
+        }
+
+        match client.received.iter().position(|byte| *byte == b'\n') {
+            Some(end) if token_matches(&client.received[..=end], token) => Authentication::Accepted,
+            Some(_) => Self::reject(client, "it sent a wrong token"),
+            // A line longer than the token with its line break cannot match it anymore.
+            None if client.received.len() > token.len() + 2 => {
+                Self::reject(client, "it sent a wrong token")
+            }
+            None if client.connected.elapsed() > AUTH_TIMEOUT => {
+                Self::reject(client, "it did not send the token in time")
+            }
+            None => Authentication::Pending,
+        }
+    }
+
+    fn reject(client: &PendingClient, reason: &str) -> Authentication {
+        log::warn!(
+            "Rejected the TCP socket connection from {} as {}",
+            client.addr,
+            reason
+        );
+        Authentication::Rejected
+    }
+
     /// Writes a message to all connected TCP sockets and removes TCP sockets that are no longer connected.
     fn write_to_all_sockets<O>(sockets: &mut Vec<(TcpStream, SocketAddr)>, update: &Value<O>)
     where
@@ -83,8 +164,13 @@ impl TcpUpdater {
     {
         let mut to_remove = vec![];
         for (i, (socket, addr)) in sockets.iter_mut().enumerate() {
-            let mut buffer = Vec::with_capacity(1 << 16);
+            let mut buffer = vec![0u8; 1 << 16];
             match socket.read(&mut buffer) {
+                Ok(0) => {
+                    // A read of zero bytes means the peer has closed the connection.
+                    log::info!("Socket connection to {} was closed", addr);
+                    to_remove.push(i);
+                }
                 Ok(count) => {
                     buffer.truncate(count);
                     match String::from_utf8(buffer.clone()) {
@@ -115,15 +201,10 @@ impl TcpUpdater {
                         log::info!("Socket connection to {} was closed", addr);
                         to_remove.push(i);
                     }
-                    std::io::ErrorKind::WouldBlock => {
-                        log::error!(
-                            "Writing to TCP socket at {} experienced an error: {:?}",
-                            addr,
-                            err
-                        )
-                    }
+                    // There is no new data available.
+                    std::io::ErrorKind::WouldBlock => (),
                     _ => log::error!(
-                        "Writing to TCP socket at {} experienced an error: {:?}",
+                        "Reading from TCP socket at {} experienced an error: {:?}",
                         addr,
                         err
                     ),
@@ -139,27 +220,36 @@ impl TcpUpdater {
 }
 
 impl<I, O> Updater<I, O> for TcpUpdater {
-    fn start(&mut self) -> UpdaterChannel<I, O>
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
     where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
         O: Serialize + Send + Sync + Debug + 'static,
     {
         let mut sockets = Vec::new();
+        let mut pending = Vec::new();
+        let token = self.token.clone();
 
-        let (rx, inbound) = channel::<Value<O>>();
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
         let (outbound, tx) = channel::<Value<I>>();
         let (halt_tx, halt_rx) = channel::<()>();
 
         log::info!("Opening TCP socket on '{}'", self.connection_string);
-        let server = TcpListener::bind(&self.connection_string).unwrap();
-        server.set_nonblocking(true).unwrap();
+        let server = TcpListener::bind(&self.connection_string)?;
+        server.set_nonblocking(true)?;
 
         self.thread_handle = Some((
             spawn(move || {
                 let mut incoming = server.incoming();
                 loop {
-                    // If a halt was requested, cease operations.
+                    // If a halt was requested, write what is still queued and cease operations.
                     if halt_rx.try_recv().is_ok() {
+                        for update in inbound.try_iter() {
+                            Self::write_to_all_sockets(&mut sockets, &update);
+                        }
                         return ();
                     }
 
@@ -171,8 +261,17 @@ impl<I, O> Updater<I, O> for TcpUpdater {
                             // Try accepting the TCP socket.
                             stream.set_nonblocking(true).unwrap();
 
-                            log::info!("Accepted a new TCP socket connection from {}", addr);
-                            sockets.push((stream, addr));
+                            if token.is_some() {
+                                pending.push(PendingClient {
+                                    stream,
+                                    addr,
+                                    received: vec![],
+                                    connected: Instant::now(),
+                                });
+                            } else {
+                                log::info!("Accepted a new TCP socket connection from {}", addr);
+                                sockets.push((stream, addr));
+                            }
                         }
                         Some(Err(err)) => {
                             if err.kind() == std::io::ErrorKind::WouldBlock {
@@ -189,6 +288,11 @@ impl<I, O> Updater<I, O> for TcpUpdater {
                         }
                     }
 
+                    // Clients only get updates once they sent the token.
+                    if let Some(token) = &token {
+                        Self::authenticate_pending(&mut pending, &mut sockets, token);
+                    }
+
                     // Read at max one new message from each socket.
                     Self::read_from_all_sockets(&mut sockets, outbound.clone());
 
@@ -201,13 +305,13 @@ impl<I, O> Updater<I, O> for TcpUpdater {
                     }
 
                     // Pause the current thread to not use CPU for no reason.
-                    sleep(Duration::from_micros(100));
+                    sleep(poll_interval);
                 }
             }),
             halt_tx,
         ));
 
-        UpdaterChannel::new(rx, tx)
+        Ok(UpdaterChannel::new(rx, tx))
     }
 
     fn stop(&mut self) -> Result<(), ()> {
@@ -228,3 +332,81 @@ impl<I, O> Updater<I, O> for TcpUpdater {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        net::{TcpListener, TcpStream},
+        sync::mpsc::channel,
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    use super::{PendingClient, TcpUpdater};
+    use crate::updater::Value;
+
+    #[test]
+    fn read_json_from_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut sockets = vec![(stream, addr)];
+
+        client.write_all(b"{\"answer\": 42}\n").unwrap();
+
+        let (sender, receiver) = channel::<Value<serde_json::Value>>();
+        // The data might not have arrived yet, so poll for a while.
+        for _ in 0..100 {
+            TcpUpdater::read_from_all_sockets(&mut sockets, sender.clone());
+            if let Ok(value) = receiver.try_recv() {
+                match value {
+                    Value::StructuredString(json) => {
+                        assert_eq!(json["answer"], 42);
+                        return;
+                    }
+                    _ => panic!("The received data was not parsed as JSON."),
+                }
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        panic!("No data was received from the socket.");
+    }
+
+    #[test]
+    fn only_clients_with_the_token_are_accepted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut pending = vec![];
+        let mut clients = vec![];
+        for line in &["secret\r\n", "wrong\n"] {
+            let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+            client.write_all(line.as_bytes()).unwrap();
+            clients.push(client);
+
+            let (stream, addr) = listener.accept().unwrap();
+            stream.set_nonblocking(true).unwrap();
+            pending.push(PendingClient {
+                stream,
+                addr,
+                received: vec![],
+                connected: Instant::now(),
+            });
+        }
+
+        let mut sockets = vec![];
+        // The data might not have arrived yet, so poll for a while.
+        for _ in 0..100 {
+            TcpUpdater::authenticate_pending(&mut pending, &mut sockets, "secret");
+            if pending.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        assert!(pending.is_empty());
+        assert_eq!(sockets.len(), 1);
+        assert_eq!(sockets[0].1.port(), clients[0].local_addr().unwrap().port());
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io::{stdout, Write};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::{channel, sync_channel, RecvTimeoutError, Sender},
+    Arc, Mutex, Once,
+};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    execute, queue,
+    style::{Attribute, Print, SetAttribute},
+    terminal::{self, EnterAlternateScreen, LeaveAlternateScreen},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// How many lines each pane keeps to scroll back to.
+const SCROLLBACK: usize = 10_000;
+
+/// How long the screen waits between two redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The help shown in the last row of the screen.
+const HELP: &str = "q: quit  tab: next pane  up/down/pgup/pgdn: scroll  end: follow";
+
+/// Whether the terminal currently is in raw mode and shows the alternate screen.
+/// The panic hook only restores the terminal if this is set.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Makes sure the panic hook is only installed once.
+static PANIC_HOOK: Once = Once::new();
+
+/// A pane of the screen which shows the output of a single terminal link.
+struct Pane {
+    title: String,
+    lines: VecDeque<String>,
+    /// The last line as long as it was not terminated by a newline.
+    partial: String,
+    /// How many lines the pane is scrolled up from the bottom.
+    scroll: usize,
+}
+
+impl Pane {
+    fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            lines: VecDeque::new(),
+            partial: String::new(),
+            scroll: 0,
+        }
+    }
+
+    /// Appends text to the pane and prefixes all completed lines with `timestamp`.
+    fn push(&mut self, text: &str, timestamp: f64) {
+        for c in text.chars() {
+            match c {
+                '\n' => {
+                    let line = std::mem::take(&mut self.partial);
+                    self.lines
+                        .push_back(format!("[{:>12.6}] {}", timestamp, line));
+                    if self.lines.len() > SCROLLBACK {
+                        self.lines.pop_front();
+                    }
+                    // Keep showing the same lines while scrolled up.
+                    if self.scroll > 0 {
+                        self.scroll = (self.scroll + 1).min(self.lines.len());
+                    }
+                }
+                '\r' => (),
+                // Escape sequences are kept so colored lines show up in color.
+                '\x1b' => self.partial.push(c),
+                // Control characters would mess up the layout.
+                c if c.is_control() => self.partial.push(' '),
+                c => self.partial.push(c),
+            }
+        }
+    }
+
+    /// Scrolls the pane up by `lines`. Negative values scroll down.
+    fn scroll_by(&mut self, lines: isize) {
+        self.scroll = if lines < 0 {
+            self.scroll.saturating_sub(lines.wrapping_abs() as usize)
+        } else {
+            (self.scroll + lines as usize).min(self.lines.len())
+        };
+    }
+
+    /// Returns the at most `height` lines which are currently visible.
+    fn visible(&self, height: usize) -> Vec<&str> {
+        let mut lines = self.lines.iter().map(String::as_str).collect::<Vec<_>>();
+        if !self.partial.is_empty() {
+            lines.push(&self.partial);
+        }
+        let end = lines.len() - self.scroll.min(lines.len());
+        let start = end.saturating_sub(height);
+        lines[start..end].to_vec()
+    }
+}
+
+/// The state of the screen which is shared by all terminal links.
+struct ScreenState {
+    panes: Vec<Pane>,
+    focus: usize,
+    /// How many terminal links currently use the screen.
+    users: usize,
+    render: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+/// The full-screen terminal all terminal links render to. Each link gets its own pane.
+///
+/// The terminal is switched to the alternate screen while at least one link is running
+/// and restored once the last link stops or the process panics.
+#[derive(Clone)]
+pub struct Screen {
+    state: Arc<Mutex<ScreenState>>,
+    start: Instant,
+    halt: Arc<AtomicBool>,
+}
+
+impl Screen {
+    /// Creates a new screen. Pressing `q` or `Ctrl+C` on it sets `halt`.
+    pub fn new(halt: Arc<AtomicBool>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ScreenState {
+                panes: vec![],
+                focus: 0,
+                users: 0,
+                render: None,
+            })),
+            start: Instant::now(),
+            halt,
+        }
+    }
+
+    /// Adds a pane to the screen and takes over the terminal if this is the first user.
+    /// Returns the index of the new pane.
+    fn acquire(&self, title: &str) -> std::io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        if state.users == 0 {
+            enter_terminal()?;
+
+            let (halt_tx, halt_rx) = channel::<()>();
+            let screen = self.clone();
+            state.render = Some((
+                spawn(move || loop {
+                    // If a halt was requested, cease operations.
+                    if halt_rx.try_recv().is_ok() {
+                        return;
+                    }
+
+                    screen.handle_input();
+                    if let Err(err) = screen.draw() {
+                        log::error!("Drawing the terminal experienced an error: {:?}", err);
+                    }
+
+                    sleep(REDRAW_INTERVAL);
+                }),
+                halt_tx,
+            ));
+        }
+        state.users += 1;
+        state.panes.push(Pane::new(title));
+        Ok(state.panes.len() - 1)
+    }
+
+    /// Gives the terminal back once the last user is done with it.
+    fn release(&self) -> Result<(), ()> {
+        let render = {
+            let mut state = self.state.lock().unwrap();
+            state.users = state.users.saturating_sub(1);
+            if state.users > 0 {
+                return Ok(());
+            }
+            state.render.take()
+        };
+
+        // The lock is released beforehand as the render thread needs it to finish its iteration.
+        let result = match render.map(|h| {
+            let _ = h.1.send(());
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        };
+        leave_terminal();
+        result
+    }
+
+    /// Appends text to a pane.
+    fn push(&self, pane: usize, text: &str) {
+        let timestamp = self.start.elapsed().as_secs_f64();
+        if let Some(pane) = self.state.lock().unwrap().panes.get_mut(pane) {
+            pane.push(text, timestamp);
+        }
+    }
+
+    /// Handles all pending key presses.
+    fn handle_input(&self) {
+        while let Ok(true) = event::poll(Duration::from_millis(0)) {
+            let key = match event::read() {
+                Ok(Event::Key(key)) => key,
+                _ => continue,
+            };
+
+            let mut state = self.state.lock().unwrap();
+            let focus = state.focus;
+            match key {
+                KeyEvent {
+                    code: KeyCode::Char('q'),
+                    ..
+                } => self.halt.store(true, Ordering::Relaxed),
+                // The terminal is in raw mode, so Ctrl+C does not raise a signal.
+                KeyEvent {
+                    code: KeyCode::Char('c'),
+                    modifiers,
+                } if modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.halt.store(true, Ordering::Relaxed)
+                }
+                KeyEvent {
+                    code: KeyCode::Tab, ..
+                } => state.focus = (focus + 1) % state.panes.len().max(1),
+                KeyEvent { code, .. } => {
+                    if let Some(pane) = state.panes.get_mut(focus) {
+                        match code {
+                            KeyCode::Up => pane.scroll_by(1),
+                            KeyCode::Down => pane.scroll_by(-1),
+                            KeyCode::PageUp => pane.scroll_by(10),
+                            KeyCode::PageDown => pane.scroll_by(-10),
+                            KeyCode::End => pane.scroll = 0,
+                            _ => (),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Draws all panes stacked on top of each other followed by the help.
+    fn draw(&self) -> crossterm::Result<()> {
+        let (columns, rows) = terminal::size()?;
+        let (columns, rows) = (columns as usize, rows as usize);
+        let state = self.state.lock().unwrap();
+
+        // Each row is drawn in full, so nothing has to be cleared and the screen does not flicker.
+        let mut content: Vec<(String, bool)> = vec![];
+        let available = rows.saturating_sub(1);
+        let count = state.panes.len().max(1);
+        for (i, pane) in state.panes.iter().enumerate() {
+            let mut height = available / count;
+            if i == count - 1 {
+                height += available % count;
+            }
+            if height == 0 {
+                continue;
+            }
+
+            let mut title = format!(" {}", pane.title);
+            if i == state.focus {
+                title.push_str(" *");
+            }
+            if pane.scroll > 0 {
+                title.push_str(&format!(" (scrolled up {} lines)", pane.scroll));
+            }
+            content.push((title, true));
+
+            let lines = pane.visible(height - 1);
+            let padding = height - 1 - lines.len();
+            content.extend(lines.into_iter().map(|line| (line.to_string(), false)));
+            content.extend((0..padding).map(|_| (String::new(), false)));
+        }
+        content.push((HELP.to_string(), true));
+
+        let mut stdout = stdout();
+        for (row, (text, inverted)) in content.into_iter().take(rows).enumerate() {
+            queue!(stdout, MoveTo(0, row as u16))?;
+            if inverted {
+                queue!(stdout, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                stdout,
+                Print(fit(&text, columns)),
+                SetAttribute(Attribute::Reset)
+            )?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+/// Cuts or pads `text` to exactly `width` visible characters.
+/// Escape sequences are kept but do not count towards the width.
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted = String::new();
+    let mut visible = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // A CSI sequence like `ESC [ 31 m` ends with its first alphabetic character.
+            fitted.push(c);
+            for c in &mut chars {
+                fitted.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else if visible < width {
+            fitted.push(c);
+            visible += 1;
+        }
+    }
+    // The colors of a line must not bleed into the padding or the next line.
+    if fitted.contains('\x1b') {
+        fitted.push_str("\x1b[0m");
+    }
+    fitted.extend(std::iter::repeat(' ').take(width - visible));
+    fitted
+}
+
+/// Switches the terminal to the alternate screen in raw mode.
+fn enter_terminal() -> std::io::Result<()> {
+    // Make sure the terminal is usable again even if rover panics while it is taken over.
+    PANIC_HOOK.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            leave_terminal();
+            previous(info);
+        }));
+    });
+
+    terminal::enable_raw_mode().map_err(to_io_error)?;
+    ACTIVE.store(true, Ordering::SeqCst);
+    execute!(stdout(), EnterAlternateScreen, Hide).map_err(to_io_error)
+}
+
+/// Restores the terminal if it is currently taken over.
+fn leave_terminal() {
+    if ACTIVE.swap(false, Ordering::SeqCst) {
+        let _ = execute!(stdout(), LeaveAlternateScreen, Show);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn to_io_error(error: crossterm::ErrorKind) -> std::io::Error {
+    match error {
+        crossterm::ErrorKind::IoError(error) => error,
+        error => std::io::Error::new(std::io::ErrorKind::Other, error.to_string()),
+    }
+}
+
+/// An updater which shows its updates in a pane of a full-screen terminal view.
+/// Nothing is ever read back, so the channel to the user never yields any updates.
+pub struct TerminalUpdater {
+    screen: Screen,
+    title: String,
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl TerminalUpdater {
+    /// Creates a new terminal updater which shows its updates in a pane called `title` on `screen`.
+    pub fn new(screen: Screen, title: impl Into<String>) -> Self {
+        Self {
+            screen,
+            title: title.into(),
+            thread_handle: None,
+        }
+    }
+
+    /// Shows an update in the pane. Structured updates are shown as one line of JSON each.
+    fn push<O>(screen: &Screen, pane: usize, update: Value<O>)
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        match update {
+            Value::String(string) => screen.push(pane, &string),
+            Value::Bytes(bytes) => screen.push(pane, &String::from_utf8_lossy(&bytes)),
+            Value::StructuredString(update) => screen.push(
+                pane,
+                &format!("{}\n", serde_json::to_string(&update).unwrap()),
+            ),
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for TerminalUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        // The updates are awaited instead of polled, so there is nothing to pause.
+        _poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        // There is no read side, so the sending end is dropped right away.
+        let (_, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        let pane = self.screen.acquire(&self.title)?;
+        let screen = self.screen.clone();
+
+        self.thread_handle = Some((
+            spawn(move || loop {
+                // If a halt was requested, show what is still queued and cease operations.
+                if halt_rx.try_recv().is_ok() {
+                    for update in inbound.try_iter() {
+                        Self::push(&screen, pane, update);
+                    }
+                    return;
+                }
+
+                match inbound.recv_timeout(Duration::from_millis(100)) {
+                    Ok(update) => Self::push(&screen, pane, update),
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        let running = thread_handle.is_some();
+        let result = match thread_handle.map(|h| {
+            // If the thread already ended because the channel was closed, nobody receives the request anymore.
+            let _ = h.1.send(());
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        };
+
+        if running {
+            self.screen.release().and(result)
+        } else {
+            result
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fit, Pane, SCROLLBACK};
+
+    #[test]
+    fn pane_splits_lines() {
+        let mut pane = Pane::new("test");
+        pane.push("first\r\nsec", 1.5);
+        pane.push("ond\n\tthird", 2.0);
+
+        assert_eq!(
+            pane.visible(10),
+            vec!["[    1.500000] first", "[    2.000000] second", " third"]
+        );
+        assert_eq!(pane.visible(1), vec![" third"]);
+    }
+
+    #[test]
+    fn pane_scrolls_and_limits_scrollback() {
+        let mut pane = Pane::new("test");
+        for i in 0..SCROLLBACK + 10 {
+            pane.push(&format!("{}\n", i), 0.0);
+        }
+        assert_eq!(pane.lines.len(), SCROLLBACK);
+
+        pane.scroll_by(2);
+        pane.push("new\n", 0.0);
+        // Scrolled up panes keep showing the same lines while new ones arrive.
+        assert_eq!(
+            pane.visible(1),
+            vec![format!("[    0.000000] {}", SCROLLBACK + 7)]
+        );
+
+        pane.scroll_by(-10);
+        assert_eq!(pane.visible(1), vec!["[    0.000000] new"]);
+    }
+
+    #[test]
+    fn fit_ignores_escape_sequences() {
+        assert_eq!(fit("abcdef", 4), "abcd");
+        assert_eq!(fit("ab", 4), "ab  ");
+        assert_eq!(
+            fit("\x1b[31mabcdef\x1b[0m", 4),
+            "\x1b[31mabcd\x1b[0m\x1b[0m"
+        );
+    }
+}
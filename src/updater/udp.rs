@@ -0,0 +1,212 @@
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::mpsc::{channel, sync_channel, RecvTimeoutError, Sender};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+use std::{convert::TryFrom, fmt::Debug};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// The most payload bytes sent in a single datagram.
+/// Together with the header and the IP and UDP headers, this stays below the usual MTU of 1500 bytes.
+const MAX_CHUNK_SIZE: usize = 1200;
+
+/// An updater which sends all its updates as datagrams to a UDP address, without knowing whether they arrive.
+/// Nothing is ever read back, so the channel to the user never yields any updates.
+///
+/// Every datagram starts with an 8 byte header of big endian numbers: the sequence number of the update (`u32`),
+/// followed by the index of the chunk (`u16`) and the number of chunks of the update (`u16`).
+/// Updates larger than `MAX_CHUNK_SIZE` are split into several chunks, which the receiver joins by their sequence number.
+pub struct UdpUpdater {
+    address: String,
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl UdpUpdater {
+    /// Creates a new UDP updater which sends to `address`.
+    pub fn new(address: impl Into<String>) -> Self {
+        Self {
+            address: address.into(),
+            thread_handle: None,
+        }
+    }
+
+    /// Sends an update as one or more datagrams.
+    /// Bytes and strings are sent as they are while structured updates are sent as JSON.
+    fn send<O>(socket: &UdpSocket, target: SocketAddr, sequence: u32, update: &Value<O>)
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let payload = match update {
+            Value::StructuredString(update) => serde_json::to_vec(update).unwrap(),
+            Value::Bytes(bytes) => bytes.clone(),
+            Value::String(string) => string.clone().into_bytes(),
+        };
+
+        match datagrams(sequence, &payload) {
+            Some(datagrams) => {
+                for datagram in datagrams {
+                    if let Err(err) = socket.send_to(&datagram, target) {
+                        log::error!(
+                            "Sending to UDP address {} experienced an error: {:?}",
+                            target,
+                            err
+                        );
+                        return;
+                    }
+                }
+            }
+            None => log::error!(
+                "An update of {} bytes is too large to be sent to UDP address {}. Dropping it.",
+                payload.len(),
+                target
+            ),
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for UdpUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        // The updates are awaited instead of polled, so there is nothing to pause.
+        _poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        // There is no read side, so the sending end is dropped right away.
+        let (_, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        let target = self.address.to_socket_addrs()?.next().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("'{}' does not resolve to any address", self.address),
+            )
+        })?;
+        log::info!("Sending UDP datagrams to {}", target);
+        // The socket is bound to any port of the same address family as the target.
+        let socket = if target.is_ipv4() {
+            UdpSocket::bind("0.0.0.0:0")?
+        } else {
+            UdpSocket::bind("[::]:0")?
+        };
+
+        self.thread_handle = Some((
+            spawn(move || {
+                let mut sequence = 0u32;
+                loop {
+                    // If a halt was requested, send what is still queued and cease operations.
+                    if halt_rx.try_recv().is_ok() {
+                        for update in inbound.try_iter() {
+                            Self::send(&socket, target, sequence, &update);
+                            sequence = sequence.wrapping_add(1);
+                        }
+                        return;
+                    }
+
+                    match inbound.recv_timeout(Duration::from_millis(100)) {
+                        Ok(update) => {
+                            Self::send(&socket, target, sequence, &update);
+                            sequence = sequence.wrapping_add(1);
+                        }
+                        Err(RecvTimeoutError::Timeout) => (),
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        match thread_handle.map(|h| {
+            // If the thread already ended because the channel was closed, nobody receives the request anymore.
+            let _ = h.1.send(());
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Splits the payload of an update into datagrams of at most `MAX_CHUNK_SIZE` payload bytes, each with its header.
+/// An empty payload is sent as a single empty chunk.
+/// Returns `None` if the payload needs more chunks than the header can count.
+fn datagrams(sequence: u32, payload: &[u8]) -> Option<Vec<Vec<u8>>> {
+    let chunks = if payload.is_empty() {
+        vec![payload]
+    } else {
+        payload.chunks(MAX_CHUNK_SIZE).collect()
+    };
+    let count = u16::try_from(chunks.len()).ok()?;
+
+    Some(
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut datagram = Vec::with_capacity(8 + chunk.len());
+                datagram.extend_from_slice(&sequence.to_be_bytes());
+                datagram.extend_from_slice(&(index as u16).to_be_bytes());
+                datagram.extend_from_slice(&count.to_be_bytes());
+                datagram.extend_from_slice(chunk);
+                datagram
+            })
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::UdpSocket, time::Duration};
+
+    use super::{datagrams, UdpUpdater, MAX_CHUNK_SIZE};
+    use crate::updater::{Updater, UpdaterChannel, Value};
+
+    #[test]
+    fn large_updates_are_chunked() {
+        let payload = vec![0xab; MAX_CHUNK_SIZE * 2 + 1];
+        let chunked = datagrams(7, &payload).unwrap();
+
+        assert_eq!(chunked.len(), 3);
+        assert_eq!(&chunked[0][..8], &[0, 0, 0, 7, 0, 0, 0, 3]);
+        assert_eq!(&chunked[2][..8], &[0, 0, 0, 7, 0, 2, 0, 3]);
+        assert_eq!(chunked[0].len(), 8 + MAX_CHUNK_SIZE);
+        assert_eq!(chunked[2].len(), 8 + 1);
+
+        assert_eq!(datagrams(0, &[]), Some(vec![vec![0, 0, 0, 0, 0, 0, 0, 1]]));
+    }
+
+    #[test]
+    fn updates_are_sent_as_datagrams() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+
+        let mut updater = UdpUpdater::new(receiver.local_addr().unwrap().to_string());
+        let mut channel: UpdaterChannel<(), serde_json::Value> =
+            updater.start(16, Duration::from_micros(100)).unwrap();
+        channel.tx().send(Value::String("first".into())).unwrap();
+        channel.tx().send(Value::String("second".into())).unwrap();
+        Updater::<(), serde_json::Value>::stop(&mut updater).unwrap();
+
+        let mut buffer = [0; 64];
+        let count = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"\0\0\0\0\0\0\0\x01first");
+        let count = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"\0\0\0\x01\0\0\0\x01second");
+    }
+}
@@ -0,0 +1,289 @@
+use std::sync::mpsc::{channel, sync_channel, Sender};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::Duration;
+use std::{fmt::Debug, io::Write};
+use std::{
+    fs,
+    io::Read,
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::PathBuf,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::{Updater, UpdaterChannel, Value};
+
+/// An updater which receives and sends it's updates from and to a Unix domain socket.
+/// It supports concurrent connections from multiple local clients and handles disconnects and errors gracefully.
+pub struct UnixSocketUpdater {
+    path: PathBuf,
+    thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
+}
+
+impl UnixSocketUpdater {
+    /// Creates a new Unix domain socket updater.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            thread_handle: None,
+        }
+    }
+
+    /// Writes a message to all connected sockets and removes sockets that are no longer connected.
+    fn write_to_all_sockets<O>(sockets: &mut Vec<(UnixStream, usize)>, update: &Value<O>)
+    where
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let mut to_remove = vec![];
+        for (i, (socket, id)) in sockets.iter_mut().enumerate() {
+            let update = match update {
+                Value::StructuredString(update) => {
+                    socket.write(serde_json::to_string(update).unwrap().as_bytes())
+                }
+                Value::Bytes(bytes) => socket.write(bytes),
+                Value::String(string) => socket.write(string.as_bytes()),
+            };
+
+            match update {
+                Ok(_) => (),
+                Err(err) => match err.kind() {
+                    std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::BrokenPipe => {
+                        log::info!("Unix socket connection #{} was closed", id);
+                        to_remove.push(i);
+                    }
+                    _ => log::error!(
+                        "Writing to Unix socket connection #{} experienced an error: {:?}",
+                        id,
+                        err
+                    ),
+                },
+            }
+        }
+
+        // Remove all closed sockets.
+        for i in to_remove.into_iter().rev() {
+            sockets.swap_remove(i);
+        }
+    }
+
+    /// Reads all messages from all connected sockets and removes sockets that are no longer connected.
+    fn read_from_all_sockets<I>(sockets: &mut Vec<(UnixStream, usize)>, sender: Sender<Value<I>>)
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+    {
+        let mut to_remove = vec![];
+        for (i, (socket, id)) in sockets.iter_mut().enumerate() {
+            let mut buffer = vec![0u8; 1 << 16];
+            match socket.read(&mut buffer) {
+                Ok(0) => {
+                    // A read of zero bytes means the peer has closed the connection.
+                    log::info!("Unix socket connection #{} was closed", id);
+                    to_remove.push(i);
+                }
+                Ok(count) => {
+                    buffer.truncate(count);
+                    match String::from_utf8(buffer.clone()) {
+                        Ok(string) => {
+                            let v: Result<I, _> = serde_json::from_str(&string);
+                            match v {
+                                Ok(update) => {
+                                    log::debug!("Parsed JSON: {:#?}", update);
+                                    let _ = sender.send(Value::StructuredString(update));
+                                }
+                                Err(error) => {
+                                    log::debug!("Failed to parse JSON: {:#?}", error);
+                                    let _ = sender.send(Value::String(string));
+                                }
+                            }
+                        }
+                        Err(error) => {
+                            log::debug!("Failed to parse string: {:#?}", error);
+                            let _ = sender.send(Value::Bytes(buffer));
+                        }
+                    }
+                }
+                Err(err) => match err.kind() {
+                    std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::TimedOut
+                    | std::io::ErrorKind::BrokenPipe => {
+                        log::info!("Unix socket connection #{} was closed", id);
+                        to_remove.push(i);
+                    }
+                    // There is no new data available.
+                    std::io::ErrorKind::WouldBlock => (),
+                    _ => log::error!(
+                        "Reading from Unix socket connection #{} experienced an error: {:?}",
+                        id,
+                        err
+                    ),
+                },
+            }
+        }
+
+        // Remove all closed sockets.
+        for i in to_remove.into_iter().rev() {
+            sockets.swap_remove(i);
+        }
+    }
+}
+
+impl<I, O> Updater<I, O> for UnixSocketUpdater {
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
+    where
+        I: DeserializeOwned + Send + Sync + Debug + 'static,
+        O: Serialize + Send + Sync + Debug + 'static,
+    {
+        let mut sockets = Vec::new();
+
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
+        let (outbound, tx) = channel::<Value<I>>();
+        let (halt_tx, halt_rx) = channel::<()>();
+
+        // A socket left over from a previous run would make binding fail.
+        // Only sockets are removed so a wrongly configured path cannot delete a regular file.
+        if let Ok(metadata) = fs::metadata(&self.path) {
+            if metadata.file_type().is_socket() {
+                fs::remove_file(&self.path)?;
+            }
+        }
+
+        log::info!("Opening Unix socket at '{}'", self.path.display());
+        let server = UnixListener::bind(&self.path)?;
+        server.set_nonblocking(true)?;
+
+        self.thread_handle = Some((
+            spawn(move || {
+                let mut incoming = server.incoming();
+                // Unix sockets of clients usually are unnamed, so the connections are numbered for the logs.
+                let mut next_id = 0;
+                loop {
+                    // If a halt was requested, write what is still queued and cease operations.
+                    if halt_rx.try_recv().is_ok() {
+                        for update in inbound.try_iter() {
+                            Self::write_to_all_sockets(&mut sockets, &update);
+                        }
+                        return;
+                    }
+
+                    // Handle new incoming connections.
+                    match incoming.next() {
+                        Some(Ok(stream)) => {
+                            // Try accepting the socket.
+                            stream.set_nonblocking(true).unwrap();
+
+                            log::info!("Accepted a new Unix socket connection #{}", next_id);
+                            sockets.push((stream, next_id));
+                            next_id += 1;
+                        }
+                        Some(Err(err)) => {
+                            if err.kind() != std::io::ErrorKind::WouldBlock {
+                                log::error!(
+                                    "Connecting to a Unix socket experienced an error: {:?}",
+                                    err
+                                )
+                            }
+                        }
+                        None => {
+                            log::error!("The Unix listener iterator was exhausted. Shutting down Unix socket listener.");
+                            return;
+                        }
+                    }
+
+                    // Read at max one new message from each socket.
+                    Self::read_from_all_sockets(&mut sockets, outbound.clone());
+
+                    // Send at max one pending message to each socket.
+                    if let Ok(update) = inbound.try_recv() {
+                        Self::write_to_all_sockets(&mut sockets, &update);
+                    }
+
+                    // Pause the current thread to not use CPU for no reason.
+                    sleep(poll_interval);
+                }
+            }),
+            halt_tx,
+        ));
+
+        Ok(UpdaterChannel::new(rx, tx))
+    }
+
+    fn stop(&mut self) -> Result<(), ()> {
+        let thread_handle = self.thread_handle.take();
+        let result = match thread_handle.map(|h| {
+            // If we have a running thread, send the request to stop it and then wait for a join.
+            // If this unwrap fails the thread has already been destroyed.
+            // This cannot be assumed under normal operation conditions. Even with normal fault handling this should never happen.
+            // So this unwarp is fine.
+            h.1.send(()).unwrap();
+            h.0.join()
+        }) {
+            Some(Err(err)) => {
+                log::error!("An error occured during thread execution: {:?}", err);
+                Err(())
+            }
+            _ => Ok(()),
+        };
+
+        // The socket file is not removed when the listener is dropped.
+        let _ = fs::remove_file(&self.path);
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::Write,
+        os::unix::net::{UnixListener, UnixStream},
+        sync::mpsc::channel,
+        thread::sleep,
+        time::Duration,
+    };
+
+    use super::UnixSocketUpdater;
+    use crate::updater::Value;
+
+    #[test]
+    fn read_json_from_socket() {
+        let path = std::env::temp_dir().join(format!("rover-unix-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let mut client = UnixStream::connect(&path).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut sockets = vec![(stream, 0)];
+        std::fs::remove_file(&path).unwrap();
+
+        client.write_all(b"{\"answer\": 42}\n").unwrap();
+
+        let (sender, receiver) = channel::<Value<serde_json::Value>>();
+        // The data might not have arrived yet, so poll for a while.
+        for _ in 0..100 {
+            UnixSocketUpdater::read_from_all_sockets(&mut sockets, sender.clone());
+            if let Ok(value) = receiver.try_recv() {
+                match value {
+                    Value::StructuredString(json) => {
+                        assert_eq!(json["answer"], 42);
+                        return;
+                    }
+                    _ => panic!("The received data was not parsed as JSON."),
+                }
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        panic!("No data was received from the socket.");
+    }
+}
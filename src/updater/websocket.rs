@@ -1,51 +1,244 @@
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use std::io;
 use std::net::{SocketAddr, TcpListener, TcpStream};
-use std::sync::mpsc::{channel, Sender};
+use std::path::Path;
+use std::sync::mpsc::{channel, sync_channel, Sender};
 use std::thread::{sleep, spawn, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use native_tls::{Identity, TlsAcceptor, TlsStream};
 use serde::{de::DeserializeOwned, Serialize};
-use tungstenite::{accept, Error, HandshakeError, Message, WebSocket};
+use tungstenite::{accept, stream::Stream, Error, HandshakeError, Message, WebSocket};
 
-use super::{Updater, UpdaterChannel, Value};
+use super::{token_matches, Updater, UpdaterChannel, Value, AUTH_TIMEOUT, SHUTDOWN_TIMEOUT};
 
 /// An updater which receives and sends it's updates from and to a websocket.
 /// It supports concurrent connections from multiple clients and handles disconnects and errors gracefully.
 pub struct WebsocketUpdater {
     connection_string: String,
+    /// Encrypts the connections of all clients if set.
+    tls: Option<TlsAcceptor>,
+    /// The message a client has to send first before it gets any updates.
+    token: Option<String>,
     thread_handle: Option<(JoinHandle<()>, Sender<()>)>,
 }
 
+/// The connection of a client, which is encrypted if the updater uses TLS.
+type ClientStream = Stream<TcpStream, TlsStream<TcpStream>>;
+
+/// How long a client may take to send its part of the TLS handshake before it is dropped.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The maximum number of messages which can be pending for a single client.
+/// A client whose backlog grows beyond this is considered too slow and is dropped.
+const MAX_PENDING_MESSAGES: usize = 1024;
+
+/// A connected websocket client.
+/// Each client has its own queue of pending messages so a slow client does not hold back the others.
+struct Client {
+    socket: WebSocket<ClientStream>,
+    addr: SocketAddr,
+    pending: VecDeque<Message>,
+}
+
+impl Client {
+    fn new(socket: WebSocket<ClientStream>, addr: SocketAddr) -> Self {
+        Self {
+            socket,
+            addr,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Writes as many pending messages as the socket accepts without blocking.
+    fn flush(&mut self) -> Result<(), Error> {
+        // Make sure everything tungstenite still buffers internally is written first.
+        self.socket.write_pending()?;
+        while let Some(message) = self.pending.pop_front() {
+            // If this would block, tungstenite keeps the message in its own buffer
+            // and writes it out on the next `write_pending`.
+            self.socket.write_message(message)?;
+        }
+        Ok(())
+    }
+}
+
 impl WebsocketUpdater {
     /// Creates a new websocket updater.
-    pub fn new(connection_string: impl Into<String>) -> Self {
+    /// If a `token` is given, clients only get updates once they sent it as their first message.
+    pub fn new(connection_string: impl Into<String>, token: Option<String>) -> Self {
+        Self {
+            connection_string: connection_string.into(),
+            tls: None,
+            token,
+            thread_handle: None,
+        }
+    }
+
+    /// Creates a new websocket updater whose clients connect with TLS (`wss://`).
+    pub fn new_tls(
+        connection_string: impl Into<String>,
+        acceptor: TlsAcceptor,
+        token: Option<String>,
+    ) -> Self {
         Self {
             connection_string: connection_string.into(),
+            tls: Some(acceptor),
+            token,
             thread_handle: None,
         }
     }
 
-    /// Writes a message to all connected websockets and removes websockets that are no longer connected.
-    fn write_to_all_sockets<O>(
-        sockets: &mut Vec<(WebSocket<TcpStream>, SocketAddr)>,
-        update: Value<O>,
-    ) where
+    /// Reads the first message of all clients which have not sent the token yet.
+    /// Clients which sent the token are moved to the connected sockets. All other clients are dropped once
+    /// they sent a different message or did not send one within `AUTH_TIMEOUT`.
+    fn authenticate_pending(
+        pending: &mut Vec<(Client, Instant)>,
+        sockets: &mut Vec<Client>,
+        token: &str,
+    ) {
+        for (mut client, connected) in std::mem::take(pending) {
+            let rejection = match client.socket.read_message() {
+                Ok(Message::Text(text)) if token_matches(text.as_bytes(), token) => None,
+                Ok(Message::Binary(data)) if token_matches(&data, token) => None,
+                Ok(Message::Text(_)) | Ok(Message::Binary(_)) => Some("it sent a wrong token"),
+                Ok(_) | Err(Error::Io(_)) if connected.elapsed() > AUTH_TIMEOUT => {
+                    Some("it did not send the token in time")
+                }
+                // Pings are answered along with the first update the client gets.
+                Ok(_) => {
+                    pending.push((client, connected));
+                    continue;
+                }
+                Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                    pending.push((client, connected));
+                    continue;
+                }
+                Err(err) => {
+                    log::info!(
+                        "Socket connection to {} was closed before it sent the token: {:?}",
+                        client.addr,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            match rejection {
+                Some(reason) => log::warn!(
+                    "Rejected the websocket connection from {} as {}",
+                    client.addr,
+                    reason
+                ),
+                None => {
+                    log::info!(
+                        "Authenticated the websocket connection from {}",
+                        client.addr
+                    );
+                    sockets.push(client);
+                }
+            }
+        }
+    }
+
+    /// Accepts the websocket of a new client, after the TLS handshake if `tls` is given.
+    /// Returns `None` if either handshake failed.
+    fn accept_client(
+        stream: TcpStream,
+        addr: SocketAddr,
+        tls: Option<&TlsAcceptor>,
+    ) -> Option<Client> {
+        let stream = match tls {
+            Some(acceptor) => {
+                // The handshake takes several round trips, so it is done blocking. The timeout keeps a client
+                // which never completes it from stalling all the others.
+                if let Err(err) = stream
+                    .set_nonblocking(false)
+                    .and_then(|_| stream.set_read_timeout(Some(TLS_HANDSHAKE_TIMEOUT)))
+                {
+                    log::error!(
+                        "Preparing the TLS handshake with {} failed: {:?}",
+                        addr,
+                        err
+                    );
+                    return None;
+                }
+                match acceptor.accept(stream) {
+                    Ok(stream) => Stream::Tls(stream),
+                    Err(err) => {
+                        log::error!(
+                            "The TLS handshake with the websocket at {} failed: {:?}",
+                            addr,
+                            err
+                        );
+                        return None;
+                    }
+                }
+            }
+            None => Stream::Plain(stream),
+        };
+
+        // Try accepting the websocket.
+        match accept(stream) {
+            Ok(websocket) => {
+                // Make sure we operate in nonblocking mode.
+                // Is is required so read does not block forever.
+                tcp_stream(websocket.get_ref())
+                    .set_nonblocking(true)
+                    .unwrap();
+                log::info!("Accepted a new websocket connection from {}", addr);
+                Some(Client::new(websocket, addr))
+            }
+            Err(HandshakeError::Interrupted(_)) => None,
+            Err(HandshakeError::Failure(err)) => {
+                log::error!("Accepting a new websocket experienced an error: {:?}", err);
+                None
+            }
+        }
+    }
+
+    /// Queues a message for all connected websockets.
+    /// Websockets whose queue is full are removed.
+    fn queue_for_all_sockets<O>(sockets: &mut Vec<Client>, update: Value<O>)
+    where
         O: Serialize + Send + Sync + Debug + 'static,
     {
+        let message = match update {
+            Value::StructuredString(update) => {
+                Message::Text(serde_json::to_string(&update).unwrap())
+            }
+            Value::Bytes(bytes) => Message::Binary(bytes),
+            Value::String(string) => Message::Text(string),
+        };
+
         let mut to_remove = vec![];
-        for (i, (socket, addr)) in sockets.iter_mut().enumerate() {
-            let update = match &update {
-                Value::StructuredString(update) => {
-                    socket.write_message(Message::Text(serde_json::to_string(&update).unwrap()))
-                }
-                Value::Bytes(bytes) => socket.write_message(Message::Binary(bytes.clone())),
-                Value::String(string) => socket.write_message(Message::Text(string.clone())),
-            };
+        for (i, client) in sockets.iter_mut().enumerate() {
+            if client.pending.len() >= MAX_PENDING_MESSAGES {
+                log::warn!(
+                    "Websocket at {} cannot keep up with the data rate and was dropped",
+                    client.addr
+                );
+                to_remove.push(i);
+            } else {
+                client.pending.push_back(message.clone());
+            }
+        }
 
-            match update {
+        // Remove all websockets which are too slow.
+        for i in to_remove.into_iter().rev() {
+            sockets.swap_remove(i);
+        }
+    }
+
+    /// Writes pending messages to all connected websockets and removes websockets that are no longer connected.
+    fn write_to_all_sockets(sockets: &mut Vec<Client>) {
+        let mut to_remove = vec![];
+        for (i, client) in sockets.iter_mut().enumerate() {
+            match client.flush() {
                 Ok(_) => (),
                 Err(Error::ConnectionClosed) => {
-                    log::info!("Socket connection to {} was closed", addr);
+                    log::info!("Socket connection to {} was closed", client.addr);
                     to_remove.push(i);
                 }
                 Err(tungstenite::Error::Io(err)) => {
@@ -53,14 +246,14 @@ impl WebsocketUpdater {
                     } else {
                         log::error!(
                             "Writing to websocket at {} experienced an error: {:?}",
-                            addr,
+                            client.addr,
                             err
                         )
                     }
                 }
                 Err(err) => log::error!(
                     "Writing to websocket at {} experienced an error: {:?}",
-                    addr,
+                    client.addr,
                     err
                 ),
             }
@@ -72,18 +265,33 @@ impl WebsocketUpdater {
         }
     }
 
+    /// Writes the pending messages of all connected websockets until they are written or `SHUTDOWN_TIMEOUT` passed.
+    /// Websockets are removed once everything was written to them or writing failed.
+    fn flush_all_sockets(sockets: &mut Vec<Client>) {
+        let start = Instant::now();
+        while !sockets.is_empty() && start.elapsed() < SHUTDOWN_TIMEOUT {
+            let mut i = 0;
+            while i < sockets.len() {
+                match sockets[i].flush() {
+                    Err(Error::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => i += 1,
+                    _ => {
+                        sockets.swap_remove(i);
+                    }
+                }
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
     /// Reads all messages from all connected websockets and removes websockets that are no longer connected.
-    fn read_from_all_sockets<I>(
-        sockets: &mut Vec<(WebSocket<TcpStream>, SocketAddr)>,
-        sender: Sender<Value<I>>,
-    ) where
+    fn read_from_all_sockets<I>(sockets: &mut Vec<Client>, sender: Sender<Value<I>>)
+    where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
     {
         let mut to_remove = vec![];
-        for (i, (socket, addr)) in sockets.iter_mut().enumerate() {
+        for (i, Client { socket, addr, .. }) in sockets.iter_mut().enumerate() {
             match socket.read_message() {
                 Ok(msg) => match msg {
-                    // For now we handle text messages only.
                     Message::Text(string) => {
                         let v: Result<I, _> = serde_json::from_str(&string);
                         match v {
@@ -100,6 +308,18 @@ impl WebsocketUpdater {
                     Message::Binary(binary) => {
                         let _ = sender.send(Value::Bytes(binary));
                     }
+                    // tungstenite queues the pong on its own. Write it right away instead of waiting
+                    // for the next outgoing update so idle connections are not dropped by proxies.
+                    Message::Ping(_) => match socket.write_pending() {
+                        Ok(_) | Err(tungstenite::Error::ConnectionClosed) => (),
+                        Err(tungstenite::Error::Io(err))
+                            if err.kind() == std::io::ErrorKind::WouldBlock => {}
+                        Err(err) => log::error!(
+                            "Answering a ping from websocket at {} experienced an error: {:?}",
+                            addr,
+                            err
+                        ),
+                    },
                     _ => (),
                 },
                 Err(tungstenite::Error::ConnectionClosed) => {
@@ -132,27 +352,46 @@ impl WebsocketUpdater {
 }
 
 impl<I, O> Updater<I, O> for WebsocketUpdater {
-    fn start(&mut self) -> UpdaterChannel<I, O>
+    fn start(
+        &mut self,
+        buffer: usize,
+        poll_interval: Duration,
+    ) -> std::io::Result<UpdaterChannel<I, O>>
     where
         I: DeserializeOwned + Send + Sync + Debug + 'static,
         O: Serialize + Send + Sync + Debug + 'static,
     {
         let mut sockets = Vec::new();
+        let mut pending = Vec::new();
+        let tls = self.tls.clone();
+        let token = self.token.clone();
 
-        let (rx, inbound) = channel::<Value<O>>();
+        let (rx, inbound) = sync_channel::<Value<O>>(buffer);
         let (outbound, tx) = channel::<Value<I>>();
         let (halt_tx, halt_rx) = channel::<()>();
 
-        log::info!("Opening websocket on '{}'", self.connection_string);
-        let server = TcpListener::bind(&self.connection_string).unwrap();
-        server.set_nonblocking(true).unwrap();
+        log::info!(
+            "Opening {} on '{}'",
+            if tls.is_some() {
+                "TLS websocket"
+            } else {
+                "websocket"
+            },
+            self.connection_string
+        );
+        let server = TcpListener::bind(&self.connection_string)?;
+        server.set_nonblocking(true)?;
 
         self.thread_handle = Some((
             spawn(move || {
                 let mut incoming = server.incoming();
                 loop {
-                    // If a halt was requested, cease operations.
+                    // If a halt was requested, write what is still queued and cease operations.
                     if halt_rx.try_recv().is_ok() {
+                        while let Ok(update) = inbound.try_recv() {
+                            Self::queue_for_all_sockets(&mut sockets, update);
+                        }
+                        Self::flush_all_sockets(&mut sockets);
                         return ();
                     }
 
@@ -161,20 +400,12 @@ impl<I, O> Updater<I, O> for WebsocketUpdater {
                         Some(Ok(stream)) => {
                             // Assume we always get a peer addr, so this unwrap is fine.
                             let addr = stream.peer_addr().unwrap();
-                            // Try accepting the websocket.
-                            match accept(stream) {
-                                Ok(mut websocket) => {
-                                    // Make sure we operate in nonblocking mode.
-                                    // Is is required so read does not block forever.
-                                    websocket.get_mut().set_nonblocking(true).unwrap();
-                                    log::info!("Accepted a new websocket connection from {}", addr);
-                                    sockets.push((websocket, addr));
+                            if let Some(client) = Self::accept_client(stream, addr, tls.as_ref()) {
+                                if token.is_some() {
+                                    pending.push((client, Instant::now()));
+                                } else {
+                                    sockets.push(client);
                                 }
-                                Err(HandshakeError::Interrupted(_)) => {}
-                                Err(HandshakeError::Failure(err)) => log::error!(
-                                    "Accepting a new websocket experienced an error: {:?}",
-                                    err
-                                ),
                             }
                         }
                         Some(Err(err)) => {
@@ -192,25 +423,30 @@ impl<I, O> Updater<I, O> for WebsocketUpdater {
                         }
                     }
 
+                    // Clients only get updates once they sent the token.
+                    if let Some(token) = &token {
+                        Self::authenticate_pending(&mut pending, &mut sockets, token);
+                    }
+
                     // Read at max one new message from each socket.
                     Self::read_from_all_sockets(&mut sockets, outbound.clone());
 
-                    // Send at max one pending message to each socket.
-                    match inbound.try_recv() {
-                        Ok(update) => {
-                            Self::write_to_all_sockets(&mut sockets, update);
-                        }
-                        _ => (),
+                    // Queue all new messages for each socket.
+                    while let Ok(update) = inbound.try_recv() {
+                        Self::queue_for_all_sockets(&mut sockets, update);
                     }
 
+                    // Write as many pending messages to each socket as possible without blocking.
+                    Self::write_to_all_sockets(&mut sockets);
+
                     // Pause the current thread to not use CPU for no reason.
-                    sleep(Duration::from_micros(100));
+                    sleep(poll_interval);
                 }
             }),
             halt_tx,
         ));
 
-        UpdaterChannel::new(rx, tx)
+        Ok(UpdaterChannel::new(rx, tx))
     }
 
     fn stop(&mut self) -> Result<(), ()> {
@@ -231,3 +467,148 @@ impl<I, O> Updater<I, O> for WebsocketUpdater {
         }
     }
 }
+
+/// Loads the identity a TLS websocket presents to its clients from a PEM encoded certificate chain
+/// and a PEM encoded PKCS #8 private key.
+pub fn load_tls_acceptor(cert: &Path, key: &Path) -> io::Result<TlsAcceptor> {
+    let cert = std::fs::read(cert)?;
+    let key = std::fs::read(key)?;
+    let identity = Identity::from_pkcs8(&cert, &key)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+    TlsAcceptor::new(identity).map_err(|error| io::Error::new(io::ErrorKind::Other, error))
+}
+
+/// The TCP connection underneath the connection of a client.
+fn tcp_stream(stream: &ClientStream) -> &TcpStream {
+    match stream {
+        Stream::Plain(stream) => stream,
+        Stream::Tls(stream) => stream.get_ref(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{TcpListener, TcpStream},
+        sync::mpsc::channel,
+        thread::{sleep, spawn},
+        time::{Duration, Instant},
+    };
+
+    use tungstenite::{accept, client, stream::Stream, Message};
+
+    use super::{load_tls_acceptor, tcp_stream, Client, WebsocketUpdater};
+    use crate::updater::Value;
+
+    #[test]
+    fn read_binary_from_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (pong_tx, pong_rx) = channel();
+        spawn(move || {
+            let stream = TcpStream::connect(addr).unwrap();
+            let (mut websocket, _) = client("ws://localhost/", stream).unwrap();
+            websocket
+                .write_message(Message::Binary(vec![0xde, 0xad, 0xbe, 0xef]))
+                .unwrap();
+            websocket.write_message(Message::Ping(vec![42])).unwrap();
+            loop {
+                if let Message::Pong(data) = websocket.read_message().unwrap() {
+                    pong_tx.send(data).unwrap();
+                    return;
+                }
+            }
+        });
+
+        let (stream, peer) = listener.accept().unwrap();
+        let websocket = accept(Stream::Plain(stream)).unwrap();
+        tcp_stream(websocket.get_ref())
+            .set_nonblocking(true)
+            .unwrap();
+        let mut sockets = vec![Client::new(websocket, peer)];
+
+        let (sender, receiver) = channel::<Value<serde_json::Value>>();
+        let mut bytes = None;
+        let mut pong = None;
+        // The frames might not have arrived yet, so poll for a while.
+        for _ in 0..100 {
+            WebsocketUpdater::read_from_all_sockets(&mut sockets, sender.clone());
+            if let Ok(value) = receiver.try_recv() {
+                match value {
+                    Value::Bytes(data) => bytes = Some(data),
+                    _ => panic!("The binary frame was not forwarded as bytes."),
+                }
+            }
+            if let Ok(data) = pong_rx.try_recv() {
+                pong = Some(data);
+            }
+            if bytes.is_some() && pong.is_some() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(bytes, Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        assert_eq!(pong, Some(vec![42]));
+    }
+
+    #[test]
+    fn only_clients_with_the_token_are_accepted() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut pending = vec![];
+        for token in &["wrong", "secret"] {
+            let token = token.to_string();
+            spawn(move || {
+                let stream = TcpStream::connect(addr).unwrap();
+                let (mut websocket, _) = client("ws://localhost/", stream).unwrap();
+                websocket.write_message(Message::Text(token)).unwrap();
+                // Keep the connection open until the server is done with it.
+                let _ = websocket.read_message();
+            });
+
+            let (stream, peer) = listener.accept().unwrap();
+            let websocket = accept(Stream::Plain(stream)).unwrap();
+            tcp_stream(websocket.get_ref())
+                .set_nonblocking(true)
+                .unwrap();
+            pending.push((Client::new(websocket, peer), Instant::now()));
+        }
+        let secret = pending[1].0.addr;
+
+        let mut sockets = vec![];
+        // The messages might not have arrived yet, so poll for a while.
+        for _ in 0..100 {
+            WebsocketUpdater::authenticate_pending(&mut pending, &mut sockets, "secret");
+            if pending.is_empty() {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        assert!(pending.is_empty());
+        assert_eq!(
+            sockets.iter().map(|client| client.addr).collect::<Vec<_>>(),
+            vec![secret]
+        );
+    }
+
+    #[test]
+    fn invalid_tls_identities_are_rejected() {
+        let dir = std::env::temp_dir().join(format!("rover-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cert = dir.join("cert.pem");
+        let key = dir.join("key.pem");
+
+        assert!(load_tls_acceptor(&cert, &key).is_err());
+
+        std::fs::write(&cert, "not a certificate").unwrap();
+        std::fs::write(&key, "not a key").unwrap();
+        let result = load_tls_acceptor(&cert, &key);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            result.map(|_| ()).map_err(|error| error.kind()),
+            Err(std::io::ErrorKind::InvalidData)
+        );
+    }
+}
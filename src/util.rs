@@ -1,10 +1,48 @@
+use serde::Serialize;
+
+use crate::config::ListFormat;
+
 pub const PACKAGE_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PACKAGE_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const GIT_VERSION: &str = git_version::git_version!(fallback = "crates.io");
 
 include!(concat!(env!("OUT_DIR"), "/probe_rs_version.include"));
 
-pub fn print_version() {
-    println!("{}: {} - {}", PACKAGE_NAME, PACKAGE_VERSION, GIT_VERSION);
-    println!("probe-rs: {} - {}", PROBE_RS_VERSION, PROBE_RS_COMMIT);
+/// The versions of rover and probe-rs as they are printed with `--version-format json`.
+#[derive(Debug, Serialize)]
+pub struct Version {
+    pub rover: &'static str,
+    pub rover_commit: &'static str,
+    pub probe_rs: &'static str,
+    pub probe_rs_commit: &'static str,
+}
+
+impl Version {
+    /// The versions this binary was built with.
+    pub fn current() -> Self {
+        Version {
+            rover: PACKAGE_VERSION,
+            rover_commit: GIT_VERSION,
+            probe_rs: PROBE_RS_VERSION,
+            probe_rs_commit: PROBE_RS_COMMIT,
+        }
+    }
+}
+
+pub fn print_version(format: ListFormat) {
+    let version = Version::current();
+    match format {
+        ListFormat::Text => {
+            println!(
+                "{}: {} - {}",
+                PACKAGE_NAME, version.rover, version.rover_commit
+            );
+            println!(
+                "probe-rs: {} - {}",
+                version.probe_rs, version.probe_rs_commit
+            );
+        }
+        // This unwrap is fine as the version only contains strings.
+        ListFormat::Json => println!("{}", serde_json::to_string(&version).unwrap()),
+    }
 }
@@ -0,0 +1,514 @@
+//! Verification of the flash contents after programming.
+
+use std::{fs, path::PathBuf, time::Duration};
+
+use indicatif::ProgressBar;
+use probe_rs::{
+    architecture::Architecture, config::MemoryRegion, flashing::BinOptions, CoreRegisterAddress,
+    MemoryInterface, Session, Target,
+};
+
+use crate::{
+    config::{Format, MemoryRange},
+    diagnostics::RoverError,
+    uf2,
+};
+
+/// The number of bytes read back from the target at once.
+const CHUNK_SIZE: usize = 1024;
+
+/// A Thumb routine which computes the CRC32 of `r1` bytes at the address in `r0` and returns it in `r0`.
+/// It only uses ARMv6-M instructions, so it runs on all Cortex-M cores, and ends with a breakpoint.
+///
+/// ```text
+///     ldr   r3, =0xedb88320
+///     movs  r2, #0
+///     mvns  r2, r2
+/// 1:  cmp   r1, #0
+///     beq   4f
+///     ldrb  r4, [r0]
+///     adds  r0, r0, #1
+///     eors  r2, r4
+///     movs  r5, #8
+/// 2:  lsrs  r2, r2, #1
+///     bcc   3f
+///     eors  r2, r3
+/// 3:  subs  r5, r5, #1
+///     bne   2b
+///     subs  r1, r1, #1
+///     b     1b
+/// 4:  mvns  r0, r2
+///     bkpt  #0
+/// ```
+const CRC32_STUB: [u8; 40] = [
+    0x08, 0x4b, 0x00, 0x22, 0xd2, 0x43, 0x00, 0x29, 0x0a, 0xd0, 0x04, 0x78, 0x40, 0x1c, 0x62, 0x40,
+    0x08, 0x25, 0x52, 0x08, 0x00, 0xd3, 0x5a, 0x40, 0x6d, 0x1e, 0xfa, 0xd1, 0x49, 0x1e, 0xf2, 0xe7,
+    0xd0, 0x43, 0x00, 0xbe, 0x20, 0x83, 0xb8, 0xed,
+];
+
+/// How long the target may take to compute the CRC32 of a single segment.
+const CRC32_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A contiguous block of data which is expected in the flash of the target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+/// Collects the data which should end up in the flash of the target after programming.
+/// Data which does not lie within the flash of the target is skipped as it is not programmed.
+/// ELF segments are clipped to the flash range if one is given.
+pub fn collect_segments(
+    target: &Target,
+    images: &[(PathBuf, Format)],
+    flash_range: Option<MemoryRange>,
+) -> Result<Vec<Segment>, RoverError> {
+    let mut segments = vec![];
+
+    for (path, format) in images {
+        let content = fs::read(path).map_err(|error| RoverError::FailedToOpenElf {
+            source: error,
+            path: format!("{}", path.display()),
+        })?;
+        let parsed = match format {
+            Format::Bin(BinOptions { base_address, skip }) => Some(vec![Segment {
+                address: base_address.unwrap_or(0),
+                data: content.get(*skip as usize..).unwrap_or_default().to_vec(),
+            }]),
+            Format::Hex => std::str::from_utf8(&content).ok().and_then(parse_hex),
+            Format::Elf => parse_elf(&content).map(|segments| match flash_range {
+                Some(range) => clip(segments, range),
+                None => segments,
+            }),
+            Format::Uf2(uf2_options) => {
+                uf2::parse(&content, uf2_options.family)
+                    .ok()
+                    .map(|segments| {
+                        segments
+                            .into_iter()
+                            .map(|segment| Segment {
+                                address: segment.address,
+                                data: segment.data,
+                            })
+                            .collect()
+                    })
+            }
+        };
+        segments.extend(parsed.ok_or_else(|| RoverError::FailedToParseVerifyData {
+            path: format!("{}", path.display()),
+        })?);
+    }
+
+    segments.retain(|segment| {
+        target
+            .memory_map
+            .iter()
+            .any(|memory_region| match memory_region {
+                MemoryRegion::Nvm(flash) => flash.range.contains(&segment.address),
+                _ => false,
+            })
+    });
+
+    Ok(segments)
+}
+
+/// Reads back all the given segments from the target and compares them byte by byte.
+pub fn verify(
+    session: &mut Session,
+    segments: &[Segment],
+    progress: Option<&ProgressBar>,
+) -> Result<(), RoverError> {
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+
+    if let Some(progress) = progress {
+        progress.set_length(segments.iter().map(|s| s.data.len() as u64).sum());
+        progress.enable_steady_tick(100);
+        progress.reset_elapsed();
+    }
+
+    let mut actual = [0u8; CHUNK_SIZE];
+    for segment in segments {
+        for (i, expected) in segment.data.chunks(CHUNK_SIZE).enumerate() {
+            let address = segment.address + (i * CHUNK_SIZE) as u32;
+            let actual = &mut actual[..expected.len()];
+            core.read_8(address, actual).map_err(|error| {
+                if let Some(progress) = progress {
+                    progress.abandon();
+                }
+                RoverError::VerifyReadFailed(error)
+            })?;
+
+            if let Some(offset) = expected.iter().zip(actual.iter()).position(|(e, a)| e != a) {
+                if let Some(progress) = progress {
+                    progress.abandon();
+                }
+                return Err(RoverError::VerifyFailed {
+                    address: address + offset as u32,
+                    expected: expected[offset],
+                    actual: actual[offset],
+                });
+            }
+
+            if let Some(progress) = progress {
+                progress.inc(expected.len() as u64);
+            }
+        }
+    }
+
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    Ok(())
+}
+
+/// Lets the target compute the CRC32 of each segment and compares it with the CRC32 of the expected data.
+///
+/// Only the checksums are transferred, which is a lot faster than reading back everything over a slow link.
+/// The checksum routine is loaded to the start of the RAM, so the RAM contents are not preserved.
+/// Segments whose checksums differ are read back to report the first differing byte.
+/// Targets the routine does not run on are verified byte by byte instead.
+pub fn verify_crc(
+    session: &mut Session,
+    segments: &[Segment],
+    progress: Option<&ProgressBar>,
+) -> Result<(), RoverError> {
+    let stub_address = session
+        .target()
+        .memory_map
+        .iter()
+        .find_map(|region| match region {
+            MemoryRegion::Ram(ram)
+                if ram.range.end - ram.range.start >= CRC32_STUB.len() as u32 =>
+            {
+                Some(ram.range.start)
+            }
+            _ => None,
+        });
+    let stub_address = match (session.target().architecture(), stub_address) {
+        (Architecture::Arm, Some(stub_address)) => stub_address,
+        _ => {
+            log::warn!("The CRC32 verification is not supported on this target. Verifying the full flash contents instead.");
+            return verify(session, segments, progress);
+        }
+    };
+
+    if let Some(progress) = progress {
+        progress.set_length(segments.iter().map(|s| s.data.len() as u64).sum());
+        progress.enable_steady_tick(100);
+        progress.reset_elapsed();
+    }
+
+    let mut mismatches = vec![];
+    {
+        let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+        let mut target_crc = |segment: &Segment| -> Result<u32, probe_rs::Error> {
+            core.halt(CRC32_TIMEOUT)?;
+            core.write_8(stub_address, &CRC32_STUB)?;
+            core.write_core_reg(CoreRegisterAddress(0), segment.address)?;
+            core.write_core_reg(CoreRegisterAddress(1), segment.data.len() as u32)?;
+            // Only the Thumb bit is set in the xPSR.
+            core.write_core_reg(CoreRegisterAddress(16), 0x0100_0000)?;
+            core.write_core_reg(CoreRegisterAddress(15), stub_address)?;
+            core.run()?;
+            core.wait_for_core_halted(CRC32_TIMEOUT)?;
+            core.read_core_reg(CoreRegisterAddress(0))
+        };
+
+        for segment in segments {
+            let actual = target_crc(segment).map_err(|error| {
+                if let Some(progress) = progress {
+                    progress.abandon();
+                }
+                RoverError::CrcVerifyFailed(error)
+            })?;
+            if actual != crc32(&segment.data) {
+                mismatches.push(segment.clone());
+            }
+
+            if let Some(progress) = progress {
+                progress.inc(segment.data.len() as u64);
+            }
+        }
+    }
+
+    if !mismatches.is_empty() {
+        if let Some(progress) = progress {
+            progress.abandon();
+        }
+        verify(session, &mismatches, None)?;
+        log::warn!(
+            "The CRC32 of the flash contents differs, but reading them back found no difference."
+        );
+    } else if let Some(progress) = progress {
+        progress.finish();
+    }
+
+    Ok(())
+}
+
+/// Computes the CRC32 (IEEE 802.3) of the data, which is the same as computed by [`CRC32_STUB`].
+fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ *byte as u32, |crc, _| {
+            if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            }
+        })
+    })
+}
+
+/// Extracts the loadable segments of an ELF file at their physical addresses.
+pub fn parse_elf(content: &[u8]) -> Option<Vec<Segment>> {
+    let elf = goblin::elf::Elf::parse(content).ok()?;
+
+    elf.program_headers
+        .iter()
+        .filter(|header| header.p_type == goblin::elf::program_header::PT_LOAD)
+        .filter(|header| header.p_filesz > 0)
+        .map(|header| {
+            let start = header.p_offset as usize;
+            let end = start + header.p_filesz as usize;
+            Some(Segment {
+                address: header.p_paddr as u32,
+                data: content.get(start..end)?.to_vec(),
+            })
+        })
+        .collect()
+}
+
+/// The section some toolchains embed the name of the chip the firmware is built for in.
+const CHIP_SECTION: &str = ".probe-rs";
+/// The owner of the note in the chip section, if it is a note section.
+const CHIP_NOTE_NAME: &[u8] = b"probe-rs";
+
+/// Reads the chip name from the `.probe-rs` section of an ELF file.
+/// The section either holds the name as a string or, as a note section, a `probe-rs` note with the name as its descriptor.
+pub fn chip_from_elf(content: &[u8]) -> Option<String> {
+    let elf = goblin::elf::Elf::parse(content).ok()?;
+
+    let header = elf
+        .section_headers
+        .iter()
+        .find(|header| {
+            matches!(elf.shdr_strtab.get(header.sh_name), Some(Ok(name)) if name == CHIP_SECTION)
+        })?;
+    let start = header.sh_offset as usize;
+    let data = content.get(start..start + header.sh_size as usize)?;
+
+    if header.sh_type == goblin::elf::section_header::SHT_NOTE {
+        chip_from_note(data, elf.little_endian)
+    } else {
+        chip_from_str(data)
+    }
+}
+
+/// Finds the `probe-rs` note among the notes and reads the chip name from its descriptor.
+fn chip_from_note(mut data: &[u8], little_endian: bool) -> Option<String> {
+    let word = |bytes: &[u8], index: usize| -> Option<usize> {
+        let mut word = [0; 4];
+        word.copy_from_slice(bytes.get(index * 4..index * 4 + 4)?);
+        Some(if little_endian {
+            u32::from_le_bytes(word)
+        } else {
+            u32::from_be_bytes(word)
+        } as usize)
+    };
+    // The name and the descriptor are padded to 4 bytes.
+    let padded = |size: usize| (size + 3) & !3;
+
+    while !data.is_empty() {
+        let name_size = word(data, 0)?;
+        let desc_size = word(data, 1)?;
+        let name = data.get(12..12 + name_size)?;
+        let desc_start = 12 + padded(name_size);
+        let desc = data.get(desc_start..desc_start + desc_size)?;
+
+        if name.split(|byte| *byte == 0).next() == Some(CHIP_NOTE_NAME) {
+            return chip_from_str(desc);
+        }
+        data = data.get(desc_start + padded(desc_size)..)?;
+    }
+    None
+}
+
+/// Reads a chip name which might be padded with NUL bytes.
+fn chip_from_str(data: &[u8]) -> Option<String> {
+    let name = std::str::from_utf8(data)
+        .ok()?
+        .trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Cuts the segments down to the parts within the given range.
+/// Segments which lie completely outside of the range are dropped.
+pub fn clip(segments: Vec<Segment>, range: MemoryRange) -> Vec<Segment> {
+    segments
+        .into_iter()
+        .filter_map(|segment| {
+            let segment_end = segment.address as u64 + segment.data.len() as u64;
+            let start = segment.address.max(range.start);
+            let end = segment_end.min(range.end as u64);
+            if (start as u64) < end {
+                let offset = (start - segment.address) as usize;
+                Some(Segment {
+                    address: start,
+                    data: segment.data[offset..(end - segment.address as u64) as usize].to_vec(),
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Extracts the data records of an Intel HEX file.
+/// Consecutive records are merged into a single segment.
+fn parse_hex(content: &str) -> Option<Vec<Segment>> {
+    let mut segments: Vec<Segment> = vec![];
+    let mut base = 0u32;
+
+    for line in content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let bytes = line
+            .strip_prefix(':')?
+            .as_bytes()
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+            .collect::<Option<Vec<u8>>>()?;
+
+        let length = *bytes.first()? as usize;
+        let offset = u16::from_be_bytes([*bytes.get(1)?, *bytes.get(2)?]) as u32;
+        let data = bytes.get(4..4 + length)?;
+
+        match bytes.get(3)? {
+            // Data record
+            0x00 => {
+                let address = base + offset;
+                match segments.last_mut() {
+                    Some(last) if last.address + last.data.len() as u32 == address => {
+                        last.data.extend_from_slice(data)
+                    }
+                    _ => segments.push(Segment {
+                        address,
+                        data: data.to_vec(),
+                    }),
+                }
+            }
+            // End of file record
+            0x01 => break,
+            // Extended segment address record
+            0x02 => base = (u16::from_be_bytes([*data.first()?, *data.get(1)?]) as u32) << 4,
+            // Extended linear address record
+            0x04 => base = (u16::from_be_bytes([*data.first()?, *data.get(1)?]) as u32) << 16,
+            // Start address records do not carry any data.
+            _ => {}
+        }
+    }
+
+    Some(segments)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{chip_from_note, chip_from_str, clip, crc32, parse_hex, Segment};
+    use crate::config::MemoryRange;
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(&[]), 0);
+    }
+
+    #[test]
+    fn chip_names_are_read_from_notes() {
+        let mut notes = vec![];
+        // A GNU build id note comes before the chip note.
+        notes.extend_from_slice(&[4, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+        notes.extend_from_slice(b"GNU\0\xab\xcd\0\0");
+        notes.extend_from_slice(&[9, 0, 0, 0, 14, 0, 0, 0, 1, 0, 0, 0]);
+        notes.extend_from_slice(b"probe-rs\0\0\0\0nRF52840_xxAA\0\0\0");
+
+        assert_eq!(
+            chip_from_note(&notes, true),
+            Some("nRF52840_xxAA".to_string())
+        );
+        assert_eq!(chip_from_note(&notes[..20], true), None);
+        assert_eq!(
+            chip_from_str(b"STM32F407VGTx\0\0\0"),
+            Some("STM32F407VGTx".to_string())
+        );
+        assert_eq!(chip_from_str(b"\0\0"), None);
+    }
+
+    #[test]
+    fn parse_hex_merges_consecutive_records() {
+        let hex = ":020000040800F2\n\
+                   :0400000001020304F2\n\
+                   :0400040005060708DE\n\
+                   :0400100009000000E3\n\
+                   :00000001FF\n";
+
+        assert_eq!(
+            parse_hex(hex),
+            Some(vec![
+                Segment {
+                    address: 0x0800_0000,
+                    data: vec![1, 2, 3, 4, 5, 6, 7, 8],
+                },
+                Segment {
+                    address: 0x0800_0010,
+                    data: vec![9, 0, 0, 0],
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn clip_cuts_straddling_segments() {
+        let segments = vec![
+            Segment {
+                address: 0x0,
+                data: vec![1, 2, 3, 4],
+            },
+            Segment {
+                address: 0x4,
+                data: vec![5, 6, 7, 8],
+            },
+            Segment {
+                address: 0x10,
+                data: vec![9],
+            },
+        ];
+
+        assert_eq!(
+            clip(
+                segments,
+                MemoryRange {
+                    start: 0x2,
+                    end: 0x6
+                }
+            ),
+            vec![
+                Segment {
+                    address: 0x2,
+                    data: vec![3, 4],
+                },
+                Segment {
+                    address: 0x4,
+                    data: vec![5, 6],
+                },
+            ]
+        );
+    }
+}
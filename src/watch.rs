@@ -0,0 +1,144 @@
+use std::{
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{channel, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use colored::*;
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use probe_rs::Session;
+use probe_rs_cli_util::{build_artifact, logging};
+
+use crate::{config::Config, config::Format, diagnostics::RoverError, flashing};
+
+/// How long the sources have to stay unchanged before a rebuild is started.
+/// Saving several files at once or formatting on save only triggers a single rebuild this way.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the cargo project in `work_dir` and rebuilds and reflashes the firmware whenever one of its sources changes.
+///
+/// The probe and the session stay open, so GDB and logging keep running across reflashes.
+/// A failing build or flash is reported and the watch goes on with the next change.
+/// After every successful reflash, `reattach` is set so logging attaches to the RTT control block of the new firmware.
+/// Runs until `halt` is set.
+pub fn run_watch(
+    session: Arc<Mutex<Session>>,
+    config: &Config,
+    work_dir: &Path,
+    args: &[String],
+    speed_khz: u32,
+    halt: &AtomicBool,
+    reattach: &AtomicBool,
+) -> Result<(), RoverError> {
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, DEBOUNCE).map_err(RoverError::FailedToWatch)?;
+    watcher
+        .watch(work_dir, RecursiveMode::Recursive)
+        .map_err(RoverError::FailedToWatch)?;
+    log::info!("Watching {} for changes.", work_dir.display());
+
+    // The build itself writes to the target directory, which must not trigger another build.
+    let target_dir = work_dir.join("target");
+
+    while !halt.load(Ordering::Relaxed) {
+        let changed = match rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(DebouncedEvent::Create(path))
+            | Ok(DebouncedEvent::Write(path))
+            | Ok(DebouncedEvent::Remove(path))
+            | Ok(DebouncedEvent::Rename(_, path)) => path,
+            Ok(DebouncedEvent::Error(error, _)) => {
+                log::warn!("Watching for changes experienced an error: {}", error);
+                continue;
+            }
+            Ok(_) | Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        if !is_source(&changed, &target_dir) {
+            continue;
+        }
+
+        log::info!("{} changed.", changed.display());
+        match reflash(&session, config, work_dir, args, speed_khz) {
+            Ok(()) => reattach.store(true, Ordering::Relaxed),
+            Err(error) => log::error!("Reflashing failed: {}", error),
+        }
+    }
+
+    Ok(())
+}
+
+/// Rebuilds the firmware and flashes it with the open session.
+fn reflash(
+    session: &Mutex<Session>,
+    config: &Config,
+    work_dir: &Path,
+    args: &[String],
+    speed_khz: u32,
+) -> Result<(), RoverError> {
+    let path = build_artifact(work_dir, args).map_err(RoverError::FailedToBuildCargoProject)?;
+    let images = vec![(path.clone(), Format::Elf)];
+
+    let instant = Instant::now();
+    logging::println(format!(
+        "    {} {}",
+        "Flashing".green().bold(),
+        path.display()
+    ));
+
+    // The session stays locked for the whole reflash so logging and GDB do not interfere with it.
+    let mut session = session.lock().unwrap();
+    // The flash loader is rebuilt from the new artifact.
+    flashing::run_flash_cycles(
+        &mut session,
+        &images,
+        config.flashing().regions(),
+        config,
+        None,
+        true,
+        speed_khz,
+    )?;
+    if config.reset().enabled() {
+        crate::reset_target(&mut session, config, Some(&path))?;
+    }
+
+    logging::println(format!(
+        "    {} in {}s",
+        "Finished".green().bold(),
+        instant.elapsed().as_millis() as f32 / 1000.0,
+    ));
+    Ok(())
+}
+
+/// Whether a change to the file at `path` requires a rebuild.
+fn is_source(path: &Path, target_dir: &Path) -> bool {
+    if path.starts_with(target_dir) {
+        return false;
+    }
+    path.extension()
+        .map_or(false, |extension| extension == "rs")
+        || path.file_name().map_or(false, |name| name == "Cargo.toml")
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::is_source;
+
+    #[test]
+    fn only_sources_trigger_a_rebuild() {
+        let target = Path::new("/project/target");
+        assert!(is_source(Path::new("/project/src/main.rs"), target));
+        assert!(is_source(Path::new("/project/Cargo.toml"), target));
+        assert!(!is_source(Path::new("/project/README.md"), target));
+        assert!(!is_source(Path::new("/project/src/main.rs~"), target));
+        assert!(!is_source(
+            Path::new("/project/target/thumbv7em-none-eabihf/debug/build/out.rs"),
+            target
+        ));
+    }
+}
@@ -0,0 +1,319 @@
+//! Keeps the hardware watchdogs of the target from resetting it in the middle of flashing.
+//!
+//! Most independent watchdogs cannot be stopped once they run, so they are slowed down to their longest timeout instead.
+//! Even the longest timeout of a window watchdog is far too short for flashing, so its clock is gated, which stops its counter.
+
+use probe_rs::{MemoryInterface, Session};
+
+use crate::diagnostics::RoverError;
+
+/// The registers of the independent watchdog shared by a family of chips.
+struct Watchdog {
+    /// The prefix of the names of all the chips with this watchdog.
+    family: &'static str,
+    /// The address of the key register.
+    key_register: u32,
+    /// The key which unlocks the other registers for writing.
+    unlock_key: u32,
+    /// The key which reloads the counter with the current register values.
+    reload_key: u32,
+    /// The registers and the values which give the longest timeout.
+    registers: &'static [(u32, u32)],
+}
+
+/// The independent watchdog of the STM32 families which share its location.
+/// The prescaler is set to divide by 256 and the reload value to its maximum.
+const STM32_IWDG: &[(u32, u32)] = &[(0x4000_3004, 0x7), (0x4000_3008, 0xFFF)];
+
+const WATCHDOGS: &[Watchdog] = &[
+    Watchdog {
+        family: "STM32F",
+        key_register: 0x4000_3000,
+        unlock_key: 0x5555,
+        reload_key: 0xAAAA,
+        registers: STM32_IWDG,
+    },
+    Watchdog {
+        family: "STM32G",
+        key_register: 0x4000_3000,
+        unlock_key: 0x5555,
+        reload_key: 0xAAAA,
+        registers: STM32_IWDG,
+    },
+    Watchdog {
+        family: "STM32L",
+        key_register: 0x4000_3000,
+        unlock_key: 0x5555,
+        reload_key: 0xAAAA,
+        registers: STM32_IWDG,
+    },
+    Watchdog {
+        family: "STM32W",
+        key_register: 0x4000_3000,
+        unlock_key: 0x5555,
+        reload_key: 0xAAAA,
+        registers: STM32_IWDG,
+    },
+];
+
+/// The clock enable bit of the window watchdog of a family of chips.
+struct WindowWatchdog {
+    /// The prefix of the names of all the chips with this watchdog.
+    family: &'static str,
+    /// The address of the RCC register with the clock enable bit.
+    clock_register: u32,
+    /// The mask of the clock enable bit.
+    clock_enable: u32,
+}
+
+/// The `WWDGEN` bit is bit 11 of the APB1 clock enable register on all STM32 families, but the register moves around.
+const WWDGEN: u32 = 1 << 11;
+
+const WINDOW_WATCHDOGS: &[WindowWatchdog] = &[
+    WindowWatchdog {
+        family: "STM32F0",
+        clock_register: 0x4002_101C,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32F1",
+        clock_register: 0x4002_101C,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32F2",
+        clock_register: 0x4002_3840,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32F3",
+        clock_register: 0x4002_101C,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32F4",
+        clock_register: 0x4002_3840,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32F7",
+        clock_register: 0x4002_3840,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32G0",
+        clock_register: 0x4002_103C,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32G4",
+        clock_register: 0x4002_1058,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32L0",
+        clock_register: 0x4002_1038,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32L1",
+        clock_register: 0x4002_3824,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32L4",
+        clock_register: 0x4002_1058,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32WB",
+        clock_register: 0x5800_0058,
+        clock_enable: WWDGEN,
+    },
+    WindowWatchdog {
+        family: "STM32WL",
+        clock_register: 0x5800_0058,
+        clock_enable: WWDGEN,
+    },
+];
+
+/// The register values of the watchdogs from before they were disabled.
+pub struct SavedWatchdog {
+    independent: Option<(&'static Watchdog, Vec<u32>)>,
+    /// The window watchdog and whether its clock was enabled.
+    window: Option<(&'static WindowWatchdog, bool)>,
+}
+
+/// Disables the watchdogs of the target if its family is known.
+/// Returns the previous register values so they can be restored after flashing.
+pub fn disable(session: &mut Session) -> Result<Option<SavedWatchdog>, RoverError> {
+    let name = session.target().name.to_ascii_uppercase();
+    let independent = WATCHDOGS
+        .iter()
+        .find(|watchdog| name.starts_with(watchdog.family));
+    let window = WINDOW_WATCHDOGS
+        .iter()
+        .find(|watchdog| name.starts_with(watchdog.family));
+    if independent.is_none() && window.is_none() {
+        log::warn!(
+            "Disabling the watchdog is not supported for the chip '{}'. A running watchdog might reset the chip during flashing.",
+            session.target().name
+        );
+        return Ok(None);
+    }
+
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+
+    let independent = match independent {
+        Some(watchdog) => {
+            let values = watchdog
+                .registers
+                .iter()
+                .map(|(address, _)| core.read_word_32(*address))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(RoverError::WatchdogAccessFailed)?;
+
+            log::info!("Disabling the watchdog of the {} family.", watchdog.family);
+            write_registers(
+                &mut core,
+                register_writes(watchdog, watchdog.registers.iter().copied()),
+            )
+            .map_err(RoverError::WatchdogAccessFailed)?;
+            Some((watchdog, values))
+        }
+        None => None,
+    };
+
+    let window = match window {
+        Some(watchdog) => {
+            let clock = core
+                .read_word_32(watchdog.clock_register)
+                .map_err(RoverError::WatchdogAccessFailed)?;
+
+            log::info!(
+                "Stopping the window watchdog of the {} family.",
+                watchdog.family
+            );
+            core.write_word_32(watchdog.clock_register, clock & !watchdog.clock_enable)
+                .map_err(RoverError::WatchdogAccessFailed)?;
+            Some((watchdog, clock & watchdog.clock_enable != 0))
+        }
+        None => None,
+    };
+
+    Ok(Some(SavedWatchdog {
+        independent,
+        window,
+    }))
+}
+
+/// Restores the watchdog registers to their values from before `disable`.
+pub fn restore(session: &mut Session, saved: SavedWatchdog) -> Result<(), RoverError> {
+    let mut core = session.core(0).map_err(RoverError::AttachingToCoreFailed)?;
+
+    if let Some((watchdog, values)) = saved.independent {
+        log::info!("Restoring the watchdog of the {} family.", watchdog.family);
+        let registers = watchdog
+            .registers
+            .iter()
+            .map(|(address, _)| *address)
+            .zip(values);
+        write_registers(&mut core, register_writes(watchdog, registers))
+            .map_err(RoverError::WatchdogAccessFailed)?;
+    }
+
+    // A window watchdog whose clock was off stays off. The other bits of the register might have changed meanwhile.
+    if let Some((watchdog, true)) = saved.window {
+        log::info!(
+            "Restarting the window watchdog of the {} family.",
+            watchdog.family
+        );
+        let clock = core
+            .read_word_32(watchdog.clock_register)
+            .map_err(RoverError::WatchdogAccessFailed)?;
+        core.write_word_32(watchdog.clock_register, clock | watchdog.clock_enable)
+            .map_err(RoverError::WatchdogAccessFailed)?;
+    }
+
+    Ok(())
+}
+
+/// The writes which unlock the watchdog, write the given registers and reload the counter so the values take effect.
+fn register_writes(
+    watchdog: &Watchdog,
+    registers: impl Iterator<Item = (u32, u32)>,
+) -> Vec<(u32, u32)> {
+    std::iter::once((watchdog.key_register, watchdog.unlock_key))
+        .chain(registers)
+        .chain(std::iter::once((
+            watchdog.key_register,
+            watchdog.reload_key,
+        )))
+        .collect()
+}
+
+/// Writes the values to the addresses in the given order.
+fn write_registers(
+    core: &mut impl MemoryInterface,
+    writes: Vec<(u32, u32)>,
+) -> Result<(), probe_rs::Error> {
+    for (address, value) in writes {
+        core.write_word_32(address, value)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{register_writes, WATCHDOGS, WINDOW_WATCHDOGS};
+
+    #[test]
+    fn independent_watchdog_is_unlocked_written_and_reloaded() {
+        let watchdog = WATCHDOGS
+            .iter()
+            .find(|watchdog| "STM32F401RE".starts_with(watchdog.family))
+            .unwrap();
+
+        assert_eq!(
+            register_writes(watchdog, watchdog.registers.iter().copied()),
+            vec![
+                (0x4000_3000, 0x5555),
+                (0x4000_3004, 0x7),
+                (0x4000_3008, 0xFFF),
+                (0x4000_3000, 0xAAAA),
+            ]
+        );
+
+        // Restoring writes the saved values in the same sequence.
+        let saved = vec![0x4, 0x7FF];
+        let registers = watchdog
+            .registers
+            .iter()
+            .map(|(address, _)| *address)
+            .zip(saved);
+        assert_eq!(
+            register_writes(watchdog, registers),
+            vec![
+                (0x4000_3000, 0x5555),
+                (0x4000_3004, 0x4),
+                (0x4000_3008, 0x7FF),
+                (0x4000_3000, 0xAAAA),
+            ]
+        );
+    }
+
+    #[test]
+    fn window_watchdog_clock_is_found_per_family() {
+        let clock_register = |chip: &str| {
+            WINDOW_WATCHDOGS
+                .iter()
+                .find(|watchdog| chip.starts_with(watchdog.family))
+                .map(|watchdog| (watchdog.clock_register, watchdog.clock_enable))
+        };
+
+        assert_eq!(clock_register("STM32F103C8"), Some((0x4002_101C, 1 << 11)));
+        assert_eq!(clock_register("STM32F401RE"), Some((0x4002_3840, 1 << 11)));
+        assert_eq!(clock_register("STM32L476RG"), Some((0x4002_1058, 1 << 11)));
+        assert_eq!(clock_register("NRF52840_XXAA"), None);
+    }
+}